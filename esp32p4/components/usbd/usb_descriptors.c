@@ -1,10 +1,11 @@
+#include <stdio.h>
+#include "esp_mac.h"
 #include "tusb.h"
 
 #define USBD_VID            (0x303a) // Espressif
 #define USBD_PID            (0x4020)
 #define USBD_MANUFACTURER   "Espressif Systems"
 #define USBD_PRODUCT        "Espressif Device"
-#define USBD_SERIAL         "123456"
 #define USBD_DESC_LEN       (TUD_CONFIG_DESC_LEN + CFG_TUD_VENDOR * TUD_VENDOR_DESC_LEN)
 #define USBD_DESC_STR_MAX   (32)
 #define USBD_JPEG_STR       "JPEG Stream"
@@ -83,7 +84,7 @@ static uint8_t const descriptor_config[] = {
 static const char *descriptor_string[] = {
     [STR_MANUFACTURER] = USBD_MANUFACTURER,
     [STR_PRODUCT     ] = USBD_PRODUCT,
-    [STR_SERIAL      ] = USBD_SERIAL,
+    [STR_SERIAL      ] = NULL, // Factory MAC, tells tablets of a wall apart
     [STR_VENDOR_JPEG ] = USBD_JPEG_STR,
 };
 
@@ -104,6 +105,13 @@ uint16_t const *tud_descriptor_string_cb(uint8_t index, uint16_t langid) {
         len = 1;
     } else {
         const char *str = descriptor_string[index];
+        if (index == STR_SERIAL) {
+            static char serial[13];
+            uint8_t mac[6];
+            esp_efuse_mac_get_default(mac);
+            snprintf(serial, sizeof(serial), "%02X%02X%02X%02X%02X%02X", mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]);
+            str = serial;
+        }
         for (len = 0; len < USBD_DESC_STR_MAX - 1 && str[len]; len++) {
             buf[1 + len] = str[len];
         }
@@ -32,3 +32,52 @@ bool usbd_mounted(void) { return tud_mounted(); }
 // Vendor specific class
 uint32_t usbd_vendor_available(void) { return tud_vendor_available(); }
 uint32_t usbd_vendor_read(void *buffer, uint32_t bufsize) { return tud_vendor_read(buffer, bufsize); }
+uint32_t usbd_vendor_write(const void *buffer, uint32_t bufsize) {
+    uint32_t size = tud_vendor_write(buffer, bufsize);
+    tud_vendor_write_flush();
+    return size;
+}
+
+// Vendor control requests
+#define USBD_VENDOR_REQUEST_GET_CAPS 0x01
+#define USBD_VENDOR_REQUEST_SET_MUX  0x02
+#define USBD_CAPS_VERSION            0x02
+#define USBD_CAPS_SUBSAMP_444        (1 << 0)
+#define USBD_CAPS_SUBSAMP_422        (1 << 1)
+#define USBD_CAPS_SUBSAMP_420        (1 << 2)
+#define USBD_CAPS_SUBSAMP_GRAY       (1 << 3)
+#define USBD_CAPS_FEATURE_SYNC       (1 << 0)
+#define USBD_CAPS_FEATURE_AUDIO      (1 << 1)
+#define USBD_CAPS_FEATURE_MUX        (1 << 2)
+
+// Bulk traffic goes in channel fragments, switched on by the host per session
+static volatile bool vendor_mux = false;
+
+bool usbd_vendor_mux(void) { return vendor_mux; }
+
+void tud_mount_cb(void) { vendor_mux = false; }
+
+bool tud_vendor_control_xfer_cb(uint8_t rhport, uint8_t stage, tusb_control_request_t const *request) {
+    if (stage != CONTROL_STAGE_SETUP) return true;
+    if (request->bmRequestType_bit.type != TUSB_REQ_TYPE_VENDOR) return false;
+
+    switch (request->bRequest) {
+    case USBD_VENDOR_REQUEST_GET_CAPS: {
+        // A host asking for the caps starts a new session, plain packets until it asks for the mux
+        vendor_mux = false;
+        // Chroma subsampling accepted by the ESP32-P4 JPEG decoder
+        static const uint8_t caps[] = {
+            USBD_CAPS_VERSION,
+            USBD_CAPS_SUBSAMP_444 | USBD_CAPS_SUBSAMP_422 | USBD_CAPS_SUBSAMP_420 | USBD_CAPS_SUBSAMP_GRAY,
+            // Clock sync packets and present-at frame headers, audio packets for the speaker, channel fragments
+            USBD_CAPS_FEATURE_SYNC | USBD_CAPS_FEATURE_AUDIO | USBD_CAPS_FEATURE_MUX,
+        };
+        return tud_control_xfer(rhport, request, (void *)caps, sizeof(caps));
+    }
+    case USBD_VENDOR_REQUEST_SET_MUX:
+        vendor_mux = request->wValue != 0;
+        return tud_control_status(rhport, request);
+    default:
+        return false;
+    }
+}
@@ -13,3 +13,5 @@ bool usbd_mounted(void);
 // vendor specific class
 uint32_t usbd_vendor_available(void);
 uint32_t usbd_vendor_read(void *buffer, uint32_t bufsize);
+uint32_t usbd_vendor_write(const void *buffer, uint32_t bufsize);
+bool usbd_vendor_mux(void);
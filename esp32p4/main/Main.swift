@@ -1,5 +1,91 @@
 fileprivate let Log = Logger(tag: "main")
 
+// Packets from the host start with their total size, then either the JPEG itself or one of these kinds
+fileprivate let packetFrame: UInt32 = 1
+fileprivate let packetSync: UInt32 = 2
+fileprivate let packetTouch: UInt32 = 3
+fileprivate let packetAudio: UInt32 = 4
+fileprivate let frameHeaderSize = 24
+fileprivate let audioHeaderSize = 24
+fileprivate let audioCodecADPCM: UInt8 = 1
+fileprivate let audioBufferSize = 8192
+fileprivate let audioBufferCount = 8
+// With the mux on, packets and reports go in fragments: channel, flags, UInt16 length, then the payload.
+// Fragments of different channels interleave, so a sync packet does not wait behind a whole frame.
+fileprivate let fragmentHeaderSize = 4
+fileprivate let fragmentSize = 16 * 1024
+fileprivate let fragmentFirst: UInt8 = 1 << 0
+fileprivate let fragmentLast: UInt8 = 1 << 1
+fileprivate let channelControl: UInt8 = 0
+fileprivate let channelTouch: UInt8 = 1
+fileprivate let channelAudio: UInt8 = 2
+fileprivate let channelVideo: UInt8 = 3
+fileprivate let channelCount = 5
+fileprivate let touchPointsMax = 5
+// Present times further ahead than this are treated as garbage and shown right away
+fileprivate let maxPresentDelay: UInt64 = 1000000
+// Audio this late is dropped, the speaker clock catches up with the host that way
+fileprivate let maxAudioLate: UInt64 = 60000
+
+fileprivate struct Frame {
+    let jpeg: UnsafeRawBufferPointer
+    /// Timer count to flip at, 0 to flip as soon as the frame is decoded
+    let presentAt: UInt64
+}
+
+fileprivate struct AudioChunk {
+    /// PCM or ADPCM as the host sent it
+    let data: UnsafeMutableRawBufferPointer
+    let codec: UInt8
+    let volume: UInt8
+    /// Stereo sample pairs in the chunk
+    let frames: Int
+    /// Timer count to start playing at, 0 to play right away
+    let presentAt: UInt64
+}
+
+fileprivate let adpcmIndexTable: [Int] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8]
+fileprivate let adpcmStepTable: [Int32] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97,
+    107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796,
+    876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871,
+    5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623,
+    27086, 29794, 32767,
+]
+
+fileprivate struct ADPCMChannel {
+    var predictor: Int32
+    var index: Int
+
+    /// Predictor (Int16) and step index (UInt8) the chunk starts with
+    init(_ data: UnsafeMutableRawBufferPointer, at offset: Int) {
+        predictor = Int32(Int16(littleEndian: data.loadUnaligned(fromByteOffset: offset, as: Int16.self)))
+        index = min(Int(data[offset + 2]), adpcmStepTable.count - 1)
+    }
+
+    mutating func decode(_ nibble: UInt8) -> Int16 {
+        let step = adpcmStepTable[index]
+        var diff = step >> 3
+        if nibble & 4 != 0 { diff += step }
+        if nibble & 2 != 0 { diff += step >> 1 }
+        if nibble & 1 != 0 { diff += step >> 2 }
+        predictor = max(-32768, min(32767, nibble & 8 != 0 ? predictor - diff : predictor + diff))
+        index = max(0, min(adpcmStepTable.count - 1, index + adpcmIndexTable[Int(nibble)]))
+        return Int16(predictor)
+    }
+}
+
+/// Left and right state, then a byte per frame with left in the low nibble and right in the high one.
+fileprivate func decodeADPCM(_ data: UnsafeMutableRawBufferPointer, frames: Int, into pcm: UnsafeMutableRawBufferPointer) {
+    var left = ADPCMChannel(data, at: 0)
+    var right = ADPCMChannel(data, at: 4)
+    for i in 0..<frames {
+        let byte = data[8 + i]
+        pcm.storeBytes(of: left.decode(byte & 0x0F).littleEndian, toByteOffset: i * 4, as: Int16.self)
+        pcm.storeBytes(of: right.decode(byte >> 4).littleEndian, toByteOffset: i * 4 + 2, as: Int16.self)
+    }
+}
+
 @_cdecl("app_main")
 func app_main() {
     do {
@@ -9,15 +95,71 @@ func app_main() {
     }
 }
 
+/// A report on the IN endpoint, in a single fragment of its channel when the host turned the mux on.
+fileprivate func sendReport(channel: UInt8, _ report: UnsafeRawBufferPointer) {
+    guard usbd_vendor_mux() else {
+        _ = usbd_vendor_write(report.baseAddress, UInt32(report.count))
+        return
+    }
+    // One write, so another task's report cannot come between header and payload
+    var fragment = [UInt8](repeating: 0, count: fragmentHeaderSize + report.count)
+    fragment.withUnsafeMutableBytes { buffer in
+        buffer[0] = channel
+        buffer[1] = fragmentFirst | fragmentLast
+        buffer.storeBytes(of: UInt16(report.count).littleEndian, toByteOffset: 2, as: UInt16.self)
+        UnsafeMutableRawBufferPointer(rebasing: buffer[fragmentHeaderSize...]).copyMemory(from: report)
+        _ = usbd_vendor_write(buffer.baseAddress, UInt32(buffer.count))
+    }
+}
+
+/// Kind, point count, then x, y pairs in panel coordinates on the IN endpoint. An empty report is a release.
+fileprivate func sendTouchReport(_ points: [Point]) {
+    var report = [UInt8](repeating: 0, count: 8 + touchPointsMax * 4)
+    report.withUnsafeMutableBytes { buffer in
+        let count = min(points.count, touchPointsMax)
+        buffer.storeBytes(of: packetTouch.littleEndian, toByteOffset: 0, as: UInt32.self)
+        buffer.storeBytes(of: UInt32(count).littleEndian, toByteOffset: 4, as: UInt32.self)
+        for i in 0..<count {
+            buffer.storeBytes(of: UInt16(points[i].x).littleEndian, toByteOffset: 8 + i * 4, as: UInt16.self)
+            buffer.storeBytes(of: UInt16(points[i].y).littleEndian, toByteOffset: 10 + i * 4, as: UInt16.self)
+        }
+        sendReport(channel: channelTouch, UnsafeRawBufferPointer(buffer))
+    }
+}
+
+/// Reads exactly `count` bytes, false when the host stops sending midway.
+fileprivate func receive(_ address: UnsafeMutableRawPointer, count: Int) -> Bool {
+    var received = 0
+    var waitCount = 0
+    while received < count {
+        let available = Int(usbd_vendor_available())
+        if available == 0 {
+            if waitCount >= 1000 { return false }
+            waitCount += 1
+            continue
+        }
+        waitCount = 0
+        received += Int(usbd_vendor_read(address.advanced(by: received), UInt32(min(available, count - received, 512))))
+    }
+    return true
+}
+
 func main() throws(IDF.Error) {
     let tab5 = try M5StackTab5.begin()
     let frameBuffers = tab5.display.frameBuffers
     tab5.display.brightness = 100
 
     let multiTouch: MultiTouch = MultiTouch()
+    var lastTouch: [Point] = []
     multiTouch.task(xCoreID: 1) {
         tab5.touch.waitInterrupt()
-        return try! tab5.touch.coordinates
+        let coordinates = try! tab5.touch.coordinates
+        // The host injects touches as mouse input, only report changes
+        if coordinates != lastTouch && usbd_mounted() {
+            sendTouchReport(coordinates)
+        }
+        lastTouch = coordinates
+        return coordinates
     }
 
     let drawable = tab5.display.drawable(frameBuffer: frameBuffers[0])
@@ -37,15 +179,15 @@ func main() throws(IDF.Error) {
     let jpegDecoder = try IDF.JPEG.Decoder(outputFormat: .rgb888(elementOrder: .bgr, conversion: .bt601))
 
     let timer = try IDF.Timer()
-    let jpegDecoderQueue = Queue<UnsafeRawBufferPointer>(capacity: 1)!
+    let jpegDecoderQueue = Queue<Frame>(capacity: 1)!
 
     Task(name: "Decoder", priority: 15, xCoreID: 1) { _ in
         var frameBufferIndex = 0
         var frameCount = 0
         var start = timer.count
-        for jpegData in jpegDecoderQueue {
+        for frame in jpegDecoderQueue {
             guard let _ = try? jpegDecoder.decode(
-                inputBuffer: jpegData,
+                inputBuffer: frame.jpeg,
                 outputBuffer: UnsafeMutableRawBufferPointer(
                     start: frameBuffers[frameBufferIndex].baseAddress!,
                     count: frameBuffers[frameBufferIndex].count * 3
@@ -54,6 +196,15 @@ func main() throws(IDF.Error) {
                 continue
             }
 
+            // Hold the flip until the present time, so every tablet of a wall shows the frame together.
+            // Sleep whole ticks, then spin for the rest.
+            let now = timer.count
+            if frame.presentAt > now && frame.presentAt - now < maxPresentDelay {
+                let sleep = UInt32((frame.presentAt - now) / 1000)
+                if sleep > 0 { Task.delay(sleep) }
+                while timer.count < frame.presentAt {}
+            }
+
             tab5.display.drawable(frameBuffer: frameBuffers[frameBufferIndex]).flush()
             frameBufferIndex = frameBufferIndex == 0 ? 1 : 0
 
@@ -67,8 +218,101 @@ func main() throws(IDF.Error) {
         }
     }
 
+    // Recv fills one buffer and Audio plays another while the rest wait in the queue
+    let audioBuffers = (0..<audioBufferCount).map({ _ in
+        Memory.allocateRaw(size: audioBufferSize, capability: .spiram)!
+    })
+    var audioBufferIndex = 0
+    let pcmBuffer = Memory.allocateRaw(size: audioBufferSize, capability: .spiram)!
+    let audioQueue = Queue<AudioChunk>(capacity: audioBufferCount - 2)!
+
+    Task(name: "Audio", priority: 10, xCoreID: 0) { _ in
+        for chunk in audioQueue {
+            if Int(chunk.volume) != tab5.audio.volume {
+                tab5.audio.volume = Int(chunk.volume)
+            }
+            var pcm = UnsafeMutableRawBufferPointer(rebasing: chunk.data[0..<min(chunk.data.count, chunk.frames * 4)])
+            if chunk.codec == audioCodecADPCM {
+                decodeADPCM(chunk.data, frames: chunk.frames, into: pcmBuffer)
+                pcm = UnsafeMutableRawBufferPointer(rebasing: pcmBuffer[0..<chunk.frames * 4])
+            }
+
+            // Start at the present time for lip sync, after that the writes block as the codec
+            // takes the samples and chunks follow each other
+            let now = timer.count
+            if chunk.presentAt != 0 && now > chunk.presentAt + maxAudioLate {
+                continue
+            }
+            if chunk.presentAt > now && chunk.presentAt - now < maxPresentDelay {
+                Task.delay(UInt32((chunk.presentAt - now) / 1000))
+            }
+            do {
+                try tab5.audio.write(pcm)
+            } catch {
+                Log.error("Audio write failed: \(error)")
+            }
+        }
+    }
+
+    // Packets of the control and audio channels are put together here, video goes straight into
+    // the JPEG buffer and anything else is read into the scratch buffer and dropped
+    let controlBuffer = Memory.allocateRaw(size: 64)!
+    let audioPacketBuffer = Memory.allocateRaw(size: audioHeaderSize + audioBufferSize, capability: .spiram)!
+    let scratchBuffer = Memory.allocateRaw(size: fragmentSize, capability: .spiram)!
+
     Task(name: "Recv", priority: 4, xCoreID: 1) { _ in
         var mounted: Bool? = nil
+        /// Bytes of each channel's packet so far, past its buffer once a packet did not fit
+        var channelReceived = [Int](repeating: 0, count: channelCount)
+
+        /// Acts on one whole packet, frames have to be in the current JPEG buffer
+        func handle(_ packet: UnsafeRawPointer, size packetSize: Int) {
+            let kind = packet.load(fromByteOffset: 4, as: UInt32.self).littleEndian
+            if kind == packetSync {
+                // Echo the host time next to ours, the host works out the clock offset from the round trip
+                var reply = (packetSync.littleEndian, UInt32(0), packet.load(fromByteOffset: 8, as: UInt64.self), timer.count.littleEndian)
+                withUnsafeBytes(of: &reply) { sendReport(channel: channelControl, $0) }
+                return
+            }
+
+            if kind == packetAudio {
+                // Copy the samples out, the receive buffer is reused for the next packet
+                let size = packetSize - audioHeaderSize
+                let frames = Int(packet.load(fromByteOffset: 12, as: UInt32.self).littleEndian)
+                let codec = packet.load(fromByteOffset: 8, as: UInt8.self)
+                let fits = codec == audioCodecADPCM ? frames <= size - 8 : frames * 4 <= size
+                guard size > 0 && size <= audioBufferSize && frames * 4 <= audioBufferSize && fits else {
+                    Log.warn("Audio chunk invalid!")
+                    return
+                }
+                let data = UnsafeMutableRawBufferPointer(rebasing: audioBuffers[audioBufferIndex][0..<size])
+                data.copyMemory(from: UnsafeRawBufferPointer(start: packet.advanced(by: audioHeaderSize), count: size))
+                let chunk = AudioChunk(
+                    data: data,
+                    codec: codec,
+                    volume: packet.load(fromByteOffset: 9, as: UInt8.self),
+                    frames: frames,
+                    presentAt: packet.load(fromByteOffset: 16, as: UInt64.self).littleEndian
+                )
+                if audioQueue.send(chunk, timeout: 0) {
+                    audioBufferIndex = (audioBufferIndex + 1) % audioBufferCount
+                } else {
+                    Log.warn("Audio drop!")
+                }
+                return
+            }
+
+            // Legacy frames carry the JPEG right after the size
+            let headerSize = kind == packetFrame ? frameHeaderSize : 4
+            let presentAt = kind == packetFrame ? packet.load(fromByteOffset: 16, as: UInt64.self).littleEndian : 0
+            let jpegDataBuffer = UnsafeRawBufferPointer(start: jpegBuffer[jpegBufferIndex].baseAddress!.advanced(by: headerSize), count: jpegBuffer[jpegBufferIndex].count - headerSize)
+            if jpegDecoderQueue.send(Frame(jpeg: jpegDataBuffer, presentAt: presentAt), timeout: 0) {
+                jpegBufferIndex = (jpegBufferIndex + 1) % 3
+            } else {
+                Log.warn("Frame drop!")
+            }
+        }
+
         frameLoop: while (true) {
             let deviceMounted = usbd_mounted()
             if mounted != deviceMounted {
@@ -80,12 +324,52 @@ func main() throws(IDF.Error) {
                 continue;
             }
 
+            if usbd_vendor_mux() {
+                if usbd_vendor_available() == 0 { continue }
+                var header: (UInt8, UInt8, UInt16) = (0, 0, 0)
+                guard withUnsafeMutableBytes(of: &header, { receive($0.baseAddress!, count: fragmentHeaderSize) }) else { continue }
+                let (channel, flags, length) = (header.0, header.1, Int(UInt16(littleEndian: header.2)))
+                let buffer = switch channel {
+                case channelControl: controlBuffer
+                case channelAudio: audioPacketBuffer
+                case channelVideo: UnsafeMutableRawBufferPointer(jpegBuffer[jpegBufferIndex])
+                default: scratchBuffer
+                }
+                let index = Int(channel)
+                if index >= channelCount || buffer.baseAddress == scratchBuffer.baseAddress || length > fragmentSize {
+                    // Nothing here takes this channel, file transfer included
+                    var left = length
+                    while left > 0 {
+                        let size = min(left, scratchBuffer.count)
+                        guard receive(scratchBuffer.baseAddress!, count: size) else { continue frameLoop }
+                        left -= size
+                    }
+                    continue
+                }
+                if flags & fragmentFirst != 0 { channelReceived[index] = 0 }
+                let offset = channelReceived[index]
+                guard offset + length <= buffer.count else {
+                    Log.warn("Packet too large on channel \(channel)!")
+                    channelReceived[index] = buffer.count + 1
+                    _ = receive(scratchBuffer.baseAddress!, count: length)
+                    continue
+                }
+                guard receive(buffer.baseAddress!.advanced(by: offset), count: length) else { continue }
+                channelReceived[index] = offset + length
+                if flags & fragmentLast != 0 {
+                    handle(buffer.baseAddress!, size: channelReceived[index])
+                }
+                continue
+            }
+
             let availableSize = usbd_vendor_available()
             if availableSize < 2 { continue }
 
             var bufferAddress = jpegBuffer[jpegBufferIndex].baseAddress!
             let readSize = usbd_vendor_read(bufferAddress, min(availableSize, 512))
-            var jpegDataSize = UnsafeRawPointer(bufferAddress).load(as: UInt32.self).littleEndian
+            let packet = UnsafeRawPointer(bufferAddress)
+            var jpegDataSize = packet.load(as: UInt32.self).littleEndian
+            let packetSize = Int(jpegDataSize)
             bufferAddress = bufferAddress.advanced(by: Int(readSize))
             // Log.info("Start Receive: \(jpegDataSize)")
 
@@ -102,12 +386,7 @@ func main() throws(IDF.Error) {
                 bufferAddress = bufferAddress.advanced(by: Int(readSize))
             }
 
-            let jpegDataBuffer = UnsafeRawBufferPointer(start: jpegBuffer[jpegBufferIndex].baseAddress!.advanced(by: 4), count: jpegBuffer[jpegBufferIndex].count - 4)
-            if jpegDecoderQueue.send(jpegDataBuffer, timeout: 0) {
-                jpegBufferIndex = (jpegBufferIndex + 1) % 3
-            } else {
-                Log.warn("Frame drop!")
-            }
+            handle(packet, size: packetSize)
         }
     }
 }
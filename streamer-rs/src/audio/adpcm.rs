@@ -0,0 +1,96 @@
+const INDEX_TABLE: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+const STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97,
+    107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796,
+    876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871,
+    5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623,
+    27086, 29794, 32767,
+];
+
+/// IMA-ADPCM state of one channel, carried from chunk to chunk.
+#[derive(Clone, Copy, Default)]
+struct Channel {
+    predictor: i32,
+    index: i32,
+}
+
+impl Channel {
+    fn encode(&mut self, sample: i16) -> u8 {
+        let step = STEP_TABLE[self.index as usize];
+        let mut diff = sample as i32 - self.predictor;
+        let mut nibble = 0;
+        if diff < 0 {
+            nibble = 8;
+            diff = -diff;
+        }
+        if diff >= step { nibble |= 4; diff -= step; }
+        if diff >= step >> 1 { nibble |= 2; diff -= step >> 1; }
+        if diff >= step >> 2 { nibble |= 1; }
+        // Follow the decoder so rounding errors do not add up
+        self.decode(nibble);
+        nibble
+    }
+
+    fn decode(&mut self, nibble: u8) {
+        let step = STEP_TABLE[self.index as usize];
+        let mut diff = step >> 3;
+        if nibble & 4 != 0 { diff += step; }
+        if nibble & 2 != 0 { diff += step >> 1; }
+        if nibble & 1 != 0 { diff += step >> 2; }
+        self.predictor = if nibble & 8 != 0 { self.predictor - diff } else { self.predictor + diff }
+            .clamp(i16::MIN as i32, i16::MAX as i32);
+        self.index = (self.index + INDEX_TABLE[nibble as usize]).clamp(0, STEP_TABLE.len() as i32 - 1);
+    }
+}
+
+/// Stereo IMA-ADPCM, 4 bits a sample.
+#[derive(Default)]
+pub struct Encoder {
+    channels: [Channel; 2],
+}
+
+impl Encoder {
+    /// Predictor (i16) and step index (u8, one byte padding) of left and right, so each chunk decodes
+    /// on its own, then a byte per frame with left in the low nibble and right in the high one.
+    pub fn encode(&mut self, frames: &[[i16; 2]]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(8 + frames.len());
+        for channel in &self.channels {
+            data.extend((channel.predictor as i16).to_le_bytes());
+            data.extend([channel.index as u8, 0]);
+        }
+        let [left, right] = &mut self.channels;
+        data.extend(frames.iter().map(|[l, r]| left.encode(*l) | right.encode(*r) << 4));
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a chunk the way the tablet does, starting from the state in its header.
+    fn decode(data: &[u8]) -> Vec<[i16; 2]> {
+        let channel = |i: usize| Channel { predictor: i16::from_le_bytes([data[i * 4], data[i * 4 + 1]]) as i32, index: data[i * 4 + 2] as i32 };
+        let [mut left, mut right] = [channel(0), channel(1)];
+        data[8..].iter().map(|byte| {
+            left.decode(byte & 0x0F);
+            right.decode(byte >> 4);
+            [left.predictor as i16, right.predictor as i16]
+        }).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let sine = |i: usize, hz: f64| ((i as f64 * hz * std::f64::consts::TAU / 48000.0).sin() * 8000.0) as i16;
+        let frames: Vec<[i16; 2]> = (0..960).map(|i| [sine(i, 440.0), sine(i, 660.0)]).collect();
+        let mut encoder = Encoder::default();
+        // Each chunk decodes on its own
+        let decoded: Vec<_> = frames.chunks(480).flat_map(|chunk| decode(&encoder.encode(chunk))).collect();
+        assert_eq!(decoded.len(), frames.len());
+        // The step size needs a few samples to adapt from silence
+        let error = frames.iter().zip(&decoded).skip(32)
+            .flat_map(|(a, b)| [(a[0] - b[0]).abs(), (a[1] - b[1]).abs()])
+            .max().unwrap();
+        assert!(error < 256, "off by {}", error);
+    }
+}
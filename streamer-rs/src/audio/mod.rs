@@ -0,0 +1,99 @@
+mod adpcm;
+mod resample;
+mod source;
+
+pub use source::{RawFormat, Source};
+
+use crate::{device::{PACKET_AUDIO, Tablet}, mux::Channel, sync::{Presenter, host_time}};
+use std::{sync::{Arc, Mutex}, thread, time::Duration};
+use clap::ValueEnum;
+
+/// What the tablet's codec is opened with, 16-bit stereo at this rate
+pub const RATE: u32 = 48000;
+pub const HEADER_SIZE: usize = 24;
+const CHUNK: Duration = Duration::from_millis(20);
+/// A pipe this far behind its timeline starts a new one
+const LATE: Duration = Duration::from_millis(100);
+
+/// How audio chunks are coded on the USB link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Codec {
+    /// 16-bit little-endian samples, 192 kB/s
+    Pcm,
+    /// IMA-ADPCM, a quarter of the bandwidth
+    Adpcm,
+}
+
+pub struct Options {
+    pub codec: Codec,
+    /// Speaker volume 0-100
+    pub volume: u8,
+    /// Start over at the end of a file
+    pub repeat: bool,
+}
+
+/// Audio Thread: reads the source in 20ms chunks at its own pace or in real time for files,
+/// resamples and codes them, and sends them to the first tablet stamped with when to play.
+/// With a presenter the stamp is the frames' timeline plus the present delay, the same delay the
+/// video gets, otherwise chunks play on arrival.
+pub fn run(mut source: Source, options: Options, tablets: Arc<Vec<Tablet>>, presenter: Option<Arc<Mutex<Presenter>>>) {
+    let mut resampler = resample::Resampler::new(source.rate, RATE);
+    let mut encoder = adpcm::Encoder::default();
+    let chunk = (source.rate as u64 * CHUNK.as_micros() as u64 / 1_000_000).max(1) as usize;
+    let mut start = host_time();
+    let mut played: u64 = 0;
+    loop {
+        let input = match source.read(chunk) {
+            Ok(input) => input,
+            Err(e) => {
+                println!("Audio: {}", e);
+                return;
+            }
+        };
+        if input.is_empty() {
+            match options.repeat.then(|| source.rewind()) {
+                Some(Ok(true)) => continue,
+                Some(Err(e)) => println!("Audio: {}", e),
+                _ => println!("Audio Finished"),
+            }
+            return;
+        }
+        let frames = resampler.process(&input);
+        if frames.is_empty() { continue }
+
+        let elapsed = played * 1_000_000 / RATE as u64;
+        let now = host_time();
+        if now > start + elapsed + LATE.as_micros() as u64 {
+            start = now - elapsed;
+        }
+        let due = start + elapsed;
+        if due > now {
+            thread::sleep(Duration::from_micros(due - now));
+        }
+        played += frames.len() as u64;
+
+        let present = presenter.as_ref().map_or(0, |presenter| presenter.lock().unwrap().present_at(0, due));
+        let payload = match options.codec {
+            Codec::Pcm => frames.iter().flatten().flat_map(|sample| sample.to_le_bytes()).collect(),
+            Codec::Adpcm => encoder.encode(&frames),
+        };
+        // Small enough for one transfer, it never splits from its header
+        let packet = [&header(options.codec, options.volume, frames.len(), present, payload.len())[..], &payload].concat();
+        if let Err(e) = tablets[0].send(Channel::Audio, &[&packet]) {
+            println!("Audio: {}", e);
+            return;
+        }
+    }
+}
+
+/// Size, kind, codec, volume, two bytes padding, frame count and the device time to play at (0 = now).
+fn header(codec: Codec, volume: u8, frames: usize, present: u64, size: usize) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    header[..4].copy_from_slice(&((HEADER_SIZE + size) as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&PACKET_AUDIO.to_le_bytes());
+    header[8] = codec as u8;
+    header[9] = volume.min(100);
+    header[12..16].copy_from_slice(&(frames as u32).to_le_bytes());
+    header[16..].copy_from_slice(&present.to_le_bytes());
+    header
+}
@@ -0,0 +1,73 @@
+/// Linear interpolation between two rates, continuous across the chunks it is fed.
+pub struct Resampler {
+    /// Input frames per output frame
+    step: f64,
+    /// Where the next output frame falls, 0 being `previous` and 1 the first frame of the next input
+    position: f64,
+    previous: [i16; 2],
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32) -> Self {
+        Resampler { step: from as f64 / to as f64, position: 1.0, previous: [0; 2] }
+    }
+
+    pub fn process(&mut self, input: &[[i16; 2]]) -> Vec<[i16; 2]> {
+        if self.step == 1.0 {
+            return input.to_vec();
+        }
+        let mut output = Vec::with_capacity((input.len() as f64 / self.step) as usize + 1);
+        while self.position < input.len() as f64 {
+            let i = self.position as usize;
+            let t = self.position - i as f64;
+            let a = if i == 0 { self.previous } else { input[i - 1] };
+            let b = input[i];
+            output.push([0, 1].map(|c| (a[c] as f64 + (b[c] as f64 - a[c] as f64) * t).round() as i16));
+            self.position += self.step;
+        }
+        if let Some(last) = input.last() {
+            self.position -= input.len() as f64;
+            self.previous = *last;
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(count: usize) -> Vec<[i16; 2]> {
+        (0..count).map(|i| [i as i16 * 10, -(i as i16) * 10]).collect()
+    }
+
+    #[test]
+    fn same_rate_passes_through() {
+        let input = ramp(100);
+        assert_eq!(Resampler::new(48000, 48000).process(&input), input);
+    }
+
+    #[test]
+    fn chunks_resample_like_the_whole() {
+        let input = ramp(1000);
+        let whole = Resampler::new(44100, 48000).process(&input);
+        let mut resampler = Resampler::new(44100, 48000);
+        let chunked: Vec<_> = input.chunks(147).flat_map(|chunk| resampler.process(chunk)).collect();
+        assert_eq!(chunked.len(), whole.len());
+        // The position is carried over as a fraction, it may round a sample the other way
+        for (a, b) in chunked.iter().zip(&whole) {
+            assert!(a[0].abs_diff(b[0]) <= 1 && a[1].abs_diff(b[1]) <= 1, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn rate_changes_the_frame_count() {
+        let mut resampler = Resampler::new(48000, 24000);
+        let output: usize = (0..10).map(|_| resampler.process(&[[0; 2]; 480]).len()).sum();
+        assert_eq!(output, 2400);
+        // The last input frame is held back until the next one to interpolate towards
+        let mut resampler = Resampler::new(24000, 48000);
+        let output: usize = (0..10).map(|_| resampler.process(&[[0; 2]; 240]).len()).sum();
+        assert_eq!(output, 4800 - 2);
+    }
+}
@@ -0,0 +1,148 @@
+use std::{fs::File, io::{BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}, str::FromStr};
+
+/// Sample rate and channel count of headerless 16-bit little-endian PCM.
+#[derive(Clone, Copy, Debug)]
+pub struct RawFormat {
+    pub rate: u32,
+    pub channels: u16,
+}
+
+impl FromStr for RawFormat {
+    type Err = String;
+
+    /// `RATE:CHANNELS`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rate, channels) = s.split_once(':').ok_or_else(|| format!("expected RATE:CHANNELS: {}", s))?;
+        let rate = rate.parse().map_err(|_| format!("invalid rate: {}", rate))?;
+        let channels = channels.parse().map_err(|_| format!("invalid channels: {}", channels))?;
+        if rate == 0 || channels == 0 {
+            return Err(format!("empty format: {}", s));
+        }
+        Ok(RawFormat { rate, channels })
+    }
+}
+
+/// 16-bit PCM from a WAV file, a raw file or stdin (`-`), handed out as stereo frames.
+pub struct Source {
+    reader: Box<dyn Read + Send>,
+    pub rate: u32,
+    channels: u16,
+    /// File, offset and length of the samples, for starting over
+    rewind: Option<(PathBuf, u64, u64)>,
+}
+
+impl Source {
+    /// Raw PCM when `raw` is given, otherwise a WAV file.
+    pub fn open(path: &Path, raw: Option<RawFormat>) -> Result<Self, String> {
+        let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        if path == Path::new("-") {
+            let RawFormat { rate, channels } = raw.ok_or("stdin needs --audio-raw RATE:CHANNELS")?;
+            return Ok(Source { reader: Box::new(std::io::stdin()), rate, channels, rewind: None });
+        }
+        let mut file = BufReader::new(File::open(path).map_err(error)?);
+        let (rate, channels, offset, length) = match raw {
+            Some(RawFormat { rate, channels }) => (rate, channels, 0, u64::MAX),
+            None => wav_header(&mut file).map_err(|e| format!("{}: {}", path.display(), e))?,
+        };
+        // Chunks after the samples are not played
+        Ok(Source { reader: Box::new(file.take(length)), rate, channels, rewind: Some((path.to_path_buf(), offset, length)) })
+    }
+
+    /// Up to `count` frames, fewer only at the end. Mono plays on both sides, past two channels are dropped.
+    pub fn read(&mut self, count: usize) -> std::io::Result<Vec<[i16; 2]>> {
+        let frame_size = self.channels as usize * 2;
+        let mut data = vec![0u8; count * frame_size];
+        let mut filled = 0;
+        while filled < data.len() {
+            match self.reader.read(&mut data[filled..])? {
+                0 => break,
+                size => filled += size,
+            }
+        }
+        let sample = |frame: &[u8], channel: usize| i16::from_le_bytes([frame[channel * 2], frame[channel * 2 + 1]]);
+        Ok(data[..filled - filled % frame_size].chunks_exact(frame_size)
+            .map(|frame| [sample(frame, 0), sample(frame, (self.channels as usize).min(2) - 1)])
+            .collect())
+    }
+
+    /// Back to the first sample, false for stdin.
+    pub fn rewind(&mut self) -> std::io::Result<bool> {
+        let Some((path, offset, length)) = &self.rewind else { return Ok(false) };
+        let mut file = BufReader::new(File::open(path)?);
+        file.seek(SeekFrom::Start(*offset))?;
+        self.reader = Box::new(file.take(*length));
+        Ok(true)
+    }
+}
+
+/// Sample rate, channels, offset and length of the samples, skipping chunks other than `fmt ` and `data`.
+fn wav_header(file: &mut BufReader<File>) -> Result<(u32, u16, u64, u64), String> {
+    let io = |e: std::io::Error| e.to_string();
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff).map_err(io)?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err("not a WAV file".to_string());
+    }
+    let mut format = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk).map_err(io)?;
+        let size = u32::from_le_bytes(chunk[4..].try_into().unwrap()) as u64;
+        match &chunk[..4] {
+            b"fmt " => {
+                let mut fmt = vec![0u8; size as usize];
+                file.read_exact(&mut fmt).map_err(io)?;
+                if fmt.len() < 16 {
+                    return Err("short fmt chunk".to_string());
+                }
+                // PCM, or WAVE_FORMAT_EXTENSIBLE which is PCM here too
+                let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                if (tag != 1 && tag != 0xFFFE) || bits != 16 || channels == 0 || rate == 0 {
+                    return Err(format!("only 16-bit PCM is supported (format {}, {} bits)", tag, bits));
+                }
+                format = Some((rate, channels));
+                if size % 2 == 1 { file.seek_relative(1).map_err(io)?; }
+            }
+            b"data" => {
+                let Some((rate, channels)) = format else { return Err("data before fmt chunk".to_string()) };
+                return Ok((rate, channels, file.stream_position().map_err(io)?, size));
+            }
+            // Chunks are padded to an even size
+            _ => file.seek_relative((size + size % 2) as i64).map_err(io)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_stops_at_the_end_of_the_data_chunk() {
+        let frames: [i16; 6] = [100, -100, 200, -200, 300, -300];
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend(b"fmt \x10\0\0\0");
+        // PCM, stereo, 48000 Hz, 192000 bytes/s, 4 bytes a frame, 16 bits
+        wav.extend([1, 0, 2, 0]);
+        wav.extend(48000u32.to_le_bytes());
+        wav.extend(192000u32.to_le_bytes());
+        wav.extend([4, 0, 16, 0]);
+        wav.extend(b"data");
+        wav.extend((frames.len() as u32 * 2).to_le_bytes());
+        wav.extend(frames.iter().flat_map(|s| s.to_le_bytes()));
+        // Metadata after the samples, it would play as noise
+        wav.extend(b"LIST\x04\0\0\0INFO");
+        let path = std::env::temp_dir().join(format!("source-{}.wav", std::process::id()));
+        std::fs::write(&path, wav).unwrap();
+
+        let mut source = Source::open(&path, None).unwrap();
+        let expected = vec![[100, -100], [200, -200], [300, -300]];
+        assert_eq!(source.read(16).unwrap(), expected);
+        assert!(source.rewind().unwrap());
+        assert_eq!(source.read(16).unwrap(), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
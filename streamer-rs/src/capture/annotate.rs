@@ -0,0 +1,174 @@
+use crate::capture::{Color, FrameCaptureData, fit::Rect, font, mask};
+use crate::inject::Gesture;
+use std::path::PathBuf;
+
+/// Pen radius in canvas pixels
+const RADIUS: f64 = 3.0;
+const SWATCH: usize = 44;
+const MARGIN: usize = 12;
+const SPACING: usize = 6;
+const PALETTE: [Color; 8] = [
+    Color { r: 255, g: 59, b: 48 },
+    Color { r: 255, g: 204, b: 0 },
+    Color { r: 52, g: 199, b: 89 },
+    Color { r: 90, g: 200, b: 250 },
+    Color { r: 0, g: 122, b: 255 },
+    Color { r: 175, g: 82, b: 222 },
+    Color { r: 255, g: 255, b: 255 },
+    Color { r: 0, g: 0, b: 0 },
+];
+// The two buttons after the colours
+const CLEAR: usize = PALETTE.len();
+const SAVE: usize = PALETTE.len() + 1;
+
+/// Start, end and ink of a piece of stroke
+type Segment = ((f64, f64), (f64, f64), u8);
+
+/// Finger strokes over the stream, drawn by the encode stage on top of everything but the HUD.
+/// A strip of swatches in the bottom left corner, clear of the HUD, picks the colour, clears and saves a PNG.
+pub struct Annotation {
+    color: usize,
+    /// Palette entry plus one of every canvas pixel, 0 where there is no ink
+    ink: Vec<u8>,
+    size: (usize, usize),
+    /// Segments drawn since the last frame, stamped into the ink then
+    pending: Vec<Segment>,
+    /// Where the finger landed, a stroke starts there once it moves
+    start: Option<(f64, f64)>,
+    /// End of the stroke being drawn
+    last: Option<(f64, f64)>,
+    clear: bool,
+    snapshot: bool,
+    directory: PathBuf,
+}
+
+impl Annotation {
+    /// Snapshots are saved into `directory`.
+    pub fn new(directory: PathBuf) -> Self {
+        Annotation {
+            color: 0,
+            ink: Vec::new(),
+            size: (0, 0),
+            pending: Vec::new(),
+            start: None,
+            last: None,
+            clear: false,
+            snapshot: false,
+            directory,
+        }
+    }
+
+    /// Draws with one finger, taps on the palette pick a colour, clear or save.
+    /// `to_canvas` maps output coordinates of the touched tablet to the canvas.
+    pub fn touch(&mut self, gesture: Gesture, to_canvas: impl Fn((f64, f64)) -> (f64, f64)) {
+        match gesture {
+            Gesture::Point(x, y) => {
+                let point = to_canvas((x, y));
+                match self.last {
+                    Some(last) => {
+                        self.pending.push((last, point, self.color as u8 + 1));
+                        self.last = Some(point);
+                    }
+                    None => self.start = Some(point),
+                }
+            }
+            Gesture::Press => self.last = self.start.filter(|start| swatch_at(*start, self.size).is_none()),
+            Gesture::Tap(x, y) => {
+                let point = to_canvas((x, y));
+                match swatch_at(point, self.size) {
+                    Some(CLEAR) => self.clear = true,
+                    Some(SAVE) => self.snapshot = true,
+                    Some(color) => self.color = color,
+                    None => self.pending.push((point, point, self.color as u8 + 1)),
+                }
+                self.start = None;
+            }
+            _ => {
+                self.start = None;
+                self.last = None;
+            }
+        }
+    }
+
+    /// Inks the frame and returns a copy of it when a snapshot was asked for, then draws the palette.
+    pub fn apply(&mut self, frame: &mut FrameCaptureData) -> Option<FrameCaptureData> {
+        if self.size != (frame.width, frame.height) || self.clear {
+            self.size = (frame.width, frame.height);
+            self.ink = vec![0; frame.width * frame.height];
+            self.clear = false;
+        }
+        for (a, b, ink) in std::mem::take(&mut self.pending) {
+            self.stamp(a, b, ink);
+        }
+
+        let colors = PALETTE.map(|color| color.bytes(frame.pixel_format));
+        for (pixel, ink) in frame.data.chunks_exact_mut(4).zip(&self.ink) {
+            if *ink != 0 {
+                pixel.copy_from_slice(&colors[*ink as usize - 1]);
+            }
+        }
+        let snapshot = std::mem::take(&mut self.snapshot).then(|| frame.clone());
+        self.draw_palette(frame);
+        snapshot
+    }
+
+    /// Where the next snapshot goes, saves in the same second get their own files.
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.directory.join(format!("annotation-{}.png", chrono::Local::now().format("%Y%m%d-%H%M%S-%3f")))
+    }
+
+    // Round-capped line of ink from `a` to `b`
+    fn stamp(&mut self, a: (f64, f64), b: (f64, f64), ink: u8) {
+        let (width, height) = self.size;
+        let left = (a.0.min(b.0) - RADIUS).floor().max(0.0) as usize;
+        let top = (a.1.min(b.1) - RADIUS).floor().max(0.0) as usize;
+        let right = ((a.0.max(b.0) + RADIUS).ceil().max(0.0) as usize).min(width);
+        let bottom = ((a.1.max(b.1) + RADIUS).ceil().max(0.0) as usize).min(height);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = dx * dx + dy * dy;
+        for y in top..bottom {
+            for x in left..right {
+                let p = (x as f64 + 0.5, y as f64 + 0.5);
+                // Closest point of the segment
+                let t = if length > 0.0 { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length).clamp(0.0, 1.0) } else { 0.0 };
+                let (cx, cy) = (a.0 + t * dx, a.1 + t * dy);
+                if (p.0 - cx).hypot(p.1 - cy) <= RADIUS {
+                    self.ink[y * width + x] = ink;
+                }
+            }
+        }
+    }
+
+    fn draw_palette(&self, frame: &mut FrameCaptureData) {
+        let white = Color { r: 255, g: 255, b: 255 };
+        for i in 0..=SAVE {
+            let rect = swatch(i, self.size);
+            let fill = PALETTE.get(i).copied().unwrap_or(Color { r: 64, g: 64, b: 64 });
+            // The chosen colour gets a white frame
+            if i == self.color {
+                let border = Rect::new(rect.x - 3.0, rect.y - 3.0, rect.width + 6.0, rect.height + 6.0);
+                mask::fill(&mut frame.data, frame.width, frame.height, border, white.bytes(frame.pixel_format));
+            }
+            mask::fill(&mut frame.data, frame.width, frame.height, rect, fill.bytes(frame.pixel_format));
+            let label = match i {
+                CLEAR => "CLR",
+                SAVE => "PNG",
+                _ => continue,
+            };
+            let (width, height) = font::text_size(label, 2);
+            let position = (rect.x as usize + (SWATCH - width) / 2, rect.y as usize + (SWATCH - height) / 2);
+            font::draw_text(frame, position, 2, label, white);
+        }
+    }
+}
+
+/// Canvas area of a palette button, `size` being the canvas size.
+fn swatch(index: usize, size: (usize, usize)) -> Rect {
+    let x = MARGIN + index * (SWATCH + SPACING);
+    let y = size.1 as f64 - (MARGIN + SWATCH) as f64;
+    Rect::new(x as f64, y, SWATCH as f64, SWATCH as f64)
+}
+
+fn swatch_at(point: (f64, f64), size: (usize, usize)) -> Option<usize> {
+    (0..=SAVE).find(|&i| swatch(i, size).contains(point))
+}
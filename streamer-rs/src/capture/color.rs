@@ -0,0 +1,40 @@
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub fn bytes(self, pixel_format: turbojpeg::PixelFormat) -> [u8; 4] {
+        match pixel_format {
+            turbojpeg::PixelFormat::RGBX => [self.r, self.g, self.b, 255],
+            turbojpeg::PixelFormat::BGRA => [self.b, self.g, self.r, 255],
+            _ => panic!("Unsupported Pixel Format!"),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        let value = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid color: {}", s))?;
+        if hex.len() != 6 {
+            return Err(format!("color must be RRGGBB: {}", s));
+        }
+        Ok(Color { r: (value >> 16) as u8, g: (value >> 8) as u8, b: value as u8 })
+    }
+}
+
+/// Byte offsets of the red, green and blue channels in a 4 bytes per pixel format.
+pub fn channels(pixel_format: turbojpeg::PixelFormat) -> [usize; 3] {
+    match pixel_format {
+        turbojpeg::PixelFormat::RGBX => [0, 1, 2],
+        turbojpeg::PixelFormat::BGRA => [2, 1, 0],
+        _ => panic!("Unsupported Pixel Format!"),
+    }
+}
@@ -1,193 +1,278 @@
-use crate::capture::FrameConvertedData;
-use std::{thread, sync::mpsc, time::Duration};
+use crate::capture::{CaptureSource, FrameCaptureData, Context, Options, WindowSelector, fit::Rect, pipeline};
+use std::{process::Command, thread, time::{Duration, Instant}};
 use scap::{
     capturer::{self, Capturer},
     frame::{Frame, FrameType},
 };
-use fast_image_resize as fir;
 
-const BUF_SIZE: usize = 512 * 1024;
-const JPEG_QUALITY_LEVELS: [i32; 4] = [40, 60, 70, 80];
+/// Relative aspect ratio difference up to which a desktop rect is taken to be what the frames show
+const ASPECT_TOLERANCE: f64 = 0.02;
 
-pub struct FrameCaptureData {
-    pub data: Vec<u8>,
-    pub width: usize,
-    pub height: usize,
-    pub pixel_format: turbojpeg::PixelFormat,
-    pub fps: Option<usize>,
+pub fn start<F>(options: Options, tx_thread: F)
+where
+    F: FnOnce(Context) + Send + 'static,
+{
+    let (senders, context) = pipeline::start(&options);
+
+    // Capture Threads, one per source
+    for ((source, options), sender) in options.sources().into_iter().zip(senders) {
+        thread::spawn(move || capture(source, options, sender));
+    }
+
+    tx_thread(context);
 }
 
-pub struct Context {
-    rx: mpsc::Receiver<FrameConvertedData>,
+fn capture(source: CaptureSource, options: Options, sender: pipeline::Sender) {
+    let CaptureSource::Window(selector) = &source else {
+        stream(&source, &options, &sender);
+        panic!("Capture Recv Failed!");
+    };
+    loop {
+        stream(&source, &options, &sender);
+        // The window was closed, wait until it is back before asking for it again
+        println!("Window closed, waiting for {}...", selector);
+        while window_listed(selector) == Some(false) {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
 }
 
-pub fn start<F>(display_index: Option<usize>, tx_thread: F)
-where
-    F: FnOnce(Context) + Send + 'static,
-{
-    let (resz_tx, resz_rx) = mpsc::sync_channel::<FrameCaptureData>(1);
-    let (jpeg_tx, jpeg_rx) = mpsc::sync_channel::<FrameCaptureData>(1);
-    let (conv_tx, conv_rx) = mpsc::sync_channel::<FrameConvertedData>(1);
-
-    // Capture Thread
-    let resz_tx_capture = resz_tx.clone();
-    let jpeg_tx_capture = jpeg_tx.clone();
-    thread::spawn(move || {
-        let options = capturer::Options {
-            fps: 60,
-            target: capture_target(display_index),
-            show_cursor: true,
-            show_highlight: true,
-            excluded_targets: None,
-            output_type: FrameType::BGRAFrame,
-            output_resolution: capturer::Resolution::_720p,
-            ..Default::default()
+/// Sends frames until the capture ends, or the captured window disappears from the window list.
+fn stream(source: &CaptureSource, options: &Options, sender: &pipeline::Sender) {
+    let capturer_options = capturer::Options {
+        fps: 60,
+        target: capture_target(source),
+        show_cursor: !options.cursor,
+        show_highlight: true,
+        output_type: FrameType::BGRAFrame,
+        output_resolution: capturer::Resolution::_720p,
+        ..Default::default()
+    };
+    let mut capturer = Capturer::build(capturer_options).unwrap();
+    capturer.start_capture();
+
+    let mut frames = 0;
+    let mut start = Instant::now();
+    // Windows move, their rect is looked up again now and then. A window is followed by its X id
+    // from the first match on, so a title change does not end the capture.
+    let mut window = None;
+    let mut location = None;
+    let mut located: Option<Instant> = None;
+    let mut mismatched = false;
+    while let Ok(frame) = capturer.get_next_frame() {
+        let (data, width, height, pixel_format) = match frame {
+            Frame::YUVFrame(_) => panic!("Unsupported Frame Format!: YUV"),
+            Frame::RGB(_) => panic!("Unsupported Frame Format!: RGB"),
+            Frame::RGBx(frame) => (frame.data, frame.width, frame.height, turbojpeg::PixelFormat::RGBX),
+            Frame::XBGR(_) => panic!("Unsupported Frame Format!: XBGR"),
+            Frame::BGRx(_) => panic!("Unsupported Frame Format!: BGRX"),
+            Frame::BGR0(_) => panic!("Unsupported Frame Format!: BGR0"),
+            Frame::BGRA(frame) => (frame.data, frame.width, frame.height, turbojpeg::PixelFormat::BGRA),
         };
-        let mut capturer = Capturer::build(options).unwrap();
-        capturer.start_capture();
-
-        let mut frames = 0;
-        let mut start = std::time::Instant::now();
-        loop {
-            let frame = capturer.get_next_frame().expect("Capture Recv Failed!");
-            let (data, width, height, pixel_format) = match frame {
-                Frame::YUVFrame(_) => panic!("Unsupported Frame Format!: YUV"),
-                Frame::RGB(_) => panic!("Unsupported Frame Format!: RGB"),
-                Frame::RGBx(frame) => (frame.data, frame.width, frame.height, turbojpeg::PixelFormat::RGBX),
-                Frame::XBGR(_) => panic!("Unsupported Frame Format!: XBGR"),
-                Frame::BGRx(_) => panic!("Unsupported Frame Format!: BGRX"),
-                Frame::BGR0(_) => panic!("Unsupported Frame Format!: BGR0"),
-                Frame::BGRA(frame) => (frame.data, frame.width, frame.height, turbojpeg::PixelFormat::BGRA),
-            };
-            if data.len() == 0 { continue }
-
-            frames += 1;
-            let fps = if start.elapsed() >= Duration::from_secs(1) {
-                let fps = Some(frames);
-                frames = 0;
-                start = std::time::Instant::now();
-                fps
-            } else {
-                None
-            };
+        if data.is_empty() { continue }
 
-            let frame_size = (width, height);
-            let data = FrameCaptureData { data, pixel_format, width: width as usize, height: height as usize, fps };
-            if frame_size == (1280, 720) || frame_size == (720, 1280) {
-                let _ = jpeg_tx_capture.try_send(data);
+        if located.is_none_or(|t| t.elapsed() >= Duration::from_secs(1)) {
+            located = Some(Instant::now());
+            location = match source {
+                CaptureSource::Window(selector) => match track_window(selector, &mut window) {
+                    Some(None) => break,
+                    found => found.flatten(),
+                },
+                _ => desktop_rect(source),
+            };
+            // The portal decides what is shared, the X screen is only a guess at where it is
+            if let Some(rect) = location && !same_aspect(rect, (width as usize, height as usize)) {
+                if !mismatched {
+                    println!("Desktop rect {:.0}x{:.0}+{:.0}+{:.0} does not match the {}x{} frames, touches map to the frame",
+                        rect.width, rect.height, rect.x, rect.y, width, height);
+                }
+                mismatched = true;
+                location = None;
             } else {
-                let _ = resz_tx_capture.try_send(data);
+                mismatched = false;
             }
         }
-    });
 
-    // Resize Thread
-    let jpeg_tx_resize = jpeg_tx.clone();
-    thread::spawn(move || {
-        let mut resizer = fir::Resizer::new();
-        for frame in resz_rx {
-            let (rwidth, rheight) = if frame.width > frame.height { (1280, 720) } else { (720, 1280) };
-            let pixel_type = match frame.pixel_format {
-                turbojpeg::PixelFormat::RGBX => fir::PixelType::U8x4,
-                turbojpeg::PixelFormat::BGRA => fir::PixelType::U8x4,
-                _ => panic!("Unsupported Pixel Format!"),
-            };
-            let original = fir::images::Image::from_vec_u8(
-                frame.width as u32, frame.height as u32, frame.data, pixel_type
-            ).expect("Failed to create original image container");
-            let mut resized = fir::images::Image::from_vec_u8(
-                rwidth as u32, rheight as u32, vec![0; rwidth * rheight * 4], pixel_type
-            ).expect("Failed to create resized image container");
-
-            resizer.resize(&original, &mut resized, &fir::ResizeOptions {
-                algorithm: fir::ResizeAlg::Nearest,
-                cropping: fir::SrcCropping::None,
-                mul_div_alpha: false,
-            }).expect("Resize Image Failed!");
-            let data = FrameCaptureData {
-                data: resized.into_vec(),
-                pixel_format: frame.pixel_format,
-                width: rwidth,
-                height: rheight,
-                fps: frame.fps
+        frames += 1;
+        let fps = if start.elapsed() >= Duration::from_secs(1) {
+            let fps = Some(frames);
+            frames = 0;
+            start = Instant::now();
+            fps
+        } else {
+            None
+        };
+
+        // Without X11 to ask, the frame is all there is to go by
+        let desktop = location.unwrap_or(Rect::new(0.0, 0.0, width as f64, height as f64));
+        sender.send(FrameCaptureData { data, pixel_format, width: width as usize, height: height as usize, desktop, fps, captured: Instant::now() });
+    }
+    capturer.stop_capture();
+}
+
+fn capture_target(source: &CaptureSource) -> Option<scap::Target> {
+    match source {
+        CaptureSource::Window(selector) => {
+            // scap only knows titles, a pid is looked up among the X windows first
+            let title = match selector {
+                WindowSelector::Pid(_) => windows().and_then(|windows| windows.into_iter().find(|w| window_matches(selector, w))).map(|w| w.title),
+                WindowSelector::Title(_) => None,
             };
-            let _ = jpeg_tx_resize.try_send(data);
+            for target in scap::get_all_targets() {
+                if let scap::Target::Window(w) = target && (selector.matches(&w.title, None) || title.as_ref() == Some(&w.title)) {
+                    return Some(scap::Target::Window(w));
+                }
+            }
+            // The portal does not list windows before the capture starts
+            println!("Window ({}) not found, choose it in the screen share dialog.", selector);
+            None
         }
-    });
+        CaptureSource::Display(Some(index)) => {
+            scap::get_all_targets().into_iter()
+                .filter(|target| matches!(target, scap::Target::Display(_)))
+                .nth(*index)
+        }
+        CaptureSource::Display(None) => None,
+    }
+}
 
-    // JPEG Encode Thread
-    let conv_tx_jpeg = conv_tx.clone();
-    thread::spawn(move || {
-        let mut compressor = turbojpeg::Compressor::new().expect("Failed to create turbojpeg Compressor");
-        let mut transformer = turbojpeg::Transformer::new().expect("Failed to create turbojpeg Transformer");
-
-        let mut quality_level: usize = 0;
-        compressor.set_quality(JPEG_QUALITY_LEVELS[0]).expect("set jpeg quality failed!");
-        compressor.set_optimize(false).expect("set jpeg optimize failed!");
-        compressor.set_subsamp(turbojpeg::Subsamp::Sub2x2).expect("set jpeg subsamp failed!");
-
-        let mut compress_buffer: [u8; BUF_SIZE] = [0; BUF_SIZE];
-        let mut last = std::time::Instant::now();
-
-        for frame in jpeg_rx {
-            let image = turbojpeg::Image {
-                pixels: frame.data.as_ref(),
-                width: 1280,
-                pitch: frame.width * 4,
-                height: 720,
-                format: frame.pixel_format,
-            };
+/// Where the display is on the X screen, `None` when that can not be found out (Wayland, no x11-utils).
+fn desktop_rect(source: &CaptureSource) -> Option<Rect> {
+    match source {
+        CaptureSource::Window(_) => None,
+        // Displays are listed in the same order as scap lists them, no index is the primary one
+        CaptureSource::Display(Some(index)) => monitors()?.get(*index).map(|(_, rect)| *rect),
+        CaptureSource::Display(None) => {
+            let monitors = monitors()?;
+            monitors.iter().find(|(primary, _)| *primary).or(monitors.first()).map(|(_, rect)| *rect)
+        }
+    }
+}
 
-            let mut converted = unsafe { Box::<[u8]>::new_uninit_slice(BUF_SIZE).assume_init() };
-            let size = if frame.width > frame.height { // need rotate
-                compressor.compress_to_slice(image, &mut compress_buffer)
-                    .expect("JPEG Encode Failed!");
+/// Whether `rect` can be what a frame of `size` shows, scap scales the frames to 720p.
+fn same_aspect(rect: Rect, (width, height): (usize, usize)) -> bool {
+    let frame = width as f64 / height as f64;
+    ((rect.width / rect.height) / frame - 1.0).abs() <= ASPECT_TOLERANCE
+}
 
-                let transform = turbojpeg::Transform::op(turbojpeg::TransformOp::Rot270);
-                // transform.optimize = true;
-                transformer.transform_to_slice(&transform, &compress_buffer, &mut converted[4..])
-                    .expect("JPEG Rotate Failed!")
-            } else {
-                compressor.compress_to_slice(image, &mut converted[4..])
-                    .expect("JPEG Encode Failed!")
-            };
+/// Whether each monitor is the primary one and its rect on the X screen, from `xrandr --listmonitors`.
+fn monitors() -> Option<Vec<(bool, Rect)>> {
+    let output = Command::new("xrandr").arg("--listmonitors").output().ok().filter(|o| o.status.success())?;
+    Some(parse_monitors(&String::from_utf8_lossy(&output.stdout)))
+}
 
-            let size = size + 4;
-            let bytes = (size as u32).to_le_bytes();
-            converted[..4].copy_from_slice(&bytes);
-            let data = FrameConvertedData { data: converted, data_size: size, quality: JPEG_QUALITY_LEVELS[quality_level], fps: frame.fps };
-            let _ = conv_tx_jpeg.try_send(data);
-
-            let tx_speed = (size as f64) / last.elapsed().as_secs_f64();
-            if quality_level > 0 && tx_speed > 7e6 {
-                quality_level -= 1;
-                compressor.set_quality(JPEG_QUALITY_LEVELS[quality_level]).expect("set jpeg quality failed!");
-            } else if quality_level + 1 < JPEG_QUALITY_LEVELS.len() && tx_speed < 4e6 {
-                quality_level += 1;
-                compressor.set_quality(JPEG_QUALITY_LEVELS[quality_level]).expect("set jpeg quality failed!");
-            }
-            last = std::time::Instant::now();
-        }
+// ` 1: +*DP-1 2560/597x1440/336+1920+0  DP-1`, width and height come with the size in millimetres
+fn parse_monitors(text: &str) -> Vec<(bool, Rect)> {
+    static LINE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let line = LINE.get_or_init(|| {
+        regex::Regex::new(r"^\s*\d+: \+?(\*?)\S+ (\d+)/\d+x(\d+)/\d+\+(\d+)\+(\d+)").unwrap()
     });
+    text.lines().filter_map(|l| {
+        let c = line.captures(l)?;
+        let number = |i: usize| c[i].parse::<f64>().ok();
+        Some((!c[1].is_empty(), Rect::new(number(4)?, number(5)?, number(2)?, number(3)?)))
+    }).collect()
+}
 
-    tx_thread(Context { rx: conv_rx });
+/// A named X window, its id stays the same while the title changes.
+#[derive(Debug, PartialEq)]
+struct XWindow {
+    id: u32,
+    title: String,
+    rect: Rect,
 }
 
-impl Context {
-    pub fn get_frame(&self) -> FrameConvertedData {
-        self.rx.recv().expect("Recv capture frame failed!")
-    }
+/// Whether a matching window is open, `None` when windows can not be listed.
+fn window_listed(selector: &WindowSelector) -> Option<bool> {
+    Some(windows()?.iter().any(|w| window_matches(selector, w)))
 }
 
-fn capture_target(index: Option<usize>) -> Option<scap::Target> {
-    if let Some(index) = index {
-        let mut current_index = 0;
-        for target in scap::get_all_targets() {
-            if let scap::Target::Display(d) = target {
-                if current_index == index { return Some(scap::Target::Display(d)); }
-                current_index += 1;
-            }
-        }
+/// Rect of the captured window, found by `selector` the first time and by its X id after that.
+/// `None` when windows can not be listed, `Some(None)` when the window is gone.
+fn track_window(selector: &WindowSelector, id: &mut Option<u32>) -> Option<Option<Rect>> {
+    let windows = windows()?;
+    let found = match *id {
+        Some(id) => windows.into_iter().find(|w| w.id == id),
+        None => windows.into_iter().find(|w| window_matches(selector, w)),
+    };
+    *id = found.as_ref().map(|w| w.id);
+    Some(found.map(|w| w.rect))
+}
+
+fn window_matches(selector: &WindowSelector, window: &XWindow) -> bool {
+    let pid = match selector {
+        WindowSelector::Pid(_) => window_pid(window.id),
+        WindowSelector::Title(_) => None,
+    };
+    selector.matches(&window.title, pid)
+}
+
+/// Process owning the window, from `xprop _NET_WM_PID`.
+fn window_pid(id: u32) -> Option<u32> {
+    let output = Command::new("xprop").args(["-id", &format!("{:#x}", id), "_NET_WM_PID"]).output().ok().filter(|o| o.status.success())?;
+    // `_NET_WM_PID(CARDINAL) = 1234`
+    String::from_utf8_lossy(&output.stdout).split_once(" = ")?.1.trim().parse().ok()
+}
+
+/// Ids, titles and screen rects of the named X windows, from `xwininfo -root -tree`.
+fn windows() -> Option<Vec<XWindow>> {
+    let output = Command::new("xwininfo").args(["-root", "-tree"]).output().ok().filter(|o| o.status.success())?;
+    Some(parse_windows(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// `0x3e00007 "Title": ("class" "Class")  800x600+0+0  +100+50`, the last pair is the position on the screen
+fn parse_windows(text: &str) -> Vec<XWindow> {
+    static LINE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let line = LINE.get_or_init(|| {
+        regex::Regex::new(r#"^\s*0x([0-9a-f]+) "(.*)": .*\s(\d+)x(\d+)[+-]-?\d+[+-]-?\d+\s+([+-]-?\d+)([+-]-?\d+)\s*$"#).unwrap()
+    });
+    text.lines().filter_map(|l| {
+        let c = line.captures(l)?;
+        let number = |i: usize| c[i].trim_start_matches('+').parse::<f64>().ok();
+        let rect = Rect::new(number(5)?, number(6)?, number(3)?, number(4)?);
+        let id = u32::from_str_radix(&c[1], 16).ok()?;
+        // Unmapped and minimised windows are 1x1
+        (rect.width > 1.0 && rect.height > 1.0).then(|| XWindow { id, title: c[2].to_string(), rect })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_from_xwininfo() {
+        let text = r#"
+xwininfo: Window id: 0x6a3 (the root window) (has no name)
+
+  Root window id: 0x6a3 (the root window) (has no name)
+  Parent window id: 0x0 (none)
+     3 children:
+     0x1e00007 "Terminal": ("gnome-terminal-server" "Gnome-terminal")  1200x800+0+0  +1930+50
+     0x2a00001 "Editor - notes.txt": ("editor" "Editor")  640x480+-8+-31  +-8+-31
+     0x2c00003 "Hidden": ("hidden" "Hidden")  1x1+0+0  +0+0
+     0x2e00004 (has no name): ()  10x10+0+0  +0+0
+"#;
+        assert_eq!(parse_windows(text), vec![
+            XWindow { id: 0x1e00007, title: "Terminal".to_string(), rect: Rect::new(1930.0, 50.0, 1200.0, 800.0) },
+            XWindow { id: 0x2a00001, title: "Editor - notes.txt".to_string(), rect: Rect::new(-8.0, -31.0, 640.0, 480.0) },
+        ]);
+    }
+
+    #[test]
+    fn desktop_rect_checked_against_the_frame() {
+        assert!(same_aspect(Rect::new(1920.0, 0.0, 2560.0, 1440.0), (1280, 720)));
+        assert!(same_aspect(Rect::new(0.0, 0.0, 1366.0, 768.0), (1280, 720)));
+        assert!(!same_aspect(Rect::new(0.0, 0.0, 1920.0, 1200.0), (1280, 720)));
+        assert!(!same_aspect(Rect::new(0.0, 0.0, 2560.0, 1440.0), (720, 1280)));
+    }
+
+    #[test]
+    fn monitors_from_xrandr() {
+        let text = "Monitors: 2\n 0: +HDMI-1 1920/527x1080/296+0+0  HDMI-1\n 1: +*DP-1 2560/597x1440/336+1920+0  DP-1\n";
+        assert_eq!(parse_monitors(text), vec![
+            (false, Rect::new(0.0, 0.0, 1920.0, 1080.0)),
+            (true, Rect::new(1920.0, 0.0, 2560.0, 1440.0)),
+        ]);
     }
-    None
 }
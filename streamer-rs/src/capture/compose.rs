@@ -0,0 +1,104 @@
+use crate::capture::{CaptureSource, FrameCaptureData, fit::Rect};
+use std::{str::FromStr, time::Duration};
+
+/// Second source scaled into a rectangle of the output canvas.
+#[derive(Clone, Debug)]
+pub struct Pip {
+    pub source: CaptureSource,
+    pub rect: Rect,
+}
+
+impl FromStr for Pip {
+    type Err = String;
+
+    /// `SOURCE@x,y,width,height`, SOURCE as in `CaptureSource`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, rect) = s.rsplit_once('@').ok_or(format!("expected SOURCE@x,y,width,height: {}", s))?;
+        Ok(Pip { source: source.parse()?, rect: rect.parse()? })
+    }
+}
+
+impl Pip {
+    pub fn size(&self) -> (usize, usize) {
+        (self.rect.width.round() as usize, self.rect.height.round() as usize)
+    }
+}
+
+/// Stage between resize and encode that builds the canvas out of one or more sources.
+pub trait Compose: Send {
+    /// Takes a frame of `source` and returns the canvas to encode, if there is one yet.
+    fn update(&mut self, source: usize, frame: FrameCaptureData) -> Option<FrameCaptureData>;
+
+    /// How long the canvas may go without a new frame before `tick` is called, never by default.
+    fn interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Canvas to encode when no source sent a frame for `interval`.
+    fn tick(&mut self) -> Option<FrameCaptureData> {
+        None
+    }
+}
+
+/// Combines the latest frame of every source, each one arrives at its own rate.
+pub struct Composer {
+    pips: Vec<Pip>,
+    main: Option<FrameCaptureData>,
+    tiles: Vec<Option<FrameCaptureData>>,
+}
+
+impl Composer {
+    pub fn new(pips: Vec<Pip>) -> Self {
+        let tiles = pips.iter().map(|_| None).collect();
+        Composer { pips, main: None, tiles }
+    }
+}
+
+impl Compose for Composer {
+    /// Source 0 is the main one, then the pips in order. Nothing is shown until the main
+    /// source delivered its first frame.
+    fn update(&mut self, source: usize, frame: FrameCaptureData) -> Option<FrameCaptureData> {
+        if self.pips.is_empty() {
+            return Some(frame);
+        }
+        let captured = frame.captured;
+        if source == 0 {
+            self.main = Some(frame);
+        } else {
+            self.tiles[source - 1] = Some(frame);
+        }
+
+        let main = self.main.as_mut()?;
+        let mut canvas = FrameCaptureData {
+            data: main.data.clone(),
+            width: main.width,
+            height: main.height,
+            pixel_format: main.pixel_format,
+            desktop: main.desktop,
+            // Reported once per second by the main source only
+            fps: if source == 0 { main.fps.take() } else { None },
+            captured,
+        };
+        for (pip, tile) in self.pips.iter().zip(&self.tiles) {
+            if let Some(tile) = tile {
+                paste(&mut canvas, tile, (pip.rect.x.round() as isize, pip.rect.y.round() as isize));
+            }
+        }
+        Some(canvas)
+    }
+}
+
+/// Copies `tile` onto the canvas at `(x, y)`, clipped to the canvas.
+pub fn paste(canvas: &mut FrameCaptureData, tile: &FrameCaptureData, (x, y): (isize, isize)) {
+    let left = x.max(0) as usize;
+    let right = ((x + tile.width as isize).max(0) as usize).min(canvas.width);
+    if left >= right { return }
+    for ty in 0..tile.height {
+        let cy = y + ty as isize;
+        if cy < 0 || cy >= canvas.height as isize { continue }
+        let src = (ty * tile.width + (left as isize - x) as usize) * 4;
+        let dst = (cy as usize * canvas.width + left) * 4;
+        let len = (right - left) * 4;
+        canvas.data[dst..dst + len].copy_from_slice(&tile.data[src..src + len]);
+    }
+}
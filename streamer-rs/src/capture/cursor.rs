@@ -0,0 +1,179 @@
+use crate::capture::{FrameCaptureData, Options, color, compose::Compose, fit::{Mapping, Rect}, pointer};
+use std::time::{Duration, Instant};
+
+// Arrow outline with the tip at the origin, 18 units tall
+const ARROW: [(f64, f64); 7] = [(0.0, 0.0), (0.0, 16.0), (4.0, 12.5), (7.0, 18.0), (9.5, 17.0), (6.5, 11.5), (11.5, 11.5)];
+const ARROW_HEIGHT: f64 = 18.0;
+const OUTLINE: f64 = 1.2;
+const RIPPLE_DURATION: Duration = Duration::from_millis(400);
+const HIGHLIGHT_COLOR: [u8; 3] = [255, 220, 0];
+const REFRESH_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Draws the pointer after resize, so it stays visible at any scale, plus click ripples.
+pub struct CursorRenderer {
+    size: f64,
+    highlight: bool,
+    pressed: bool,
+    /// Click positions in desktop coordinates
+    ripples: Vec<((f64, f64), Instant)>,
+}
+
+impl CursorRenderer {
+    pub fn new(options: &Options) -> Option<Self> {
+        options.cursor.then(|| CursorRenderer {
+            size: options.cursor_size,
+            highlight: options.click_highlight,
+            pressed: false,
+            ripples: Vec::new(),
+        })
+    }
+
+    pub fn apply(&mut self, frame: &mut FrameCaptureData) {
+        let Some(position) = pointer::position() else { return };
+        let output = Rect::new(0.0, 0.0, frame.width as f64, frame.height as f64);
+        let mapping = Mapping { src: frame.desktop, dst: output };
+
+        if self.highlight {
+            let pressed = pointer::pressed();
+            if pressed && !self.pressed {
+                self.ripples.push((position, Instant::now()));
+            }
+            self.pressed = pressed;
+            self.ripples.retain(|(_, start)| start.elapsed() < RIPPLE_DURATION);
+
+            let radius = self.size * 0.6;
+            if pressed {
+                disc(frame, mapping.to_output(position), radius, 0.0, 0.35);
+            }
+            for (click, start) in &self.ripples {
+                let t = start.elapsed().as_secs_f64() / RIPPLE_DURATION.as_secs_f64();
+                disc(frame, mapping.to_output(*click), radius * (1.0 + 2.0 * t), self.size / 8.0, 0.8 * (1.0 - t));
+            }
+        }
+
+        if output.contains(mapping.to_output(position)) {
+            arrow(frame, mapping.to_output(position), self.size / ARROW_HEIGHT);
+        }
+    }
+}
+
+/// Sends the last canvas again while the pointer moves, so the drawn cursor follows it on a static screen.
+pub struct CursorRefresh {
+    composer: Box<dyn Compose>,
+    last: Option<FrameCaptureData>,
+    /// When the wrapped composer last made a canvas, its own ticks keep their interval
+    composed: Instant,
+    pointer: Option<((f64, f64), bool)>,
+    /// Last pointer move or click, ripples keep animating for a while after it
+    moved: Instant,
+}
+
+impl CursorRefresh {
+    pub fn new(composer: Box<dyn Compose>) -> Self {
+        CursorRefresh { composer, last: None, composed: Instant::now(), pointer: None, moved: Instant::now() }
+    }
+
+    fn keep(&mut self, canvas: Option<FrameCaptureData>) -> Option<FrameCaptureData> {
+        if let Some(canvas) = &canvas {
+            self.composed = Instant::now();
+            self.last = Some(canvas.clone());
+        }
+        canvas
+    }
+}
+
+impl Compose for CursorRefresh {
+    fn update(&mut self, source: usize, frame: FrameCaptureData) -> Option<FrameCaptureData> {
+        let canvas = self.composer.update(source, frame);
+        self.keep(canvas)
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.composer.interval().map_or(REFRESH_INTERVAL, |interval| interval.min(REFRESH_INTERVAL)))
+    }
+
+    fn tick(&mut self) -> Option<FrameCaptureData> {
+        if self.composer.interval().is_some_and(|interval| self.composed.elapsed() >= interval) {
+            let canvas = self.composer.tick();
+            return self.keep(canvas);
+        }
+        let pointer = pointer::position().map(|position| (position, pointer::pressed()));
+        if pointer != self.pointer {
+            self.pointer = pointer;
+            self.moved = Instant::now();
+        }
+        if self.moved.elapsed() > RIPPLE_DURATION { return None }
+        let last = self.last.as_ref()?;
+        Some(FrameCaptureData { fps: None, captured: Instant::now(), ..last.clone() })
+    }
+}
+
+/// Translucent highlight circle, a ring of `thickness` when it is not zero.
+fn disc(frame: &mut FrameCaptureData, (cx, cy): (f64, f64), radius: f64, thickness: f64, opacity: f64) {
+    let outer = radius + thickness / 2.0;
+    paint(frame, (cx - outer, cy - outer, cx + outer, cy + outer), |(x, y)| {
+        let distance = ((x - cx).powi(2) + (y - cy).powi(2)).sqrt();
+        let inside = if thickness > 0.0 { (distance - radius).abs() <= thickness / 2.0 } else { distance <= radius };
+        inside.then_some((HIGHLIGHT_COLOR, opacity))
+    });
+}
+
+/// White arrow with a black outline, tip at `(x, y)`.
+fn arrow(frame: &mut FrameCaptureData, (x, y): (f64, f64), scale: f64) {
+    let points = ARROW.map(|(px, py)| (x + px * scale, y + py * scale));
+    let (right, bottom) = points.iter().fold((x, y), |(r, b), (px, py)| (r.max(*px), b.max(*py)));
+    let outline = (OUTLINE * scale).max(1.0);
+    paint(frame, (x - outline, y - outline, right + outline, bottom + outline), |p| {
+        let edge = edge_distance(&points, p);
+        if inside(&points, p) {
+            Some(if edge < outline { ([0, 0, 0], 1.0) } else { ([255, 255, 255], 1.0) })
+        } else {
+            (edge < outline / 2.0).then_some(([0, 0, 0], 1.0))
+        }
+    });
+}
+
+/// Blends `shape` over the pixels of a bounding box, sampled 2x2 per pixel for smooth edges.
+fn paint(frame: &mut FrameCaptureData, (left, top, right, bottom): (f64, f64, f64, f64), shape: impl Fn((f64, f64)) -> Option<([u8; 3], f64)>) {
+    let channels = color::channels(frame.pixel_format);
+    let clamp = |v: f64, max: usize| (v.max(0.0) as usize).min(max);
+    for py in clamp(top.floor(), frame.height)..clamp(bottom.ceil(), frame.height) {
+        for px in clamp(left.floor(), frame.width)..clamp(right.ceil(), frame.width) {
+            let mut sum = [0.0; 3];
+            let mut coverage = 0.0;
+            for (sx, sy) in [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+                if let Some((rgb, alpha)) = shape((px as f64 + sx, py as f64 + sy)) {
+                    sum.iter_mut().zip(rgb).for_each(|(s, v)| *s += v as f64 * alpha / 4.0);
+                    coverage += alpha / 4.0;
+                }
+            }
+            if coverage <= 0.0 { continue }
+            let p = (py * frame.width + px) * 4;
+            for (channel, value) in channels.iter().zip(sum) {
+                let dst = &mut frame.data[p + channel];
+                *dst = (*dst as f64 * (1.0 - coverage) + value).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+// Even-odd rule
+fn inside(points: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    for (i, &(x1, y1)) in points.iter().enumerate() {
+        let (x2, y2) = points[(i + 1) % points.len()];
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn edge_distance(points: &[(f64, f64)], (x, y): (f64, f64)) -> f64 {
+    (0..points.len()).map(|i| {
+        let ((x1, y1), (x2, y2)) = (points[i], points[(i + 1) % points.len()]);
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let t = (((x - x1) * dx + (y - y1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+        ((x - x1 - t * dx).powi(2) + (y - y1 - t * dy).powi(2)).sqrt()
+    }).fold(f64::MAX, f64::min)
+}
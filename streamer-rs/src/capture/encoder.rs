@@ -0,0 +1,134 @@
+use crate::capture::FrameConvertedData;
+use clap::ValueEnum;
+
+const BUF_SIZE: usize = 512 * 1024;
+const JPEG_QUALITY_LEVELS: [i32; 4] = [40, 60, 70, 80];
+const AUTO_LEVELS: [(Subsampling, i32); 6] = [
+    (Subsampling::Sub420, 40),
+    (Subsampling::Sub420, 60),
+    (Subsampling::Sub420, 70),
+    (Subsampling::Sub420, 80),
+    (Subsampling::Sub422, 80),
+    (Subsampling::Sub444, 80),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Subsampling {
+    /// 4:2:0, switching up to 4:2:2 / 4:4:4 when bandwidth allows
+    Auto,
+    #[value(name = "420")]
+    Sub420,
+    #[value(name = "422")]
+    Sub422,
+    #[value(name = "444")]
+    Sub444,
+    Gray,
+}
+
+impl Subsampling {
+    pub const ALL: [Subsampling; 4] = [Subsampling::Sub420, Subsampling::Sub422, Subsampling::Sub444, Subsampling::Gray];
+
+    // The device receives landscape frames rotated by a lossless transform, which also
+    // transposes the chroma planes. 4:2:2 is encoded as 4:4:0 so that it arrives as 4:2:2.
+    fn subsamp(self, rotate: bool) -> turbojpeg::Subsamp {
+        match self {
+            Subsampling::Sub420 | Subsampling::Auto => turbojpeg::Subsamp::Sub2x2,
+            Subsampling::Sub422 if rotate => turbojpeg::Subsamp::Sub1x2,
+            Subsampling::Sub422 => turbojpeg::Subsamp::Sub2x1,
+            Subsampling::Sub444 => turbojpeg::Subsamp::None,
+            Subsampling::Gray => turbojpeg::Subsamp::Gray,
+        }
+    }
+}
+
+impl std::fmt::Display for Subsampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Subsampling::Auto => "auto",
+            Subsampling::Sub420 => "4:2:0",
+            Subsampling::Sub422 => "4:2:2",
+            Subsampling::Sub444 => "4:4:4",
+            Subsampling::Gray => "gray",
+        };
+        f.write_str(name)
+    }
+}
+
+pub struct Encoder {
+    compressor: turbojpeg::Compressor,
+    transformer: turbojpeg::Transformer,
+    compress_buffer: Box<[u8]>,
+    levels: Vec<(Subsampling, i32)>,
+    level: usize,
+    subsamp: Option<turbojpeg::Subsamp>,
+    last: std::time::Instant,
+}
+
+impl Encoder {
+    pub fn new(subsampling: Subsampling, supported: &[Subsampling]) -> Self {
+        let mut levels: Vec<(Subsampling, i32)> = if subsampling == Subsampling::Auto {
+            AUTO_LEVELS.into_iter().filter(|(s, _)| supported.contains(s)).collect()
+        } else {
+            JPEG_QUALITY_LEVELS.into_iter().map(|q| (subsampling, q)).collect()
+        };
+        // Every tablet decodes 4:2:0, even when its firmware lists none of the auto levels
+        if levels.is_empty() {
+            levels = AUTO_LEVELS.into_iter().filter(|(s, _)| *s == Subsampling::Sub420).collect();
+        }
+
+        let mut compressor = turbojpeg::Compressor::new().expect("Failed to create turbojpeg Compressor");
+        let transformer = turbojpeg::Transformer::new().expect("Failed to create turbojpeg Transformer");
+        compressor.set_quality(levels[0].1).expect("set jpeg quality failed!");
+        compressor.set_optimize(false).expect("set jpeg optimize failed!");
+
+        Encoder {
+            compressor,
+            transformer,
+            compress_buffer: vec![0; BUF_SIZE].into_boxed_slice(),
+            levels,
+            level: 0,
+            subsamp: None,
+            last: std::time::Instant::now(),
+        }
+    }
+
+    pub fn encode(&mut self, image: turbojpeg::Image<&[u8]>, fps: Option<usize>, captured: std::time::Instant) -> FrameConvertedData {
+        let rotate = image.width > image.height;
+        let (subsampling, quality) = self.levels[self.level];
+        let subsamp = subsampling.subsamp(rotate);
+        if self.subsamp != Some(subsamp) {
+            self.compressor.set_subsamp(subsamp).expect("set jpeg subsamp failed!");
+            self.subsamp = Some(subsamp);
+        }
+
+        let mut converted = unsafe { Box::<[u8]>::new_uninit_slice(BUF_SIZE).assume_init() };
+        let size = if rotate {
+            self.compressor.compress_to_slice(image, &mut self.compress_buffer)
+                .expect("JPEG Encode Failed!");
+
+            let transform = turbojpeg::Transform::op(turbojpeg::TransformOp::Rot270);
+            // transform.optimize = true;
+            self.transformer.transform_to_slice(&transform, &self.compress_buffer, &mut converted[4..])
+                .expect("JPEG Rotate Failed!")
+        } else {
+            self.compressor.compress_to_slice(image, &mut converted[4..])
+                .expect("JPEG Encode Failed!")
+        };
+
+        let size = size + 4;
+        let bytes = (size as u32).to_le_bytes();
+        converted[..4].copy_from_slice(&bytes);
+        let data = FrameConvertedData { data: converted, data_size: size, quality, subsampling, fps, captured };
+
+        let tx_speed = (size as f64) / self.last.elapsed().as_secs_f64();
+        if self.level > 0 && tx_speed > 7e6 {
+            self.level -= 1;
+            self.compressor.set_quality(self.levels[self.level].1).expect("set jpeg quality failed!");
+        } else if self.level + 1 < self.levels.len() && tx_speed < 4e6 {
+            self.level += 1;
+            self.compressor.set_quality(self.levels[self.level].1).expect("set jpeg quality failed!");
+        }
+        self.last = std::time::Instant::now();
+        data
+    }
+}
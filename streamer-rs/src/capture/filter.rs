@@ -0,0 +1,151 @@
+use crate::capture::{FrameCaptureData, Options, color};
+
+const NEUTRAL_TEMPERATURE: f32 = 6500.0;
+
+/// In-place pixel adjustment on a resized frame, before it is encoded.
+pub trait FrameFilter: Send {
+    fn apply(&mut self, frame: &mut FrameCaptureData);
+}
+
+/// Filters run in order, each one on the output of the previous.
+pub struct FilterChain {
+    filters: Vec<Box<dyn FrameFilter>>,
+}
+
+impl FilterChain {
+    pub fn new(options: &Options) -> Self {
+        let mut chain = FilterChain { filters: Vec::new() };
+        if options.brightness != 0.0 || options.contrast != 1.0 || options.gamma != 1.0 {
+            chain.push(Levels::new(options.brightness, options.contrast, options.gamma));
+        }
+        if options.temperature != NEUTRAL_TEMPERATURE {
+            chain.push(Temperature::new(options.temperature));
+        }
+        if options.grayscale {
+            chain.push(Grayscale);
+        }
+        if options.invert {
+            chain.push(Invert);
+        }
+        if options.sharpen > 0.0 {
+            chain.push(Sharpen(options.sharpen));
+        }
+        chain
+    }
+
+    pub fn push(&mut self, filter: impl FrameFilter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn apply(&mut self, frame: &mut FrameCaptureData) {
+        for filter in &mut self.filters {
+            filter.apply(frame);
+        }
+    }
+}
+
+/// Brightness (-1 to 1), contrast (1 = unchanged) and gamma (1 = unchanged) folded into one lookup table.
+pub struct Levels {
+    table: [u8; 256],
+}
+
+impl Levels {
+    pub fn new(brightness: f32, contrast: f32, gamma: f32) -> Self {
+        let table = std::array::from_fn(|v| {
+            let v = v as f32 / 255.0;
+            let v = (v - 0.5) * contrast + 0.5 + brightness;
+            let v = v.clamp(0.0, 1.0).powf(1.0 / gamma);
+            (v * 255.0).round() as u8
+        });
+        Levels { table }
+    }
+}
+
+impl FrameFilter for Levels {
+    fn apply(&mut self, frame: &mut FrameCaptureData) {
+        for pixel in frame.data.chunks_exact_mut(4) {
+            pixel[..3].iter_mut().for_each(|v| *v = self.table[*v as usize]);
+        }
+    }
+}
+
+/// White point shift to a colour temperature in Kelvin, 6500 is neutral.
+pub struct Temperature {
+    gains: [f32; 3],
+}
+
+impl Temperature {
+    pub fn new(kelvin: f32) -> Self {
+        let (target, neutral) = (blackbody(kelvin), blackbody(NEUTRAL_TEMPERATURE));
+        Temperature { gains: std::array::from_fn(|i| target[i] / neutral[i]) }
+    }
+}
+
+impl FrameFilter for Temperature {
+    fn apply(&mut self, frame: &mut FrameCaptureData) {
+        let channels = color::channels(frame.pixel_format);
+        let tables: [[u8; 256]; 3] = self.gains.map(|gain| {
+            std::array::from_fn(|v| (v as f32 * gain).clamp(0.0, 255.0) as u8)
+        });
+        for pixel in frame.data.chunks_exact_mut(4) {
+            for (channel, table) in channels.iter().zip(&tables) {
+                pixel[*channel] = table[pixel[*channel] as usize];
+            }
+        }
+    }
+}
+
+// Approximate RGB of a black body (Tanner Helland's fit), 0-255 per channel
+fn blackbody(kelvin: f32) -> [f32; 3] {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.69873 * (t - 60.0).powf(-0.13320476) };
+    let g = if t <= 66.0 { 99.4708 * t.ln() - 161.11957 } else { 288.12216 * (t - 60.0).powf(-0.075514846) };
+    let b = if t >= 66.0 { 255.0 } else if t <= 19.0 { 0.0 } else { 138.51773 * (t - 10.0).ln() - 305.0448 };
+    [r, g, b].map(|v| v.clamp(0.0, 255.0))
+}
+
+/// Rec. 601 luma, the same weights the JPEG encoder uses.
+pub struct Grayscale;
+
+impl FrameFilter for Grayscale {
+    fn apply(&mut self, frame: &mut FrameCaptureData) {
+        let [r, g, b] = color::channels(frame.pixel_format);
+        for pixel in frame.data.chunks_exact_mut(4) {
+            let luma = (pixel[r] as u32 * 299 + pixel[g] as u32 * 587 + pixel[b] as u32 * 114) / 1000;
+            pixel[..3].fill(luma as u8);
+        }
+    }
+}
+
+/// Night mode, dark backgrounds stay dark on the panel.
+pub struct Invert;
+
+impl FrameFilter for Invert {
+    fn apply(&mut self, frame: &mut FrameCaptureData) {
+        for pixel in frame.data.chunks_exact_mut(4) {
+            pixel[..3].iter_mut().for_each(|v| *v = 255 - *v);
+        }
+    }
+}
+
+/// 3x3 unsharp mask on the colour channels, alpha is kept.
+pub struct Sharpen(pub f32);
+
+impl FrameFilter for Sharpen {
+    fn apply(&mut self, frame: &mut FrameCaptureData) {
+        let (data, width, height, amount) = (&mut frame.data, frame.width, frame.height, self.0);
+        let stride = width * 4;
+        let source = data.to_vec();
+        for y in 1..height.saturating_sub(1) {
+            for x in 1..width - 1 {
+                let i = y * stride + x * 4;
+                for c in i..i + 3 {
+                    let neighbours = source[c - 4] as f32 + source[c + 4] as f32
+                        + source[c - stride] as f32 + source[c + stride] as f32;
+                    let value = source[c] as f32 * (1.0 + 4.0 * amount) - neighbours * amount;
+                    data[c] = value.clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
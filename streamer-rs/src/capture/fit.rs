@@ -0,0 +1,131 @@
+use std::str::FromStr;
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FitMode {
+    /// Keep aspect ratio, fill the remaining area with the background color
+    Letterbox,
+    /// Keep aspect ratio, cut off what does not fit around the centre
+    Crop,
+    Stretch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn contains(&self, (x, y): (f64, f64)) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Rect::new(x, y, right - x, bottom - y))
+    }
+
+    /// `self` in pixels of a `size` frame showing `desktop`, converted to desktop coordinates.
+    pub fn to_desktop(self, desktop: &Rect, size: (usize, usize)) -> Rect {
+        let (sx, sy) = (desktop.width / size.0 as f64, desktop.height / size.1 as f64);
+        Rect::new(desktop.x + self.x * sx, desktop.y + self.y * sy, self.width * sx, self.height * sy)
+    }
+
+    /// Largest size with the same aspect ratio that fits in `bounds`.
+    pub fn fit_size(&self, bounds: (usize, usize)) -> (usize, usize) {
+        let scale = (bounds.0 as f64 / self.width).min(bounds.1 as f64 / self.height);
+        ((self.width * scale).round() as usize, (self.height * scale).round() as usize)
+    }
+}
+
+impl FromStr for Rect {
+    type Err = String;
+
+    /// `x,y,width,height`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s.split(',')
+            .map(|v| v.trim().parse::<f64>().map_err(|_| format!("invalid number: {}", v)))
+            .collect::<Result<Vec<f64>, String>>()?;
+        match values[..] {
+            [x, y, width, height] if width > 0.0 && height > 0.0 => Ok(Rect::new(x, y, width, height)),
+            [_, _, _, _] => Err(format!("empty rectangle: {}", s)),
+            _ => Err(format!("expected x,y,width,height: {}", s)),
+        }
+    }
+}
+
+/// Placement of a source area on the output canvas.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mapping {
+    pub src: Rect,
+    pub dst: Rect,
+}
+
+impl Mapping {
+    pub fn new(fit: FitMode, src: Rect, output: (usize, usize)) -> Self {
+        let (out_width, out_height) = (output.0 as f64, output.1 as f64);
+        let full = Rect::new(0.0, 0.0, out_width, out_height);
+        match fit {
+            FitMode::Stretch => Mapping { src, dst: full },
+            FitMode::Letterbox => {
+                // Destination is integer aligned, it becomes a cropped view of the output image
+                let (width, height) = src.fit_size(output);
+                let (width, height) = ((width as f64).min(out_width), (height as f64).min(out_height));
+                let x = ((out_width - width) / 2.0).floor();
+                let y = ((out_height - height) / 2.0).floor();
+                Mapping { src, dst: Rect::new(x, y, width, height) }
+            }
+            FitMode::Crop => {
+                let scale = (out_width / src.width).max(out_height / src.height);
+                let width = out_width / scale;
+                let height = out_height / scale;
+                let x = src.x + (src.width - width) / 2.0;
+                let y = src.y + (src.height - height) / 2.0;
+                Mapping { src: Rect::new(x, y, width, height), dst: full }
+            }
+        }
+    }
+
+    pub fn identity(size: (usize, usize)) -> Self {
+        let rect = Rect::new(0.0, 0.0, size.0 as f64, size.1 as f64);
+        Mapping { src: rect, dst: rect }
+    }
+
+    /// Area the whole output canvas covers in source coordinates, letterbox bars included.
+    pub fn output_source(self, output: (usize, usize)) -> Rect {
+        let (sx, sy) = (self.src.width / self.dst.width, self.src.height / self.dst.height);
+        Rect::new(self.src.x - self.dst.x * sx, self.src.y - self.dst.y * sy, output.0 as f64 * sx, output.1 as f64 * sy)
+    }
+
+    /// Source (desktop) coordinates to output canvas coordinates.
+    pub fn to_output(self, (x, y): (f64, f64)) -> (f64, f64) {
+        (
+            self.dst.x + (x - self.src.x) * self.dst.width / self.src.width,
+            self.dst.y + (y - self.src.y) * self.dst.height / self.src.height,
+        )
+    }
+
+    /// Output canvas coordinates to source coordinates, `None` on the letterbox bars.
+    pub fn to_source(self, (x, y): (f64, f64)) -> Option<(f64, f64)> {
+        if !self.dst.contains((x, y)) {
+            return None;
+        }
+        Some((
+            self.src.x + (x - self.dst.x) * self.src.width / self.dst.width,
+            self.src.y + (y - self.dst.y) * self.src.height / self.dst.height,
+        ))
+    }
+}
@@ -0,0 +1,48 @@
+use crate::capture::{Color, FrameCaptureData, font};
+use std::time::Duration;
+
+const SCALE: usize = 2;
+const MARGIN: usize = 8;
+const PADDING: usize = 6;
+const LINE_SPACING: usize = 4;
+
+/// Stream health numbers, filled in by the pipeline and the USB thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub capture_fps: usize,
+    pub tx_fps: usize,
+    /// kB/s sent to the device
+    pub bitrate: usize,
+    pub quality: i32,
+    /// Capture to end of USB transfer
+    pub latency: Duration,
+}
+
+/// Draws the stats into the top left corner on a darkened box.
+pub fn draw(frame: &mut FrameCaptureData, stats: &Stats) {
+    let lines = [
+        format!("CAPTURE {}FPS", stats.capture_fps),
+        format!("TX {}FPS {}KB/S", stats.tx_fps, stats.bitrate),
+        format!("QUALITY {}", stats.quality),
+        format!("LATENCY {}MS", stats.latency.as_millis()),
+    ];
+    let line_height = font::HEIGHT * SCALE + LINE_SPACING;
+    let width = lines.iter().map(|l| font::text_size(l, SCALE).0).max().unwrap_or(0) + PADDING * 2;
+    let height = lines.len() * line_height - LINE_SPACING + PADDING * 2;
+    shade(frame, (MARGIN, MARGIN), (width, height));
+    for (i, line) in lines.iter().enumerate() {
+        let position = (MARGIN + PADDING, MARGIN + PADDING + i * line_height);
+        font::draw_text(frame, position, SCALE, line, Color { r: 255, g: 255, b: 255 });
+    }
+}
+
+// Halves the colour channels, so the HUD stays readable on any content
+fn shade(frame: &mut FrameCaptureData, (x, y): (usize, usize), (width, height): (usize, usize)) {
+    let right = (x + width).min(frame.width);
+    for row in y..(y + height).min(frame.height) {
+        let line = &mut frame.data[(row * frame.width + x.min(right)) * 4..(row * frame.width + right) * 4];
+        for pixel in line.chunks_exact_mut(4) {
+            pixel[..3].iter_mut().for_each(|v| *v /= 2);
+        }
+    }
+}
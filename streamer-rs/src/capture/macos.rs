@@ -1,9 +1,9 @@
-use crate::capture::FrameConvertedData;
-use std::{os::raw::c_void, sync::mpsc, thread, time::Duration};
+use crate::capture::{CaptureSource, FrameCaptureData, Context, Options, WindowSelector, fit::Rect, pipeline};
+use std::{os::raw::c_void, sync::{mpsc, Mutex}, thread, time::Duration};
 use core_foundation::{error::CFError, runloop::CFRunLoopRun};
 use core_media_rs::{cm_time::CMTime, cm_sample_buffer::CMSampleBuffer};
 use screencapturekit::{
-    output::LockTrait, shareable_content::SCShareableContent, stream::{
+    output::LockTrait, shareable_content::{SCShareableContent, SCWindow}, stream::{
         configuration::{pixel_format::PixelFormat, SCStreamConfiguration},
         content_filter::SCContentFilter,
         output_trait::SCStreamOutputTrait,
@@ -16,178 +16,213 @@ use objc2::runtime::AnyObject;
 use objc2::rc::Retained;
 use objc2_foundation::{NSString, NSArray};
 use objc2_core_foundation::CGSize;
-use objc2_core_graphics::{CGDirectDisplayID, CGDisplayChangeSummaryFlags, CGDisplayIsInMirrorSet, CGDisplayMirrorsDisplay, CGDisplayRegisterReconfigurationCallback};
+use core_graphics::geometry::{CGPoint, CGRect, CGSize as CGRectSize};
+use objc2_core_graphics::{CGDirectDisplayID, CGDisplayBounds, CGDisplayChangeSummaryFlags, CGDisplayIsInMirrorSet, CGDisplayMirrorsDisplay, CGDisplayRegisterReconfigurationCallback};
 use objc2_app_kit::NSApplication;
 use dispatch2::DispatchQueue;
 
-const BUF_SIZE: usize = 512 * 1024;
-const JPEG_QUALITY_LEVELS: [i32; 4] = [40, 60, 70, 80];
-
-pub struct Context {
-    rx: mpsc::Receiver<FrameConvertedData>,
-}
-
-static mut DISPLAY_WATCH: Option<CGDirectDisplayID> = None;
-static mut DISPLAY_UPDATED: bool = false;
+// Displays being captured, flagged when their settings change
+static DISPLAY_UPDATED: Mutex<Vec<(CGDirectDisplayID, bool)>> = Mutex::new(Vec::new());
 unsafe extern "C-unwind" fn display_settings_changed(display: u32, _flags: CGDisplayChangeSummaryFlags, _user_info: *mut c_void) {
     // println!("Display Settings Changed: display={}, flags={:?}", display, flags);
-    if unsafe { DISPLAY_WATCH } == Some(display) {
-        unsafe { DISPLAY_UPDATED = true; }
+    for (watched, updated) in DISPLAY_UPDATED.lock().unwrap().iter_mut() {
+        if *watched == display { *updated = true; }
+    }
+}
+
+fn watch_display(display: CGDirectDisplayID) {
+    let mut displays = DISPLAY_UPDATED.lock().unwrap();
+    if !displays.iter().any(|(watched, _)| *watched == display) {
+        displays.push((display, false));
     }
 }
 
-pub fn start<F>(display_index: Option<usize>, tx_thread: F)
+fn display_updated(display: CGDirectDisplayID) -> bool {
+    let mut displays = DISPLAY_UPDATED.lock().unwrap();
+    displays.iter_mut().find(|(watched, _)| *watched == display)
+        .is_some_and(|(_, updated)| std::mem::replace(updated, false))
+}
+
+pub fn start<F>(options: Options, tx_thread: F)
 where
     F: FnOnce(Context) + Send + 'static,
 {
-    let (conv_tx, conv_rx) = mpsc::sync_channel::<FrameConvertedData>(1);
+    // ScreenCaptureKit crops the region itself and the resize stage only fits it to the panel,
+    // except when zooming, which needs the whole desktop in the resize stage
+    let resize_region = if options.zoom.is_some() { options.region } else { None };
+    let (senders, context) = pipeline::start(&Options { region: resize_region, ..options.clone() });
+
+    // Capture Threads, one per source
+    for ((source, options), sender) in options.sources().into_iter().zip(senders) {
+        thread::spawn(move || capture(source, options, sender));
+    }
 
-    // Capture Thread
     thread::spawn(move || {
-        let (capt_tx, capt_rx) = mpsc::sync_channel::<CMSampleBuffer>(1);
+        tx_thread(context);
+    });
 
-        let (display_id, _virtual_display) = if let Some(i) = display_index {
+    // Run Loop
+    unsafe {
+        let _ = CGDisplayRegisterReconfigurationCallback(Some(display_settings_changed), std::ptr::null_mut());
+        NSApplication::load();
+        CFRunLoopRun();
+    }
+}
+
+fn capture(source: CaptureSource, options: Options, sender: pipeline::Sender) {
+    let sck_region = if options.zoom.is_some() { None } else { options.region };
+    let (capt_tx, capt_rx) = mpsc::sync_channel::<CMSampleBuffer>(1);
+
+    let (display_id, _virtual_display) = match &source {
+        CaptureSource::Window(_) => (0, None),
+        CaptureSource::Display(Some(i)) => {
             let contents = SCShareableContent::get()
                 .expect("Failed to get display list.");
-            (contents.displays()[i].display_id(), None)
-        } else {
+            (contents.displays()[*i].display_id(), None)
+        }
+        CaptureSource::Display(None) => {
             let virtual_display = VirtualDisplay::new(
                 "M5Stack Tab5",
                 (1280, 720),
                 (110.0, 62.0)
             );
             (virtual_display.get_id(), Some(virtual_display))
-        };
-        let output = SCStreamOutput { tx: capt_tx };
-        let mut compressor = turbojpeg::Compressor::new().expect("Failed to create turbojpeg Compressor");
-        let mut transformer = turbojpeg::Transformer::new().expect("Failed to create turbojpeg Transformer");
-
-        let mut quality_level: usize = 0;
-        compressor.set_quality(JPEG_QUALITY_LEVELS[0]).expect("set jpeg quality failed!");
-        compressor.set_optimize(false).expect("set jpeg optimize failed!");
-        compressor.set_subsamp(turbojpeg::Subsamp::Sub2x2).expect("set jpeg subsamp failed!");
-
-        loop {
-            let stream = start_screen_capture_kit(output.clone(), display_id)
+        }
+    };
+    let output = SCStreamOutput { tx: capt_tx };
+
+    loop {
+        let excluded = find_windows(&options.exclude_windows);
+        let excluded_ids: Vec<u32> = excluded.iter().map(|w| w.window_id()).collect();
+        let (filter, area, window_size) = if let CaptureSource::Window(selector) = &source {
+            let Some(window) = find_window(selector) else {
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            };
+            let title: Option<String> = window.title().into();
+            println!("Capturing Window: {}", title.unwrap_or_default());
+            let frame = window.frame();
+            let area = Rect::new(frame.origin.x, frame.origin.y, frame.size.width, frame.size.height);
+            let filter = SCContentFilter::new().with_desktop_independent_window(&window);
+            (filter, area, Some((frame.size.width, frame.size.height)))
+        } else {
+            let (filter, _selected_display_id) = create_filter_from_display_id(display_id, &excluded)
                 .expect("Failed to start ScreenCaptureKit!");
-
-            let mut compress_buffer: [u8; BUF_SIZE] = [0; BUF_SIZE];
-            let mut frames = 0;
-            let mut start = std::time::Instant::now();
-            let mut last = std::time::Instant::now();
-            loop {
-                let sample_buffer = capt_rx.recv_timeout(Duration::from_millis(100));
-                if unsafe { DISPLAY_UPDATED } { break; }
-                let sample_buffer = match sample_buffer {
-                    Ok(sb) => sb,
-                    Err(_) => continue,
-                };
-
-                let pixel_buffer = if let Ok(pb) = sample_buffer.get_pixel_buffer() {
-                    pb
-                } else {
-                    continue
-                };
-
-                frames += 1;
-                let fps = if start.elapsed() >= Duration::from_secs(1) {
-                    let fps = Some(frames);
-                    frames = 0;
-                    start = std::time::Instant::now();
-                    fps
-                } else {
-                    None
-                };
-
-                let size = (pixel_buffer.get_width(), pixel_buffer.get_height());
-                let data = if let Ok(d) = pixel_buffer.lock() {
-                    d
-                } else {
-                    continue
-                };
-
-                let image = turbojpeg::Image {
-                    pixels: data.0.as_slice(),
-                    width: size.0 as usize,
-                    pitch: (size.0 as usize) * 4,
-                    height: size.1 as usize,
-                    format: turbojpeg::PixelFormat::BGRA,
-                };
-
-                let mut converted = unsafe { Box::<[u8]>::new_uninit_slice(BUF_SIZE).assume_init() };
-                let size = if size.0 > size.1 { // need rotate
-                    compressor.compress_to_slice(image, &mut compress_buffer)
-                        .expect("JPEG Encode Failed!");
-
-                    let transform = turbojpeg::Transform::op(turbojpeg::TransformOp::Rot270);
-                    // transform.optimize = true;
-                    transformer.transform_to_slice(&transform, &compress_buffer, &mut converted[4..])
-                        .expect("JPEG Rotate Failed!")
-                } else {
-                    compressor.compress_to_slice(image, &mut converted[4..])
-                        .expect("JPEG Encode Failed!")
-                };
-
-                let size = size + 4;
-                let bytes = (size as u32).to_le_bytes();
-                converted[..4].copy_from_slice(&bytes);
-                let data = FrameConvertedData { data: converted, data_size: size, quality: JPEG_QUALITY_LEVELS[quality_level], fps };
-                let _ = conv_tx.try_send(data);
-
-                let tx_speed = (size as f64) / last.elapsed().as_secs_f64();
-                if quality_level > 0 && tx_speed > 7e6 {
-                    quality_level -= 1;
-                    compressor.set_quality(JPEG_QUALITY_LEVELS[quality_level]).expect("set jpeg quality failed!");
-                } else if quality_level + 1 < JPEG_QUALITY_LEVELS.len() && tx_speed < 4e6 {
-                    quality_level += 1;
-                    compressor.set_quality(JPEG_QUALITY_LEVELS[quality_level]).expect("set jpeg quality failed!");
+            watch_display(display_id);
+            let bounds = CGDisplayBounds(display_id);
+            let area = Rect::new(bounds.origin.x, bounds.origin.y, bounds.size.width, bounds.size.height);
+            (filter, area, None)
+        };
+        // The region is in desktop coordinates, ScreenCaptureKit wants it relative to the captured area
+        let desktop = sck_region.and_then(|r| r.intersect(&area)).unwrap_or(area);
+        let source_rect = sck_region.map(|_| Rect::new(desktop.x - area.x, desktop.y - area.y, desktop.width, desktop.height));
+        let size = match desktop {
+            _ if options.zoom.is_some() => (desktop.width as usize, desktop.height as usize),
+            r if r.width > r.height => r.fit_size((1280, 720)),
+            r => r.fit_size((720, 1280)),
+        };
+        let stream = start_screen_capture_kit(output.clone(), filter, size, source_rect, !options.cursor)
+            .expect("Failed to start ScreenCaptureKit!");
+
+        let mut window_check = std::time::Instant::now();
+        let mut frames = 0;
+        let mut start = std::time::Instant::now();
+        let reason = loop {
+            let sample_buffer = capt_rx.recv_timeout(Duration::from_millis(100));
+            if display_updated(display_id) { break "Display Settings Changed"; }
+            if window_check.elapsed() >= Duration::from_secs(1) {
+                // Restart on close or resize so the stream follows the window size
+                if let CaptureSource::Window(selector) = &source {
+                    let size = find_window(selector).map(|w| (w.frame().size.width, w.frame().size.height));
+                    if size != window_size { break "Window Changed"; }
                 }
-                last = std::time::Instant::now();
+                // Restart when excluded windows open or close so the filter stays current
+                if window_size.is_none() && !options.exclude_windows.is_empty() {
+                    let ids: Vec<u32> = find_windows(&options.exclude_windows).iter().map(|w| w.window_id()).collect();
+                    if ids != excluded_ids { break "Excluded Windows Changed"; }
+                }
+                window_check = std::time::Instant::now();
             }
-            println!("Display Settings Changed, Reopening Stream...");
-            let _ = stream.stop_capture();
-            thread::sleep(Duration::from_millis(100));
-            unsafe { DISPLAY_UPDATED = false; }
-        }
-    });
-
-    thread::spawn(move || {
-        tx_thread(Context { rx: conv_rx });
-    });
-
-    // Run Loop
-    unsafe {
-        let _ = CGDisplayRegisterReconfigurationCallback(Some(display_settings_changed), std::ptr::null_mut());
-        NSApplication::load();
-        CFRunLoopRun();
+            let sample_buffer = match sample_buffer {
+                Ok(sb) => sb,
+                Err(_) => continue,
+            };
+
+            let pixel_buffer = if let Ok(pb) = sample_buffer.get_pixel_buffer() {
+                pb
+            } else {
+                continue
+            };
+
+            frames += 1;
+            let fps = if start.elapsed() >= Duration::from_secs(1) {
+                let fps = Some(frames);
+                frames = 0;
+                start = std::time::Instant::now();
+                fps
+            } else {
+                None
+            };
+
+            let size = (pixel_buffer.get_width(), pixel_buffer.get_height());
+            let stride = pixel_buffer.get_bytes_per_row() as usize;
+            let data = if let Ok(d) = pixel_buffer.lock() {
+                d
+            } else {
+                continue
+            };
+
+            // Window and region widths are arbitrary, so rows may be padded
+            let row = size.0 as usize * 4;
+            let data = if stride == row {
+                data.0.as_slice().to_vec()
+            } else {
+                data.0.as_slice().chunks(stride).flat_map(|r| &r[..row]).copied().collect()
+            };
+            sender.send(FrameCaptureData {
+                data,
+                width: size.0 as usize,
+                height: size.1 as usize,
+                pixel_format: turbojpeg::PixelFormat::BGRA,
+                desktop,
+                fps,
+                captured: std::time::Instant::now(),
+            });
+        };
+        println!("{}, Reopening Stream...", reason);
+        let _ = stream.stop_capture();
+        thread::sleep(Duration::from_millis(100));
     }
 }
 
-fn create_filter_from_display_id(display_id: CGDirectDisplayID) -> Result<(SCContentFilter, CGDirectDisplayID), CFError> {
+fn create_filter_from_display_id(display_id: CGDirectDisplayID, excluded: &[SCWindow]) -> Result<(SCContentFilter, CGDirectDisplayID), CFError> {
+    let excluded: Vec<&SCWindow> = excluded.iter().collect();
     for d in SCShareableContent::get()?.displays() {
         if d.display_id() == display_id {
-            return Ok((SCContentFilter::new().with_display_excluding_windows(&d, &[]), display_id));
+            return Ok((SCContentFilter::new().with_display_excluding_windows(&d, &excluded), display_id));
         }
     }
     if CGDisplayIsInMirrorSet(display_id) {
         let mirrored_id = CGDisplayMirrorsDisplay(display_id);
         for d in SCShareableContent::get()?.displays() {
             if d.display_id() == mirrored_id {
-                return Ok((SCContentFilter::new().with_display_excluding_windows(&d, &[]), mirrored_id));
+                return Ok((SCContentFilter::new().with_display_excluding_windows(&d, &excluded), mirrored_id));
             }
         }
     }
     panic!("Target Display not found in Shareable Content!");
 }
-fn start_screen_capture_kit(output: SCStreamOutput, display_id: CGDirectDisplayID) -> Result<SCStream, CFError> {
-    let (filter, _selected_display_id) = create_filter_from_display_id(display_id)?;
-    unsafe { DISPLAY_WATCH = Some(display_id) };
-
-    let config = SCStreamConfiguration::new()
-        .set_width(1280)?
-        .set_height(720)?
+fn start_screen_capture_kit(output: SCStreamOutput, filter: SCContentFilter, (width, height): (usize, usize), region: Option<Rect>, shows_cursor: bool) -> Result<SCStream, CFError> {
+    let mut config = SCStreamConfiguration::new()
+        .set_width(width as u32)?
+        .set_height(height as u32)?;
+    if let Some(r) = region {
+        config = config.set_source_rect(CGRect::new(&CGPoint::new(r.x, r.y), &CGRectSize::new(r.width, r.height)))?;
+    }
+    let config = config
         .set_minimum_frame_interval(&CMTime { value: 1, timescale: 60, flags: 0, epoch: 0 })?
         .set_pixel_format(PixelFormat::BGRA)?
+        .set_shows_cursor(shows_cursor)?
         .set_captures_audio(false)?;
 
     let mut stream = SCStream::new(&filter, &config);
@@ -196,6 +231,22 @@ fn start_screen_capture_kit(output: SCStreamOutput, display_id: CGDirectDisplayI
     Ok(stream)
 }
 
+fn find_window(selector: &WindowSelector) -> Option<SCWindow> {
+    SCShareableContent::get().ok()?.windows().into_iter().find(|w| window_matches(w, selector))
+}
+
+fn find_windows(selectors: &[WindowSelector]) -> Vec<SCWindow> {
+    if selectors.is_empty() { return Vec::new() }
+    let Ok(content) = SCShareableContent::get() else { return Vec::new() };
+    content.windows().into_iter().filter(|w| selectors.iter().any(|s| window_matches(w, s))).collect()
+}
+
+fn window_matches(window: &SCWindow, selector: &WindowSelector) -> bool {
+    let title: Option<String> = window.title().into();
+    let pid = window.owning_application().process_id() as u32;
+    selector.matches(&title.unwrap_or_default(), Some(pid))
+}
+
 #[derive(Clone)]
 struct SCStreamOutput {
     tx: mpsc::SyncSender<CMSampleBuffer>,
@@ -206,12 +257,6 @@ impl SCStreamOutputTrait for SCStreamOutput {
     }
 }
 
-impl Context {
-    pub fn get_frame(&self) -> FrameConvertedData {
-        self.rx.recv().expect("Recv FrameConvertedData failed!")
-    }
-}
-
 struct VirtualDisplay {
     pub display: Retained<AnyObject>,
 }
@@ -0,0 +1,122 @@
+use crate::capture::{FrameCaptureData, fit::{Mapping, Rect}};
+use std::str::FromStr;
+use clap::ValueEnum;
+
+const PIXELATE_BLOCK: usize = 16;
+const BLUR_RADIUS: isize = 8;
+const BLUR_PASSES: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MaskStyle {
+    Black,
+    Pixelate,
+    Blur,
+}
+
+/// Area of the desktop hidden before encoding.
+#[derive(Clone, Copy, Debug)]
+pub struct Mask {
+    pub rect: Rect,
+    pub style: MaskStyle,
+}
+
+impl FromStr for Mask {
+    type Err = String;
+
+    /// `x,y,width,height[:style]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rect, style) = match s.split_once(':') {
+            Some((rect, style)) => (rect, MaskStyle::from_str(style, true)?),
+            None => (s, MaskStyle::Black),
+        };
+        Ok(Mask { rect: rect.parse()?, style })
+    }
+}
+
+/// Hides the masks in a captured frame, they are given in desktop coordinates and located through
+/// `frame.desktop`. Runs per source before resize, so a mask never lands on another source of the canvas.
+pub fn apply(masks: &[Mask], frame: &mut FrameCaptureData) {
+    let output = Rect::new(0.0, 0.0, frame.width as f64, frame.height as f64);
+    let mapping = Mapping { src: frame.desktop, dst: output };
+    for mask in masks {
+        let (x0, y0) = mapping.to_output((mask.rect.x, mask.rect.y));
+        let (x1, y1) = mapping.to_output((mask.rect.x + mask.rect.width, mask.rect.y + mask.rect.height));
+        let rect = Rect::new(x0, y0, x1 - x0, y1 - y0);
+        let (data, width, height) = (frame.data.as_mut_slice(), frame.width, frame.height);
+        match mask.style {
+            MaskStyle::Black => fill(data, width, height, rect, [0, 0, 0, 255]),
+            MaskStyle::Pixelate => pixelate(data, width, height, rect),
+            MaskStyle::Blur => blur(data, width, height, rect),
+        }
+    }
+}
+
+/// Pixel bounds `(left, top, right, bottom)` covering `rect`, clipped to the image.
+fn bounds(rect: Rect, width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+    let r = rect.intersect(&Rect::new(0.0, 0.0, width as f64, height as f64))?;
+    Some((r.x.floor() as usize, r.y.floor() as usize, (r.x + r.width).ceil() as usize, (r.y + r.height).ceil() as usize))
+}
+
+/// Paints a rectangle of a 4 bytes per pixel image, clipped to the image bounds.
+pub fn fill(data: &mut [u8], width: usize, height: usize, rect: Rect, color: [u8; 4]) {
+    let Some((left, top, right, bottom)) = bounds(rect, width, height) else { return };
+    for y in top..bottom {
+        data[(y * width + left) * 4..(y * width + right) * 4]
+            .chunks_exact_mut(4)
+            .for_each(|pixel| pixel.copy_from_slice(&color));
+    }
+}
+
+fn pixelate(data: &mut [u8], width: usize, height: usize, rect: Rect) {
+    let Some((left, top, right, bottom)) = bounds(rect, width, height) else { return };
+    for by in (top..bottom).step_by(PIXELATE_BLOCK) {
+        for bx in (left..right).step_by(PIXELATE_BLOCK) {
+            let block = (bx, by, (bx + PIXELATE_BLOCK).min(right), (by + PIXELATE_BLOCK).min(bottom));
+            let mut sum = [0usize; 4];
+            for y in block.1..block.3 {
+                for pixel in data[(y * width + block.0) * 4..(y * width + block.2) * 4].chunks_exact(4) {
+                    sum.iter_mut().zip(pixel).for_each(|(s, v)| *s += *v as usize);
+                }
+            }
+            let count = (block.2 - block.0) * (block.3 - block.1);
+            let color = sum.map(|s| (s / count) as u8);
+            let rect = Rect::new(block.0 as f64, block.1 as f64, (block.2 - block.0) as f64, (block.3 - block.1) as f64);
+            fill(data, width, height, rect, color);
+        }
+    }
+}
+
+// Repeated box blurs, close enough to a gaussian and cheap at any radius
+fn blur(data: &mut [u8], width: usize, height: usize, rect: Rect) {
+    let Some((left, top, right, bottom)) = bounds(rect, width, height) else { return };
+    for _ in 0..BLUR_PASSES {
+        for y in top..bottom {
+            box_line(data, (y * width + left) * 4, 4, right - left);
+        }
+        for x in left..right {
+            box_line(data, (top * width + x) * 4, width * 4, bottom - top);
+        }
+    }
+}
+
+/// Box blur of `len` pixels starting at byte `start`, `step` bytes apart. Edges are clamped
+/// so nothing outside the masked area is mixed in.
+fn box_line(data: &mut [u8], start: usize, step: usize, len: usize) {
+    let source: Vec<[u8; 4]> = (0..len).map(|i| {
+        let p = start + i * step;
+        [data[p], data[p + 1], data[p + 2], data[p + 3]]
+    }).collect();
+    let at = |i: isize| source[i.clamp(0, len as isize - 1) as usize];
+    let taps = (2 * BLUR_RADIUS + 1) as u32;
+
+    let mut sum = [0u32; 3];
+    for i in -BLUR_RADIUS..=BLUR_RADIUS {
+        sum.iter_mut().zip(at(i)).for_each(|(s, v)| *s += v as u32);
+    }
+    for i in 0..len as isize {
+        let p = start + i as usize * step;
+        data[p..p + 3].iter_mut().zip(sum).for_each(|(d, s)| *d = (s / taps) as u8);
+        let (add, sub) = (at(i + BLUR_RADIUS + 1), at(i - BLUR_RADIUS));
+        sum.iter_mut().zip(add.into_iter().zip(sub)).for_each(|(s, (a, b))| *s = *s + a as u32 - b as u32);
+    }
+}
@@ -1,8 +1,151 @@
+mod annotate;
+mod color;
+mod compose;
+mod cursor;
+mod encoder;
+mod filter;
+mod font;
+mod hud;
+pub mod fit;
+mod mask;
+mod overlay;
+mod panel;
+mod pipeline;
+mod png;
+pub mod pointer;
+mod reload;
+mod resize;
+mod scene;
+mod viewport;
+mod wall;
+mod window;
+mod zoom;
+
+pub use self::color::Color;
+pub use self::compose::Pip;
+pub use self::encoder::Subsampling;
+pub use self::fit::FitMode;
+pub use self::mask::Mask;
+pub use self::overlay::Overlay;
+pub use self::panel::{Action as PanelAction, Panel, shell};
+pub use self::pipeline::{Context, Placement};
+pub use self::resize::{Scaler, benchmark as benchmark_scalers};
+pub use self::scene::Scene;
+pub use self::viewport::Viewport;
+pub use self::wall::Wall;
+pub use self::window::WindowSelector;
+
+#[derive(Clone)]
+pub struct FrameCaptureData {
+    pub data: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub pixel_format: turbojpeg::PixelFormat,
+    /// Area of the desktop the frame shows, in desktop coordinates
+    pub desktop: fit::Rect,
+    pub fps: Option<usize>,
+    pub captured: std::time::Instant,
+}
+
 pub struct FrameConvertedData {
     pub data: Box<[u8]>,
     pub data_size: usize,
     pub quality: i32,
+    pub subsampling: Subsampling,
     pub fps: Option<usize>,
+    pub captured: std::time::Instant,
+}
+
+/// What a backend records: a display (`None` for the default one) or a window.
+#[derive(Clone, Debug)]
+pub enum CaptureSource {
+    Display(Option<usize>),
+    Window(WindowSelector),
+}
+
+impl std::str::FromStr for CaptureSource {
+    type Err = String;
+
+    /// `display=INDEX`, `window=REGEX` or `pid=PID`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("display", index)) => index.parse().map(|i| CaptureSource::Display(Some(i))).map_err(|_| format!("invalid display: {}", index)),
+            Some(("window", title)) => regex::Regex::new(title).map(|r| CaptureSource::Window(WindowSelector::Title(r))).map_err(|e| e.to_string()),
+            Some(("pid", pid)) => pid.parse().map(|p| CaptureSource::Window(WindowSelector::Pid(p))).map_err(|_| format!("invalid pid: {}", pid)),
+            _ => Err(format!("expected display=INDEX, window=REGEX or pid=PID: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Options {
+    pub display: Option<usize>,
+    pub window: Option<WindowSelector>,
+    pub exclude_windows: Vec<WindowSelector>,
+    pub subsampling: Subsampling,
+    pub supported_subsampling: Vec<Subsampling>,
+    pub scaler: Scaler,
+    pub sharpen: f32,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+    pub temperature: f32,
+    pub grayscale: bool,
+    pub invert: bool,
+    pub fit: FitMode,
+    pub background: Color,
+    pub region: Option<fit::Rect>,
+    pub masks: Vec<Mask>,
+    pub overlays: Vec<Overlay>,
+    pub overlay_size: f32,
+    pub overlay_color: Color,
+    pub overlay_opacity: f32,
+    /// Watermarks use the overlay opacity when not given
+    pub watermark_opacity: Option<f32>,
+    pub font: Option<std::path::PathBuf>,
+    pub zoom: Option<f64>,
+    pub zoom_smoothing: f64,
+    /// Touch pans and zooms the main source
+    pub viewport: bool,
+    pub hud: bool,
+    pub cursor: bool,
+    pub cursor_size: f64,
+    pub click_highlight: bool,
+    pub pips: Vec<Pip>,
+    pub scene: Option<Scene>,
+    pub wall: Option<Wall>,
+    /// Snapshot directory, finger strokes are drawn over the stream when set
+    pub annotate: Option<std::path::PathBuf>,
+    /// Buttons drawn by the host instead of a capture
+    pub panel: Option<Panel>,
+}
+
+impl Options {
+    /// The main source, shown on the whole canvas
+    pub fn source(&self) -> CaptureSource {
+        match &self.window {
+            Some(selector) => CaptureSource::Window(selector.clone()),
+            None => CaptureSource::Display(self.display),
+        }
+    }
+
+    /// Options for capturing a picture-in-picture source, which is never cropped or zoomed
+    pub fn pip(&self) -> Options {
+        Options { region: None, zoom: None, viewport: false, ..self.clone() }
+    }
+
+    /// Every source to capture with its options, in the order `pipeline::start` returns the senders
+    pub fn sources(&self) -> Vec<(CaptureSource, Options)> {
+        if self.panel.is_some() {
+            return Vec::new();
+        }
+        if let Some(scene) = &self.scene {
+            return scene.sources().into_iter().map(|(_, source)| (source, self.pip())).collect();
+        }
+        let mut sources = vec![(self.source(), self.clone())];
+        sources.extend(self.pips.iter().map(|pip| (pip.source.clone(), self.pip())));
+        sources
+    }
 }
 
 pub fn check_permission() -> bool {
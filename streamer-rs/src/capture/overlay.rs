@@ -0,0 +1,279 @@
+use crate::capture::{Color, FrameCaptureData, Options, color};
+use std::{path::{Path, PathBuf}, str::FromStr};
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont, point};
+use chrono::format::StrftimeItems;
+use clap::ValueEnum;
+use fast_image_resize as fir;
+
+const DEFAULT_FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSansMono.ttf");
+const DEFAULT_CLOCK: &str = "%H:%M:%S";
+const MARGIN: isize = 16;
+const SPACING: isize = 8;
+const JPEG_MAGIC: &[u8] = &[0xFF, 0xD8, 0xFF];
+const PNG_MAGIC: &[u8] = b"\x89PNG";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Position {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverlayKind {
+    Text(String),
+    /// strftime style format
+    Clock(String),
+    Hostname,
+    /// JPEG image, there is no PNG decoder
+    Watermark(PathBuf),
+}
+
+/// Item stamped onto every frame, items sharing a position are stacked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlay {
+    pub kind: OverlayKind,
+    pub position: Position,
+}
+
+impl FromStr for Overlay {
+    type Err = String;
+
+    /// `text=LABEL`, `clock[=FORMAT]`, `hostname` or `watermark=FILE.jpg`, optionally followed by `@position`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spec, position) = s.rsplit_once('@')
+            .and_then(|(spec, position)| Some((spec, Position::from_str(position, true).ok()?)))
+            .unwrap_or((s, Position::BottomRight));
+        let kind = match spec.split_once('=').map_or((spec, None), |(k, v)| (k, Some(v))) {
+            ("text", Some(text)) => OverlayKind::Text(text.to_string()),
+            ("clock", format) => OverlayKind::Clock(check_clock(format.unwrap_or(DEFAULT_CLOCK))?.to_string()),
+            ("hostname", None) => OverlayKind::Hostname,
+            ("watermark", Some(path)) if is_jpeg_name(path) => OverlayKind::Watermark(path.into()),
+            ("watermark", Some(path)) => return Err(format!("watermarks must be JPEG images (.jpg or .jpeg): {}", path)),
+            _ => return Err(format!("expected text=LABEL, clock[=FORMAT], hostname or watermark=FILE.jpg: {}", s)),
+        };
+        Ok(Overlay { kind, position })
+    }
+}
+
+/// Rejects strftime formats chrono can not render, rendering one would panic.
+pub fn check_clock(format: &str) -> Result<&str, String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, chrono::format::Item::Error)) {
+        return Err(format!("invalid clock format: {}", format));
+    }
+    Ok(format)
+}
+
+pub enum Layer {
+    /// Coverage of rendered text, 0 to 1
+    Text { alpha: Vec<f32>, width: usize, height: usize },
+    /// RGB pixels
+    Image { pixels: Vec<u8>, width: usize, height: usize },
+}
+
+impl Layer {
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            Layer::Text { width, height, .. } | Layer::Image { width, height, .. } => (*width, *height),
+        }
+    }
+}
+
+struct Item {
+    overlay: Overlay,
+    text: String,
+    layer: Layer,
+}
+
+/// Compositing stage ahead of the encoder, draws the overlays onto the output canvas.
+pub struct Compositor {
+    pen: Pen,
+    /// Same font and colour, at the watermark opacity
+    watermark: Pen,
+    items: Vec<Item>,
+}
+
+impl Compositor {
+    pub fn new(options: &Options) -> Self {
+        let font = load_font(options.font.as_deref());
+        let pen = Pen::new(font.clone(), options.overlay_size, options.overlay_color, options.overlay_opacity);
+        let watermark_opacity = options.watermark_opacity.unwrap_or(options.overlay_opacity);
+        let watermark = Pen::new(font, options.overlay_size, options.overlay_color, watermark_opacity);
+        let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+
+        let items = options.overlays.iter().map(|overlay| {
+            let (text, layer) = match &overlay.kind {
+                OverlayKind::Watermark(path) => (String::new(), load_image(path, None).expect("Load Watermark Failed!")),
+                OverlayKind::Hostname => (hostname.clone(), pen.render(&hostname)),
+                OverlayKind::Text(text) => (text.clone(), pen.render(text)),
+                OverlayKind::Clock(_) => (String::new(), pen.render("")),
+            };
+            Item { overlay: overlay.clone(), text, layer }
+        }).collect();
+        Compositor { pen, watermark, items }
+    }
+
+    pub fn apply(&mut self, frame: &mut FrameCaptureData) {
+        if self.items.is_empty() { return }
+
+        // The clock is the only text that changes, render again only when it does
+        let now = chrono::Local::now();
+        for i in 0..self.items.len() {
+            let text = match &self.items[i].overlay.kind {
+                OverlayKind::Clock(format) => now.format(format).to_string(),
+                _ => continue,
+            };
+            if text != self.items[i].text {
+                self.items[i].layer = self.pen.render(&text);
+                self.items[i].text = text;
+            }
+        }
+
+        let (frame_width, frame_height) = (frame.width as isize, frame.height as isize);
+        let mut offsets = [0isize; 5];
+        for item in &self.items {
+            let (width, height) = item.layer.size();
+            let (width, height) = (width as isize, height as isize);
+            let offset = &mut offsets[item.overlay.position as usize];
+            let x = match item.overlay.position {
+                Position::TopLeft | Position::BottomLeft => MARGIN,
+                Position::TopRight | Position::BottomRight => frame_width - MARGIN - width,
+                Position::Center => (frame_width - width) / 2,
+            };
+            let y = match item.overlay.position {
+                Position::TopLeft | Position::TopRight => MARGIN + *offset,
+                Position::BottomLeft | Position::BottomRight => frame_height - MARGIN - *offset - height,
+                Position::Center => (frame_height - height) / 2 + *offset,
+            };
+            *offset += height + SPACING;
+            let pen = match item.overlay.kind {
+                OverlayKind::Watermark(_) => &self.watermark,
+                _ => &self.pen,
+            };
+            pen.draw(frame, &item.layer, (x, y));
+        }
+    }
+}
+
+/// Text rendering in one font, size and colour, also draws images at the same opacity.
+pub struct Pen {
+    font: FontArc,
+    size: f32,
+    color: Color,
+    opacity: f32,
+}
+
+impl Pen {
+    pub fn new(font: FontArc, size: f32, color: Color, opacity: f32) -> Self {
+        Pen { font, size, color, opacity: opacity.clamp(0.0, 1.0) }
+    }
+
+    pub fn render(&self, text: &str) -> Layer {
+        let font = self.font.as_scaled(PxScale::from(self.size));
+        let mut glyphs = Vec::new();
+        let mut x = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                x += font.kern(previous, id);
+            }
+            glyphs.push(id.with_scale_and_position(font.scale(), point(x, font.ascent())));
+            x += font.h_advance(id);
+            previous = Some(id);
+        }
+
+        let (width, height) = (x.ceil() as usize, font.height().ceil() as usize);
+        let mut alpha = vec![0.0; width * height];
+        for glyph in glyphs {
+            let Some(outlined) = self.font.outline_glyph(glyph) else { continue };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let x = bounds.min.x as isize + gx as isize;
+                let y = bounds.min.y as isize + gy as isize;
+                if (0..width as isize).contains(&x) && (0..height as isize).contains(&y) {
+                    let a = &mut alpha[y as usize * width + x as usize];
+                    *a = (*a + coverage).min(1.0);
+                }
+            });
+        }
+        Layer::Text { alpha, width, height }
+    }
+
+    pub fn draw(&self, frame: &mut FrameCaptureData, layer: &Layer, (x, y): (isize, isize)) {
+        match layer {
+            Layer::Text { alpha, width, height } => {
+                // Drop shadow, keeps light text readable on light content
+                let shadow = (self.size / 16.0).max(1.0) as isize;
+                let at = |i: usize| alpha[i] * self.opacity;
+                blend(frame, (x + shadow, y + shadow), (*width, *height), |i| ([0, 0, 0], at(i) * 0.6));
+                let color = [self.color.r, self.color.g, self.color.b];
+                blend(frame, (x, y), (*width, *height), |i| (color, at(i)));
+            }
+            Layer::Image { pixels, width, height } => {
+                blend(frame, (x, y), (*width, *height), |i| {
+                    ([pixels[i * 3], pixels[i * 3 + 1], pixels[i * 3 + 2]], self.opacity)
+                });
+            }
+        }
+    }
+}
+
+/// Mixes a `width` x `height` layer into the frame at `(x, y)`, `pixel` gives the RGB colour and
+/// opacity of the layer pixel at an index. Whatever falls outside the frame is skipped.
+fn blend(frame: &mut FrameCaptureData, (x, y): (isize, isize), (width, height): (usize, usize), pixel: impl Fn(usize) -> ([u8; 3], f32)) {
+    let channels = color::channels(frame.pixel_format);
+    for ly in 0..height {
+        let fy = y + ly as isize;
+        if fy < 0 || fy >= frame.height as isize { continue }
+        for lx in 0..width {
+            let fx = x + lx as isize;
+            if fx < 0 || fx >= frame.width as isize { continue }
+            let (rgb, alpha) = pixel(ly * width + lx);
+            if alpha <= 0.0 { continue }
+            let p = (fy as usize * frame.width + fx as usize) * 4;
+            for (channel, value) in channels.iter().zip(rgb) {
+                let dst = &mut frame.data[p + channel];
+                *dst = (*dst as f32 * (1.0 - alpha) + value as f32 * alpha).round() as u8;
+            }
+        }
+    }
+}
+
+/// The font given on the command line, or the bundled one.
+pub fn load_font(path: Option<&Path>) -> FontArc {
+    match path {
+        Some(path) => FontArc::try_from_vec(std::fs::read(path).expect("Read Font Failed!")),
+        None => FontArc::try_from_slice(DEFAULT_FONT),
+    }.expect("Load Font Failed!")
+}
+
+fn is_jpeg_name(path: &str) -> bool {
+    Path::new(path).extension().and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("jpg") || e.eq_ignore_ascii_case("jpeg"))
+}
+
+/// Decodes a JPEG, scaled to `size` when given. Other formats are refused, there is no decoder for them.
+pub fn load_image(path: &Path, size: Option<(usize, usize)>) -> Result<Layer, String> {
+    let jpeg = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if jpeg.starts_with(PNG_MAGIC) {
+        return Err(format!("{}: PNG is not supported, convert the image to JPEG", path.display()));
+    }
+    if !jpeg.starts_with(JPEG_MAGIC) {
+        return Err(format!("{}: not a JPEG image", path.display()));
+    }
+    let image = turbojpeg::decompress(&jpeg, turbojpeg::PixelFormat::RGB).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let pixels: Vec<u8> = image.pixels.chunks(image.pitch).flat_map(|row| &row[..image.width * 3]).copied().collect();
+    let Some((width, height)) = size.filter(|size| *size != (image.width, image.height)) else {
+        return Ok(Layer::Image { pixels, width: image.width, height: image.height });
+    };
+
+    let source = fir::images::Image::from_vec_u8(image.width as u32, image.height as u32, pixels, fir::PixelType::U8x3)
+        .map_err(|e| e.to_string())?;
+    let mut scaled = fir::images::Image::new(width as u32, height as u32, fir::PixelType::U8x3);
+    let options = fir::ResizeOptions::new().resize_alg(fir::ResizeAlg::Convolution(fir::FilterType::Bilinear));
+    fir::Resizer::new().resize(&source, &mut scaled, &options).map_err(|e| e.to_string())?;
+    Ok(Layer::Image { pixels: scaled.into_vec(), width, height })
+}
@@ -0,0 +1,344 @@
+use crate::capture::{Color, FrameCaptureData, Options, fit::Rect, mask, overlay::{self, Layer, Pen}, reload::Watch, scene::{get_float, get_str}};
+use crate::inject::{Gesture, Shortcut};
+use std::{collections::HashMap, path::{Path, PathBuf}, process::Command, time::{Duration, Instant}};
+use ab_glyph::FontArc;
+use toml::{Table, Value};
+
+/// The last frame goes out again this often, the encoder drops frames while it is busy
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_STATE_INTERVAL: f32 = 5.0;
+const GAP: f64 = 16.0;
+const PADDING: f64 = 12.0;
+const PIXEL_FORMAT: turbojpeg::PixelFormat = turbojpeg::PixelFormat::BGRA;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Shell command, not waited for
+    Command(String),
+    Keys(Shortcut),
+    /// Switch to the page with this name
+    Page(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Button {
+    pub label: String,
+    /// JPEG image
+    pub icon: Option<PathBuf>,
+    pub color: Color,
+    pub action: Option<Action>,
+    /// Shell command whose first line of output is shown under the label, and how often it runs
+    pub state: Option<(String, Duration)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Page {
+    pub name: String,
+    /// Laid out row by row
+    pub buttons: Vec<Button>,
+}
+
+/// Pages of buttons in a grid, loaded from a TOML file.
+#[derive(Clone, Debug)]
+pub struct Panel {
+    pub path: PathBuf,
+    pub size: (usize, usize),
+    pub grid: (usize, usize),
+    pub background: Color,
+    pub label_color: Color,
+    pub font_size: f32,
+    pub pages: Vec<Page>,
+}
+
+impl Panel {
+    pub fn load(path: &Path) -> Result<Panel, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let table: Table = text.parse().map_err(|e| format!("{}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        let pair = |key: &str, default: (usize, usize)| match table.get(key) {
+            None => Ok(default),
+            Some(Value::Array(a)) => match a[..] {
+                [Value::Integer(x), Value::Integer(y)] if x > 0 && y > 0 => Ok((x as usize, y as usize)),
+                _ => Err(format!("{}: expected two positive integers", key)),
+            },
+            Some(_) => Err(format!("{}: expected two positive integers", key)),
+        };
+        let size = pair("size", (1280, 720))?;
+        let grid = pair("grid", (4, 3))?;
+        let background = get_str(&table, "background")?.map(str::parse).transpose()?.unwrap_or(Color { r: 0, g: 0, b: 0 });
+        let label_color = get_str(&table, "label_color")?.map(str::parse).transpose()?.unwrap_or(Color { r: 255, g: 255, b: 255 });
+        let font_size = get_float(&table, "font_size")?.unwrap_or(size.1 as f32 / grid.1 as f32 / 6.0);
+
+        let pages = match table.get("page") {
+            Some(Value::Array(pages)) if !pages.is_empty() => pages.iter().enumerate().map(|(i, page)| {
+                let Value::Table(page) = page else { return Err(format!("page {}: expected a table", i + 1)) };
+                parse_page(page, base, grid).map_err(|e| format!("page {}: {}", i + 1, e))
+            }).collect::<Result<Vec<_>, String>>()?,
+            _ => return Err("page: expected at least one [[page]] table".to_string()),
+        };
+        for action in pages.iter().flat_map(|page| &page.buttons).filter_map(|button| button.action.as_ref()) {
+            if let Action::Page(name) = action && !pages.iter().any(|page| page.name == *name) {
+                return Err(format!("no page named {}", name));
+            }
+        }
+        Ok(Panel { path: path.to_path_buf(), size, grid, background, label_color, font_size, pages })
+    }
+
+    /// Canvas area of the button at `index` on a page.
+    fn cell(&self, index: usize) -> Rect {
+        let (cols, rows) = (self.grid.0 as f64, self.grid.1 as f64);
+        let width = (self.size.0 as f64 - GAP * (cols + 1.0)) / cols;
+        let height = (self.size.1 as f64 - GAP * (rows + 1.0)) / rows;
+        let (col, row) = ((index % self.grid.0) as f64, (index / self.grid.0) as f64);
+        Rect::new(GAP + col * (width + GAP), GAP + row * (height + GAP), width, height)
+    }
+}
+
+fn parse_page(page: &Table, base: &Path, grid: (usize, usize)) -> Result<Page, String> {
+    let name = get_str(page, "name")?.ok_or("name: missing")?.to_string();
+    let buttons = match page.get("button") {
+        None => Vec::new(),
+        Some(Value::Array(buttons)) => buttons.iter().enumerate().map(|(i, button)| {
+            let Value::Table(button) = button else { return Err(format!("button {}: expected a table", i + 1)) };
+            parse_button(button, base).map_err(|e| format!("button {}: {}", i + 1, e))
+        }).collect::<Result<Vec<_>, String>>()?,
+        Some(_) => return Err("button: expected [[page.button]] tables".to_string()),
+    };
+    if buttons.len() > grid.0 * grid.1 {
+        return Err(format!("{} buttons do not fit a {}x{} grid", buttons.len(), grid.0, grid.1));
+    }
+    Ok(Page { name, buttons })
+}
+
+fn parse_button(button: &Table, base: &Path) -> Result<Button, String> {
+    let action = if let Some(command) = get_str(button, "command")? {
+        Some(Action::Command(command.to_string()))
+    } else if let Some(keys) = get_str(button, "keys")? {
+        Some(Action::Keys(keys.parse()?))
+    } else {
+        get_str(button, "page")?.map(|page| Action::Page(page.to_string()))
+    };
+    let interval = get_float(button, "interval")?.unwrap_or(DEFAULT_STATE_INTERVAL).max(0.1);
+    Ok(Button {
+        label: get_str(button, "label")?.unwrap_or_default().to_string(),
+        icon: get_str(button, "icon")?.map(|icon| base.join(icon)),
+        color: get_str(button, "color")?.map(str::parse).transpose()?.unwrap_or(Color { r: 48, g: 48, b: 48 }),
+        action,
+        state: get_str(button, "state")?.map(|state| (state.to_string(), Duration::from_secs_f32(interval))),
+    })
+}
+
+/// Shell command line, run by `sh` or `cmd`.
+pub fn shell(command: &str) -> Command {
+    #[cfg(target_os = "windows")]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    };
+    #[cfg(not(target_os = "windows"))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Rendered pieces of a button, kept until the page or the file changes.
+struct Face {
+    icon: Option<Layer>,
+    label: Layer,
+    /// Output it shows and its rendering
+    state: Option<(String, Layer)>,
+}
+
+/// The panel at runtime: current page, the button under the finger and the live state, drawn
+/// into frames for the encoder.
+pub struct Deck {
+    panel: Panel,
+    font: FontArc,
+    page: usize,
+    pressed: Option<usize>,
+    faces: Vec<Face>,
+    /// Last output of every state command, by page and button
+    states: HashMap<(usize, usize), String>,
+    /// When each state command runs next
+    due: HashMap<(usize, usize), Instant>,
+    frame: Option<FrameCaptureData>,
+    dirty: bool,
+    rendered: Instant,
+    watch: Watch,
+}
+
+impl Deck {
+    pub fn new(panel: Panel, options: &Options) -> Self {
+        let mut deck = Deck {
+            watch: Watch::new(&panel.path),
+            panel,
+            font: overlay::load_font(options.font.as_deref()),
+            page: 0,
+            pressed: None,
+            faces: Vec::new(),
+            states: HashMap::new(),
+            due: HashMap::new(),
+            frame: None,
+            dirty: true,
+            rendered: Instant::now(),
+        };
+        deck.build();
+        deck
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        self.panel.size
+    }
+
+    fn build(&mut self) {
+        let pen = Pen::new(self.font.clone(), self.panel.font_size, self.panel.label_color, 1.0);
+        let page = &self.panel.pages[self.page];
+        self.faces = page.buttons.iter().enumerate().map(|(i, button)| {
+            let cell = self.panel.cell(i);
+            let side = (cell.width.min(cell.height * 0.6) - 2.0 * PADDING).max(1.0).round() as usize;
+            let icon = button.icon.as_ref().and_then(|path| match overlay::load_image(path, Some((side, side))) {
+                Ok(icon) => Some(icon),
+                Err(e) => {
+                    println!("Panel: {}", e);
+                    None
+                }
+            });
+            Face { icon, label: pen.render(&button.label), state: None }
+        }).collect();
+        self.dirty = true;
+    }
+
+    fn reload(&mut self) {
+        if !self.watch.changed() { return }
+        match Panel::load(&self.panel.path) {
+            Ok(panel) => {
+                println!("Panel Reloaded: {}", panel.path.display());
+                self.page = self.page.min(panel.pages.len() - 1);
+                self.panel = panel;
+                self.pressed = None;
+                self.states.clear();
+                self.due.clear();
+                self.build();
+            }
+            Err(e) => println!("Panel: {}, keeping the previous one", e),
+        }
+    }
+
+    /// A frame to send, when the buttons changed or the last one is due again.
+    pub fn render(&mut self) -> Option<FrameCaptureData> {
+        self.reload();
+        if !self.dirty {
+            if self.rendered.elapsed() < REFRESH_INTERVAL { return None }
+            self.rendered = Instant::now();
+            return self.frame.clone().map(|frame| FrameCaptureData { captured: Instant::now(), ..frame });
+        }
+        self.dirty = false;
+        self.rendered = Instant::now();
+
+        let (width, height) = self.panel.size;
+        let mut frame = FrameCaptureData {
+            data: self.panel.background.bytes(PIXEL_FORMAT).repeat(width * height),
+            width,
+            height,
+            pixel_format: PIXEL_FORMAT,
+            desktop: Rect::new(0.0, 0.0, width as f64, height as f64),
+            fps: None,
+            captured: Instant::now(),
+        };
+        let pen = Pen::new(self.font.clone(), self.panel.font_size, self.panel.label_color, 1.0);
+        let buttons = &self.panel.pages[self.page].buttons;
+        for (i, (button, face)) in buttons.iter().zip(&mut self.faces).enumerate() {
+            let cell = self.panel.cell(i);
+            let color = match self.pressed == Some(i) {
+                // Pressed buttons light up
+                true => Color { r: lighten(button.color.r), g: lighten(button.color.g), b: lighten(button.color.b) },
+                false => button.color,
+            };
+            mask::fill(&mut frame.data, width, height, cell, color.bytes(PIXEL_FORMAT));
+
+            let state = self.states.get(&(self.page, i));
+            if state != face.state.as_ref().map(|(text, _)| text) {
+                face.state = state.map(|text| (text.clone(), pen.render(text)));
+            }
+            // Icon, label and state stacked and centred in the cell
+            let layers: Vec<&Layer> = face.icon.iter().chain([&face.label]).chain(face.state.as_ref().map(|(_, layer)| layer)).collect();
+            let total: usize = layers.iter().map(|layer| layer.size().1).sum();
+            let mut y = cell.y + (cell.height - total as f64) / 2.0;
+            for layer in layers {
+                let (layer_width, layer_height) = layer.size();
+                let x = cell.x + (cell.width - layer_width as f64) / 2.0;
+                pen.draw(&mut frame, layer, (x.round() as isize, y.round() as isize));
+                y += layer_height as f64;
+            }
+        }
+        self.frame = Some(frame.clone());
+        Some(frame)
+    }
+
+    /// Button of the current page at a canvas position.
+    fn button_at(&self, point: (f64, f64)) -> Option<usize> {
+        let count = self.panel.pages[self.page].buttons.len();
+        (0..count).find(|&i| self.panel.cell(i).contains(point))
+    }
+
+    /// Lights up the button under a finger and returns the action of a tapped one, page switches
+    /// happen right here. `to_canvas` maps output coordinates to panel coordinates.
+    pub fn touch(&mut self, gesture: Gesture, to_canvas: impl Fn((f64, f64)) -> Option<(f64, f64)>) -> Option<Action> {
+        let (pressed, tapped) = match gesture {
+            Gesture::Point(x, y) => (to_canvas((x, y)).and_then(|point| self.button_at(point)), None),
+            Gesture::Tap(x, y) => (None, to_canvas((x, y)).and_then(|point| self.button_at(point))),
+            // Sliding off or holding cancels the press
+            _ => (None, None),
+        };
+        if pressed != self.pressed {
+            self.pressed = pressed;
+            self.dirty = true;
+        }
+        match self.panel.pages[self.page].buttons[tapped?].action.clone()? {
+            Action::Page(name) => {
+                self.page = self.panel.pages.iter().position(|page| page.name == name)?;
+                self.build();
+                None
+            }
+            action => Some(action),
+        }
+    }
+
+    /// State commands of the current page that are due to run, with their page and button.
+    pub fn due_states(&mut self) -> Vec<((usize, usize), String)> {
+        let now = Instant::now();
+        let buttons = &self.panel.pages[self.page].buttons;
+        buttons.iter().enumerate().filter_map(|(i, button)| {
+            let (command, interval) = button.state.as_ref()?;
+            let due = self.due.entry((self.page, i)).or_insert(now);
+            if *due > now { return None }
+            *due = now + *interval;
+            Some(((self.page, i), command.clone()))
+        }).collect()
+    }
+
+    pub fn set_state(&mut self, key: (usize, usize), text: String) {
+        if self.states.get(&key) != Some(&text) {
+            self.states.insert(key, text);
+            self.dirty |= key.0 == self.page;
+        }
+    }
+}
+
+/// First line a state command prints.
+pub fn state_output(command: &str) -> String {
+    match shell(command).output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().trim().to_string(),
+        Err(e) => format!("{}", e),
+    }
+}
+
+fn lighten(value: u8) -> u8 {
+    (value as u16 + (255 - value as u16) * 2 / 5) as u8
+}
@@ -0,0 +1,429 @@
+use crate::capture::{FrameCaptureData, FrameConvertedData, Options, annotate::Annotation, compose::{Compose, Composer}, cursor::{CursorRefresh, CursorRenderer}, encoder::Encoder, filter::FilterChain, hud, mask, overlay::Compositor, png, panel::{self, Deck}, resize::Resizer, scene::SceneComposer, viewport::Viewport, wall::Wall};
+use crate::capture::fit::{Mapping, Rect};
+use std::{thread, time::{Duration, Instant}, sync::{mpsc, Arc, Mutex}};
+
+const PANEL_TICK: Duration = Duration::from_millis(50);
+/// How often the last frame is resized again while the viewport moves over a still desktop
+const VIEWPORT_TICK: Duration = Duration::from_millis(16);
+
+#[derive(Clone)]
+pub struct Sender {
+    /// Index into `Options::sources`
+    source: usize,
+    /// Scene sources go to the compose stage unscaled
+    resz_tx: Option<mpsc::SyncSender<FrameCaptureData>>,
+    comp_tx: mpsc::SyncSender<(usize, FrameCaptureData)>,
+    /// Only the main source decides where the desktop lands on the canvas
+    mapping: Option<Arc<Mutex<Mapping>>>,
+    bypass_resize: bool,
+    /// Hidden on the captured frame, before it is scaled or composed with other sources
+    masks: Arc<Vec<mask::Mask>>,
+}
+
+pub struct Context {
+    /// One frame per device, tiles of a wall share the same capture
+    rx: mpsc::Receiver<Vec<FrameConvertedData>>,
+    placement: Placement,
+    stats: Arc<Mutex<hud::Stats>>,
+}
+
+/// Where the output of each device comes from, for mapping touches back to the desktop.
+#[derive(Clone)]
+pub struct Placement {
+    mapping: Arc<Mutex<Mapping>>,
+    /// Size of the last encoded canvas
+    canvas: Arc<Mutex<(usize, usize)>>,
+    /// Canvas area of each device on a wall
+    tiles: Option<Vec<Rect>>,
+    /// Crop of the main source, when touches steer it
+    viewport: Option<Arc<Mutex<Viewport>>>,
+    /// Buttons shown instead of a capture
+    panel: Option<Arc<Mutex<Deck>>>,
+    annotation: Option<Arc<Mutex<Annotation>>>,
+}
+
+/// Returns one sender per entry of `Options::sources` and the context for the USB thread.
+pub fn start(options: &Options) -> (Vec<Sender>, Context) {
+    let source_count = options.sources().len();
+    let (comp_tx, comp_rx) = mpsc::sync_channel::<(usize, FrameCaptureData)>(source_count);
+    let (jpeg_tx, jpeg_rx) = mpsc::sync_channel::<FrameCaptureData>(1);
+    let (conv_tx, conv_rx) = mpsc::sync_channel::<Vec<FrameConvertedData>>(1);
+
+    let deck = options.panel.clone().map(|panel| Deck::new(panel, options));
+    let canvas = match (&deck, options.wall) {
+        (Some(deck), _) => deck.size(),
+        (None, Some(wall)) => wall.canvas_size(),
+        (None, None) => (1280, 720),
+    };
+    let mapping = Arc::new(Mutex::new(Mapping::identity(canvas)));
+    let placement = Placement {
+        mapping: mapping.clone(),
+        canvas: Arc::new(Mutex::new(canvas)),
+        tiles: options.wall.map(|wall| wall.tiles()),
+        viewport: options.viewport.then(|| Arc::new(Mutex::new(Viewport::default()))),
+        panel: deck.map(|deck| Arc::new(Mutex::new(deck))),
+        annotation: options.annotate.clone().map(|directory| Arc::new(Mutex::new(Annotation::new(directory)))),
+    };
+    let stats = Arc::new(Mutex::new(hud::Stats::default()));
+    let masks = Arc::new(options.masks.clone());
+
+    let (senders, mut composer): (Vec<Sender>, Box<dyn Compose>) = if let Some(deck) = &placement.panel {
+        // Panel Render Thread, the panel stands in for the main source
+        let (deck_render, comp_tx_panel) = (deck.clone(), comp_tx.clone());
+        thread::spawn(move || loop {
+            let frame = deck_render.lock().unwrap().render();
+            if let Some(frame) = frame {
+                let _ = comp_tx_panel.send((0, frame));
+            }
+            thread::sleep(PANEL_TICK);
+        });
+        // Panel State Thread, runs the commands the buttons show the output of
+        let deck_state = deck.clone();
+        thread::spawn(move || loop {
+            let due = deck_state.lock().unwrap().due_states();
+            for (button, command) in due {
+                let output = panel::state_output(&command);
+                deck_state.lock().unwrap().set_state(button, output);
+            }
+            thread::sleep(PANEL_TICK);
+        });
+        (Vec::new(), Box::new(Composer::new(Vec::new())))
+    } else if let Some(scene) = &options.scene {
+        let senders = (0..source_count).map(|source| {
+            Sender { source, resz_tx: None, comp_tx: comp_tx.clone(), mapping: None, bypass_resize: false, masks: masks.clone() }
+        }).collect();
+        (senders, Box::new(SceneComposer::new(scene.clone(), options, mapping.clone())))
+    } else {
+        // Resize Thread
+        let (resz_tx, resz_rx) = mpsc::sync_channel::<FrameCaptureData>(1);
+        let resizer = match options.wall {
+            Some(wall) => Resizer::new(options).with_output(wall.canvas_size()),
+            None => Resizer::new(options),
+        };
+        let mut resizer = resizer.with_viewport(placement.viewport.clone());
+        let viewport_resize = placement.viewport.clone();
+        let comp_tx_resize = comp_tx.clone();
+        let mapping_resize = mapping.clone();
+        let masks_resize = masks.clone();
+        thread::spawn(move || {
+            // The last frame stays around for the viewport, a pan or fling shows without a new frame
+            let mut last: Option<FrameCaptureData> = None;
+            loop {
+                let data = match resz_rx.recv_timeout(VIEWPORT_TICK) {
+                    Ok(mut frame) => {
+                        mask::apply(&masks_resize, &mut frame);
+                        let data = resizer.resize(&frame);
+                        if viewport_resize.is_some() {
+                            last = Some(frame);
+                        }
+                        data
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        let moving = viewport_resize.as_ref().is_some_and(|viewport| viewport.lock().unwrap().moving());
+                        let Some(frame) = last.as_ref().filter(|_| moving) else { continue };
+                        FrameCaptureData { fps: None, captured: Instant::now(), ..resizer.resize(frame) }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                };
+                *mapping_resize.lock().unwrap() = resizer.mapping();
+                let _ = comp_tx_resize.try_send((0, data));
+            }
+        });
+        let bypass_resize = options.region.is_none() && options.zoom.is_none() && options.wall.is_none() && !options.viewport;
+        let mut senders = vec![Sender { source: 0, resz_tx: Some(resz_tx), comp_tx: comp_tx.clone(), mapping: Some(mapping.clone()), bypass_resize, masks: masks.clone() }];
+
+        // PiP Resize Threads, each pip is scaled into its own rectangle at its own rate
+        senders.extend(options.pips.iter().enumerate().map(|(i, pip)| {
+            let (pip_tx, pip_rx) = mpsc::sync_channel::<FrameCaptureData>(1);
+            let mut resizer = Resizer::new(&options.pip()).with_output(pip.size());
+            let comp_tx_pip = comp_tx.clone();
+            let masks_pip = masks.clone();
+            thread::spawn(move || {
+                for mut frame in pip_rx {
+                    mask::apply(&masks_pip, &mut frame);
+                    let _ = comp_tx_pip.try_send((i + 1, resizer.resize(&frame)));
+                }
+            });
+            Sender { source: i + 1, resz_tx: Some(pip_tx), comp_tx: comp_tx.clone(), mapping: None, bypass_resize: false, masks: masks.clone() }
+        }));
+        (senders, Box::new(Composer::new(options.pips.clone())))
+    };
+
+    if options.cursor {
+        composer = Box::new(CursorRefresh::new(composer));
+    }
+
+    // Compose Thread, also on a timer for composers that change without source frames
+    thread::spawn(move || {
+        let mut composed = Instant::now();
+        loop {
+            let canvas = match composer.interval() {
+                Some(interval) => match comp_rx.recv_timeout(interval.saturating_sub(composed.elapsed())) {
+                    Ok((source, frame)) => composer.update(source, frame),
+                    Err(mpsc::RecvTimeoutError::Timeout) => composer.tick(),
+                    // A scene of stills and clocks has no sources
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        thread::sleep(interval.saturating_sub(composed.elapsed()));
+                        composer.tick()
+                    }
+                },
+                None => match comp_rx.recv() {
+                    Ok((source, frame)) => composer.update(source, frame),
+                    Err(_) => return,
+                },
+            };
+            if let Some(canvas) = canvas {
+                composed = Instant::now();
+                let _ = jpeg_tx.try_send(canvas);
+            }
+        }
+    });
+
+    // JPEG Encode Thread
+    let (subsampling, supported) = (options.subsampling, options.supported_subsampling.clone());
+    let mut filters = FilterChain::new(options);
+    let mut compositor = Compositor::new(options);
+    let mut cursor = CursorRenderer::new(options);
+    let (show_hud, stats_jpeg) = (options.hud, stats.clone());
+    let annotation = placement.annotation.clone();
+    let canvas_jpeg = placement.canvas.clone();
+    // Tile Encode Threads, one per device, each keeps its own quality level for its own USB link
+    let tiles = options.wall.map_or(vec![None], |wall| wall.tiles().into_iter().map(Some).collect());
+    let tile_workers: Vec<_> = tiles.into_iter().map(|tile| {
+        let (tile_tx, tile_rx) = mpsc::sync_channel::<Arc<FrameCaptureData>>(1);
+        let (done_tx, done_rx) = mpsc::sync_channel::<FrameConvertedData>(1);
+        let supported = supported.clone();
+        thread::spawn(move || {
+            let mut encoder = Encoder::new(subsampling, &supported);
+            for frame in tile_rx {
+                let converted = match &tile {
+                    Some(tile) => encode(&mut encoder, &Wall::slice(&frame, tile)),
+                    None => encode(&mut encoder, &frame),
+                };
+                let _ = done_tx.send(converted);
+            }
+        });
+        (tile_tx, done_rx)
+    }).collect();
+    thread::spawn(move || {
+        for mut frame in jpeg_rx {
+            filters.apply(&mut frame);
+            compositor.apply(&mut frame);
+            if let Some(cursor) = &mut cursor {
+                cursor.apply(&mut frame);
+            }
+            if let Some(annotation) = &annotation {
+                let mut annotation = annotation.lock().unwrap();
+                if let Some(snapshot) = annotation.apply(&mut frame) {
+                    // PNG Write Thread, the stream goes on meanwhile
+                    let path = annotation.snapshot_path();
+                    thread::spawn(move || match png::write(&path, &snapshot) {
+                        Ok(()) => println!("Snapshot: {}", path.display()),
+                        Err(e) => println!("Snapshot: {}: {}", path.display(), e),
+                    });
+                }
+            }
+            if let Some(fps) = frame.fps {
+                stats_jpeg.lock().unwrap().capture_fps = fps;
+            }
+            if show_hud {
+                let stats = *stats_jpeg.lock().unwrap();
+                hud::draw(&mut frame, &stats);
+            }
+            *canvas_jpeg.lock().unwrap() = (frame.width, frame.height);
+            let frame = Arc::new(frame);
+            for (tile_tx, _) in &tile_workers {
+                tile_tx.send(frame.clone()).expect("Send Tile Failed!");
+            }
+            let converted: Vec<_> = tile_workers.iter()
+                .map(|(_, done_rx)| done_rx.recv().expect("Tile Encode Failed!")).collect();
+            stats_jpeg.lock().unwrap().quality = converted[0].quality;
+            let _ = conv_tx.try_send(converted);
+        }
+    });
+
+    (senders, Context { rx: conv_rx, placement, stats })
+}
+
+fn encode(encoder: &mut Encoder, frame: &FrameCaptureData) -> FrameConvertedData {
+    let image = turbojpeg::Image {
+        pixels: frame.data.as_ref(),
+        width: frame.width,
+        pitch: frame.width * 4,
+        height: frame.height,
+        format: frame.pixel_format,
+    };
+    encoder.encode(image, frame.fps, frame.captured)
+}
+
+impl Sender {
+    pub fn send(&self, mut frame: FrameCaptureData) {
+        let frame_size = (frame.width, frame.height);
+        let bypass = self.bypass_resize && (frame_size == (1280, 720) || frame_size == (720, 1280));
+        match &self.resz_tx {
+            Some(resz_tx) if !bypass => {
+                let _ = resz_tx.try_send(frame);
+            }
+            _ => {
+                mask::apply(&self.masks, &mut frame);
+                if let Some(mapping) = &self.mapping {
+                    let output = Rect::new(0.0, 0.0, frame.width as f64, frame.height as f64);
+                    *mapping.lock().unwrap() = Mapping { src: frame.desktop, dst: output };
+                }
+                let _ = self.comp_tx.try_send((self.source, frame));
+            }
+        }
+    }
+}
+
+impl Placement {
+    pub fn mapping(&self) -> Mapping {
+        *self.mapping.lock().unwrap()
+    }
+
+    /// Panel coordinates of a device to the upright output picture it shows.
+    pub fn upright(&self, device: usize, (u, v): (f64, f64)) -> (f64, f64) {
+        let Some(tile) = self.tile(device) else { return (u, v) };
+        // Landscape frames reach the portrait panel rotated 90 degrees counter-clockwise
+        if tile.width > tile.height { (tile.width - v, u) } else { (u, v) }
+    }
+
+    /// Upright output coordinates of a device to desktop coordinates, `None` outside the picture.
+    pub fn to_desktop(&self, device: usize, (x, y): (f64, f64)) -> Option<(f64, f64)> {
+        let tile = self.tile(device)?;
+        self.mapping().to_source((tile.x + x, tile.y + y))
+    }
+
+    /// Upright output coordinates of a device as fractions of the picture the canvas shows.
+    pub fn in_picture(&self, device: usize, (x, y): (f64, f64)) -> Option<(f64, f64)> {
+        let tile = self.tile(device)?;
+        let dst = self.mapping().dst;
+        Some(((tile.x + x - dst.x) / dst.width, (tile.y + y - dst.y) / dst.height))
+    }
+
+    pub fn viewport(&self) -> Option<Arc<Mutex<Viewport>>> {
+        self.viewport.clone()
+    }
+
+    pub fn panel(&self) -> Option<Arc<Mutex<Deck>>> {
+        self.panel.clone()
+    }
+
+    pub fn annotation(&self) -> Option<Arc<Mutex<Annotation>>> {
+        self.annotation.clone()
+    }
+
+    /// Upright output coordinates of a device to canvas coordinates.
+    pub fn to_canvas(&self, device: usize, (x, y): (f64, f64)) -> (f64, f64) {
+        self.tile(device).map_or((x, y), |tile| (tile.x + x, tile.y + y))
+    }
+
+    /// Canvas area a device shows
+    fn tile(&self, device: usize) -> Option<Rect> {
+        match &self.tiles {
+            Some(tiles) => tiles.get(device).copied(),
+            None => {
+                let (width, height) = *self.canvas.lock().unwrap();
+                Some(Rect::new(0.0, 0.0, width as f64, height as f64))
+            }
+        }
+    }
+}
+
+impl Context {
+    /// Next frame for every device, in wall order
+    pub fn get_frames(&self) -> Vec<FrameConvertedData> {
+        self.rx.recv().expect("Recv FrameConvertedData failed!")
+    }
+
+    /// Where the captured source currently lands on the output canvas
+    pub fn mapping(&self) -> Mapping {
+        self.placement.mapping()
+    }
+
+    pub fn placement(&self) -> Placement {
+        self.placement.clone()
+    }
+
+    /// Transfer numbers of the last second, for the HUD
+    pub fn report(&self, tx_fps: usize, bitrate: usize, latency: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.tx_fps = tx_fps;
+        stats.bitrate = bitrate;
+        stats.latency = latency;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::FitMode;
+    use crate::inject::{self, Button, Event, GestureMap, Recorder, Target};
+
+    fn placement(canvas: (usize, usize), tiles: Option<Vec<Rect>>, mapping: Mapping) -> Placement {
+        Placement {
+            mapping: Arc::new(Mutex::new(mapping)),
+            canvas: Arc::new(Mutex::new(canvas)),
+            tiles,
+            viewport: None,
+            panel: None,
+            annotation: None,
+        }
+    }
+
+    /// Taps at panel coordinates `(device, u, v)` through the Touch Thread, returns what it injected.
+    fn tap(placement: Placement, taps: &[(usize, u16, u16)]) -> Vec<Event> {
+        let recorder = Recorder::default();
+        let (touch_tx, touch_rx) = mpsc::channel();
+        let target = Target::Host(GestureMap::new(&[]), Box::new(recorder.clone()));
+        let touch = thread::spawn(move || inject::run(touch_rx, placement, target));
+        for &(device, u, v) in taps {
+            touch_tx.send((device, vec![(u, v)])).unwrap();
+            touch_tx.send((device, Vec::new())).unwrap();
+        }
+        drop(touch_tx);
+        touch.join().unwrap();
+        recorder.events.lock().unwrap().clone()
+    }
+
+    // The pointer follows the finger down, then the tap clicks where it is
+    fn click(x: f64, y: f64) -> [Event; 4] {
+        [Event::Move(x, y), Event::Move(x, y), Event::Press(Button::Left), Event::Release(Button::Left)]
+    }
+
+    #[test]
+    fn rotated_touch_lands_on_second_monitor() {
+        // 2560x1440 monitor right of a 1920 wide one, landscape canvas on the portrait panel
+        let mapping = Mapping { src: Rect::new(1920.0, 0.0, 2560.0, 1440.0), dst: Rect::new(0.0, 0.0, 1280.0, 720.0) };
+        let events = tap(placement((1280, 720), None, mapping), &[(0, 360, 640), (0, 0, 1279)]);
+        assert_eq!(events, [click(3200.0, 720.0), click(1922.0, 0.0)].concat());
+    }
+
+    #[test]
+    fn letterbox_bars_do_not_move_the_pointer() {
+        // 4:3 window at (100, 50) with bars left and right of it
+        let mapping = Mapping::new(FitMode::Letterbox, Rect::new(100.0, 50.0, 1024.0, 768.0), (1280, 720));
+        let events = tap(placement((1280, 720), None, mapping), &[(0, 360, 1200), (0, 360, 640)]);
+        assert_eq!(events, [
+            vec![Event::Press(Button::Left), Event::Release(Button::Left)],
+            click(100.0 + 512.0, 50.0 + 384.0).to_vec(),
+        ].concat());
+    }
+
+    #[test]
+    fn cropped_region_maps_back_to_desktop() {
+        // Region 640x360 at (320, 180) of the desktop, stretched over the canvas
+        let mapping = Mapping::new(FitMode::Stretch, Rect::new(320.0, 180.0, 640.0, 360.0), (1280, 720));
+        let events = tap(placement((1280, 720), None, mapping), &[(0, 0, 1280 - 200)]);
+        assert_eq!(events, click(320.0 + 100.0, 180.0).to_vec());
+    }
+
+    #[test]
+    fn wall_tiles_offset_their_touches() {
+        // Two landscape tablets side by side showing one 2560x720 desktop
+        let tiles = vec![Rect::new(0.0, 0.0, 1280.0, 720.0), Rect::new(1280.0, 0.0, 1280.0, 720.0)];
+        let mapping = Mapping::identity((2560, 720));
+        let events = tap(placement((2560, 720), Some(tiles), mapping), &[(0, 100, 1180), (1, 100, 1180)]);
+        assert_eq!(events, [click(100.0, 100.0), click(1380.0, 100.0)].concat());
+    }
+}
@@ -0,0 +1,50 @@
+use crate::capture::{FrameCaptureData, color};
+use std::{io::BufWriter, path::Path};
+
+/// Writes the frame as an RGB PNG.
+pub fn write(path: &Path, frame: &FrameCaptureData) -> std::io::Result<()> {
+    let channels = color::channels(frame.pixel_format);
+    let rgb: Vec<u8> = frame.data.chunks_exact(4).take(frame.width * frame.height)
+        .flat_map(|pixel| channels.map(|c| pixel[c]))
+        .collect();
+
+    let file = BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = ::png::Encoder::new(file, frame.width as u32, frame.height as u32);
+    encoder.set_color(::png::ColorType::Rgb);
+    encoder.set_depth(::png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgb)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::fit::Rect;
+    use std::io::BufReader;
+
+    #[test]
+    fn snapshot_decodes_as_rgb() {
+        // Two BGRA pixels, red and blue
+        let frame = FrameCaptureData {
+            data: vec![0, 0, 255, 255, 255, 0, 0, 255],
+            width: 2,
+            height: 1,
+            pixel_format: turbojpeg::PixelFormat::BGRA,
+            desktop: Rect::new(0.0, 0.0, 2.0, 1.0),
+            fps: None,
+            captured: std::time::Instant::now(),
+        };
+        let path = std::env::temp_dir().join(format!("snapshot-{}.png", std::process::id()));
+        write(&path, &frame).unwrap();
+
+        let decoder = ::png::Decoder::new(BufReader::new(std::fs::File::open(&path).unwrap()));
+        let mut reader = decoder.read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut rgb).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (2, 1, ::png::ColorType::Rgb));
+        assert_eq!(rgb, [255, 0, 0, 0, 0, 255]);
+    }
+}
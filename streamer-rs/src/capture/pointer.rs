@@ -0,0 +1,121 @@
+/// Mouse cursor position in desktop coordinates, `None` when the platform does not tell.
+#[cfg(target_os = "macos")]
+pub fn position() -> Option<(f64, f64)> {
+    use objc2::{class, msg_send};
+    use objc2_core_foundation::CGPoint;
+    use objc2_core_graphics::{CGDisplayBounds, CGMainDisplayID};
+
+    // AppKit measures from the bottom left of the main display
+    let location: CGPoint = unsafe { msg_send![class!(NSEvent), mouseLocation] };
+    let main = CGDisplayBounds(CGMainDisplayID());
+    Some((location.x, main.size.height - location.y))
+}
+
+/// Whether the primary mouse button is held down.
+#[cfg(target_os = "macos")]
+pub fn pressed() -> bool {
+    use objc2::{class, msg_send};
+
+    let buttons: usize = unsafe { msg_send![class!(NSEvent), pressedMouseButtons] };
+    buttons & 1 != 0
+}
+
+#[cfg(target_os = "windows")]
+pub fn position() -> Option<(f64, f64)> {
+    use windows::Win32::{Foundation::POINT, UI::WindowsAndMessaging::GetCursorPos};
+
+    let mut point = POINT::default();
+    unsafe { GetCursorPos(&mut point) }.ok()?;
+    Some((point.x as f64, point.y as f64))
+}
+
+#[cfg(target_os = "windows")]
+pub fn pressed() -> bool {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{GetAsyncKeyState, VK_LBUTTON};
+
+    unsafe { GetAsyncKeyState(VK_LBUTTON.0 as i32) as u16 & 0x8000 != 0 }
+}
+
+#[cfg(target_os = "linux")]
+pub fn position() -> Option<(f64, f64)> {
+    xlib::query_pointer().map(|(x, y, _)| (x, y))
+}
+
+#[cfg(target_os = "linux")]
+pub fn pressed() -> bool {
+    const BUTTON1_MASK: u32 = 1 << 8;
+    xlib::query_pointer().is_some_and(|(_, _, mask)| mask & BUTTON1_MASK != 0)
+}
+
+/// Size of the whole X screen, all monitors included.
+#[cfg(target_os = "linux")]
+pub fn screen_size() -> Option<(f64, f64)> {
+    xlib::screen_size()
+}
+
+// libX11 is loaded at runtime, so Wayland-only systems still run (without a cursor position)
+#[cfg(target_os = "linux")]
+mod xlib {
+    use std::{ffi::{c_char, c_int, c_uint, c_ulong, c_void}, sync::{Mutex, OnceLock}};
+
+    type XOpenDisplay = unsafe extern "C" fn(*const c_char) -> *mut c_void;
+    type XDefaultRootWindow = unsafe extern "C" fn(*mut c_void) -> c_ulong;
+    type XDefaultScreen = unsafe extern "C" fn(*mut c_void) -> c_int;
+    type XDisplaySize = unsafe extern "C" fn(*mut c_void, c_int) -> c_int;
+    type XQueryPointer = unsafe extern "C" fn(
+        *mut c_void, c_ulong, *mut c_ulong, *mut c_ulong,
+        *mut c_int, *mut c_int, *mut c_int, *mut c_int, *mut c_uint,
+    ) -> c_int;
+
+    struct Xlib {
+        display: usize,
+        root: c_ulong,
+        query_pointer: XQueryPointer,
+        size: (c_int, c_int),
+    }
+
+    static XLIB: OnceLock<Option<Mutex<Xlib>>> = OnceLock::new();
+
+    fn open() -> Option<Mutex<Xlib>> {
+        unsafe {
+            let lib = libc::dlopen(c"libX11.so.6".as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if lib.is_null() { return None; }
+            let symbol = |name: &std::ffi::CStr| {
+                let ptr = libc::dlsym(lib, name.as_ptr());
+                (!ptr.is_null()).then_some(ptr)
+            };
+            let open_display: XOpenDisplay = std::mem::transmute(symbol(c"XOpenDisplay")?);
+            let default_root: XDefaultRootWindow = std::mem::transmute(symbol(c"XDefaultRootWindow")?);
+            let query_pointer: XQueryPointer = std::mem::transmute(symbol(c"XQueryPointer")?);
+            let default_screen: XDefaultScreen = std::mem::transmute(symbol(c"XDefaultScreen")?);
+            let display_width: XDisplaySize = std::mem::transmute(symbol(c"XDisplayWidth")?);
+            let display_height: XDisplaySize = std::mem::transmute(symbol(c"XDisplayHeight")?);
+
+            let display = open_display(std::ptr::null());
+            if display.is_null() { return None; }
+            let root = default_root(display);
+            let screen = default_screen(display);
+            let size = (display_width(display, screen), display_height(display, screen));
+            Some(Mutex::new(Xlib { display: display as usize, root, query_pointer, size }))
+        }
+    }
+
+    /// Position and the modifier / button mask
+    pub fn query_pointer() -> Option<(f64, f64, c_uint)> {
+        let xlib = XLIB.get_or_init(open).as_ref()?.lock().unwrap();
+        let (mut root, mut child): (c_ulong, c_ulong) = (0, 0);
+        let (mut x, mut y, mut win_x, mut win_y, mut mask) = (0, 0, 0, 0, 0);
+        let found = unsafe {
+            (xlib.query_pointer)(
+                xlib.display as *mut c_void, xlib.root, &mut root, &mut child,
+                &mut x, &mut y, &mut win_x, &mut win_y, &mut mask,
+            )
+        };
+        (found != 0).then_some((x as f64, y as f64, mask))
+    }
+
+    pub fn screen_size() -> Option<(f64, f64)> {
+        let xlib = XLIB.get_or_init(open).as_ref()?.lock().unwrap();
+        Some((xlib.size.0 as f64, xlib.size.1 as f64))
+    }
+}
@@ -0,0 +1,30 @@
+use std::{path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Notices edits to a file loaded at startup, looking at its modification time at most once a second.
+pub struct Watch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watch {
+    pub fn new(path: &Path) -> Self {
+        Watch { path: path.to_path_buf(), modified: modified(path), checked: Instant::now() }
+    }
+
+    /// Whether the file changed since the last time this said so, or since it was watched.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < RELOAD_INTERVAL { return false }
+        self.checked = Instant::now();
+        let modified = modified(&self.path);
+        if modified == self.modified { return false }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,178 @@
+use crate::capture::{FrameCaptureData, Options, Color, FitMode, filter::FilterChain, fit::{Mapping, Rect}, pointer, viewport::Viewport, zoom::Zoom};
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+use clap::ValueEnum;
+use fast_image_resize as fir;
+
+const BENCHMARK_SOURCES: [(usize, usize); 3] = [(1920, 1080), (2560, 1440), (3840, 2160)];
+const BENCHMARK_FRAMES: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scaler {
+    Nearest,
+    Bilinear,
+    Lanczos3,
+    /// Box filter, averages every source pixel covered by the output pixel
+    Area,
+    /// Nearest to 2x the output size, then bilinear
+    Supersample,
+}
+
+impl Scaler {
+    fn algorithm(self) -> fir::ResizeAlg {
+        match self {
+            Scaler::Nearest => fir::ResizeAlg::Nearest,
+            Scaler::Bilinear => fir::ResizeAlg::Convolution(fir::FilterType::Bilinear),
+            Scaler::Lanczos3 => fir::ResizeAlg::Convolution(fir::FilterType::Lanczos3),
+            Scaler::Area => fir::ResizeAlg::Convolution(fir::FilterType::Box),
+            Scaler::Supersample => fir::ResizeAlg::SuperSampling(fir::FilterType::Bilinear, 2),
+        }
+    }
+}
+
+pub struct Resizer {
+    resizer: fir::Resizer,
+    algorithm: fir::ResizeAlg,
+    fit: FitMode,
+    background: Color,
+    region: Option<Rect>,
+    zoom: Option<Zoom>,
+    viewport: Option<Arc<Mutex<Viewport>>>,
+    /// Fixed output size, otherwise the panel in the orientation of the source
+    output: Option<(usize, usize)>,
+    mapping: Mapping,
+}
+
+impl Resizer {
+    pub fn new(options: &Options) -> Self {
+        Resizer {
+            resizer: fir::Resizer::new(),
+            algorithm: options.scaler.algorithm(),
+            fit: options.fit,
+            background: options.background,
+            region: options.region,
+            zoom: options.zoom.map(|factor| Zoom::new(factor, options.zoom_smoothing)),
+            viewport: None,
+            output: None,
+            mapping: Mapping::identity((1280, 720)),
+        }
+    }
+
+    pub fn with_output(self, size: (usize, usize)) -> Self {
+        Resizer { output: Some(size), ..self }
+    }
+
+    pub fn with_viewport(self, viewport: Option<Arc<Mutex<Viewport>>>) -> Self {
+        Resizer { viewport, ..self }
+    }
+
+    pub fn mapping(&self) -> Mapping {
+        self.mapping
+    }
+
+    pub fn resize(&mut self, frame: &FrameCaptureData) -> FrameCaptureData {
+        let full = Rect::new(0.0, 0.0, frame.width as f64, frame.height as f64);
+        let bounds = self.region.and_then(|r| {
+            // The region is in desktop coordinates, located in the frame through `frame.desktop`
+            let mapping = Mapping { src: frame.desktop, dst: full };
+            let (x0, y0) = mapping.to_output((r.x, r.y));
+            let (x1, y1) = mapping.to_output((r.x + r.width, r.y + r.height));
+            Rect::new(x0, y0, x1 - x0, y1 - y0).intersect(&full)
+        }).unwrap_or(full);
+        let (rwidth, rheight) = match self.output {
+            Some(size) => size,
+            None if bounds.width > bounds.height => (1280, 720),
+            None => (720, 1280),
+        };
+        let source = match (&mut self.zoom, &self.viewport) {
+            (Some(zoom), _) => {
+                let desktop = frame.desktop;
+                let cursor = pointer::position().map(|(x, y)| (
+                    (x - desktop.x) * frame.width as f64 / desktop.width,
+                    (y - desktop.y) * frame.height as f64 / desktop.height,
+                ));
+                zoom.viewport(bounds, (rwidth, rheight), cursor)
+            }
+            (None, Some(viewport)) => viewport.lock().unwrap().source(bounds),
+            (None, None) => bounds,
+        };
+        let mapping = Mapping::new(self.fit, source, (rwidth, rheight));
+        let pixel_type = match frame.pixel_format {
+            turbojpeg::PixelFormat::RGBX => fir::PixelType::U8x4,
+            turbojpeg::PixelFormat::BGRA => fir::PixelType::U8x4,
+            _ => panic!("Unsupported Pixel Format!"),
+        };
+        let original = fir::images::ImageRef::new(
+            frame.width as u32, frame.height as u32, &frame.data, pixel_type
+        ).expect("Failed to create original image container");
+        let mut buffer = vec![0; rwidth * rheight * 4];
+        if mapping.dst != Mapping::identity((rwidth, rheight)).dst {
+            let color = self.background.bytes(frame.pixel_format);
+            buffer.chunks_exact_mut(4).for_each(|pixel| pixel.copy_from_slice(&color));
+        }
+        let mut resized = fir::images::Image::from_vec_u8(
+            rwidth as u32, rheight as u32, buffer, pixel_type
+        ).expect("Failed to create resized image container");
+
+        let dst = mapping.dst;
+        let mut destination = fir::images::CroppedImageMut::new(
+            &mut resized, dst.x as u32, dst.y as u32, dst.width as u32, dst.height as u32
+        ).expect("Failed to create destination view");
+        self.resizer.resize(&original, &mut destination, &fir::ResizeOptions {
+            algorithm: self.algorithm,
+            cropping: fir::SrcCropping::Crop(fir::CropBox {
+                left: mapping.src.x,
+                top: mapping.src.y,
+                width: mapping.src.width,
+                height: mapping.src.height,
+            }),
+            mul_div_alpha: false,
+        }).expect("Resize Image Failed!");
+        self.mapping = Mapping { src: mapping.src.to_desktop(&frame.desktop, (frame.width, frame.height)), dst: mapping.dst };
+
+        FrameCaptureData {
+            data: resized.into_vec(),
+            pixel_format: frame.pixel_format,
+            width: rwidth,
+            height: rheight,
+            desktop: self.mapping.output_source((rwidth, rheight)),
+            fps: frame.fps,
+            captured: frame.captured,
+        }
+    }
+}
+
+pub fn benchmark(options: &Options) {
+    println!("Resize benchmark, {} frames per source, sharpen={}", BENCHMARK_FRAMES, options.sharpen);
+    for (width, height) in BENCHMARK_SOURCES {
+        // Thin one pixel strokes, the worst case for nearest neighbour
+        let pattern: Vec<u8> = (0..width * height).flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            let v = if x % 7 == 0 || y % 5 == 0 { 0 } else { 255 };
+            [v, v, (x * 255 / width) as u8, 255]
+        }).collect();
+
+        for scaler in Scaler::value_variants() {
+            let mut resizer = Resizer::new(&Options { scaler: *scaler, ..options.clone() });
+            let mut filters = FilterChain::new(options);
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..BENCHMARK_FRAMES {
+                let frame = FrameCaptureData {
+                    data: pattern.clone(),
+                    width,
+                    height,
+                    pixel_format: turbojpeg::PixelFormat::BGRA,
+                    desktop: Rect::new(0.0, 0.0, width as f64, height as f64),
+                    fps: None,
+                    captured: Instant::now(),
+                };
+                let start = Instant::now();
+                let mut resized = resizer.resize(&frame);
+                filters.apply(&mut resized);
+                elapsed += start.elapsed();
+            }
+            let per_frame = elapsed.as_secs_f64() * 1000.0 / BENCHMARK_FRAMES as f64;
+            let name = scaler.to_possible_value().expect("Scaler name not found!");
+            println!("{}x{} {}: {:.2}ms/frame", width, height, name.get_name(), per_frame);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use crate::capture::{CaptureSource, Color, FitMode, FrameCaptureData, Options};
+use crate::capture::{compose::{self, Compose}, filter::FilterChain, fit::{Mapping, Rect}, overlay::{self, Layer, Pen}, reload::Watch, resize::Resizer};
+use std::{path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use clap::ValueEnum;
+use toml::{Table, Value};
+
+const DEFAULT_FPS: f64 = 10.0;
+/// Until the first source frame tells otherwise
+const PIXEL_FORMAT: turbojpeg::PixelFormat = turbojpeg::PixelFormat::BGRA;
+
+#[derive(Clone, Debug)]
+pub enum Content {
+    /// Source spec as written in the file, and the source
+    Capture(String, CaptureSource),
+    /// JPEG image
+    Image(PathBuf),
+    Text(String),
+    /// strftime style format
+    Clock(String),
+}
+
+/// Per-layer adjustments, unset ones are neutral.
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    pub brightness: Option<f32>,
+    pub contrast: Option<f32>,
+    pub gamma: Option<f32>,
+    pub temperature: Option<f32>,
+    pub grayscale: Option<bool>,
+    pub invert: Option<bool>,
+    pub sharpen: Option<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneLayer {
+    pub content: Content,
+    /// Canvas rectangle, text starts at its top left corner
+    pub rect: Rect,
+    pub z: i64,
+    pub fit: FitMode,
+    pub filters: Filters,
+    pub color: Color,
+    pub opacity: f32,
+    pub font_size: f32,
+}
+
+/// Layers rendered into a panel sized canvas, loaded from a TOML file.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub path: PathBuf,
+    pub size: (usize, usize),
+    /// Rate the canvas is composed at when no source frame arrives, for clocks and still scenes
+    pub fps: f64,
+    pub background: Color,
+    /// Sorted by z, bottom first
+    pub layers: Vec<SceneLayer>,
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Scene, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let table: Table = text.parse().map_err(|e| format!("{}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        let size = match table.get("size") {
+            None => (1280, 720),
+            Some(Value::Array(a)) => match a[..] {
+                [Value::Integer(w), Value::Integer(h)] if w > 0 && h > 0 => (w as usize, h as usize),
+                _ => return Err("size: expected [width, height]".to_string()),
+            },
+            Some(_) => return Err("size: expected [width, height]".to_string()),
+        };
+        let fps = match get_float(&table, "fps")? {
+            None => DEFAULT_FPS,
+            Some(fps) if fps > 0.0 => fps as f64,
+            Some(_) => return Err("fps: expected a positive number".to_string()),
+        };
+        let background = get_str(&table, "background")?.map(str::parse).transpose()?.unwrap_or(Color { r: 0, g: 0, b: 0 });
+        let fit = get_str(&table, "fit")?.map(|f| FitMode::from_str(f, true)).transpose()?.unwrap_or(FitMode::Letterbox);
+
+        let mut layers = match table.get("layer") {
+            None => Vec::new(),
+            Some(Value::Array(layers)) => layers.iter().enumerate().map(|(i, layer)| {
+                let Value::Table(layer) = layer else { return Err(format!("layer {}: expected a table", i + 1)) };
+                parse_layer(layer, base, fit, size).map_err(|e| format!("layer {}: {}", i + 1, e))
+            }).collect::<Result<Vec<_>, String>>()?,
+            Some(_) => return Err("layer: expected [[layer]] tables".to_string()),
+        };
+        layers.sort_by_key(|layer| layer.z);
+        Ok(Scene { path: path.to_path_buf(), size, fps, background, layers })
+    }
+
+    /// Capture sources in order of first use, each captured once however many layers show it.
+    pub fn sources(&self) -> Vec<(String, CaptureSource)> {
+        let mut sources: Vec<(String, CaptureSource)> = Vec::new();
+        for layer in &self.layers {
+            if let Content::Capture(spec, source) = &layer.content && !sources.iter().any(|(s, _)| s == spec) {
+                sources.push((spec.clone(), source.clone()));
+            }
+        }
+        sources
+    }
+}
+
+fn parse_layer(layer: &Table, base: &Path, fit: FitMode, size: (usize, usize)) -> Result<SceneLayer, String> {
+    let content = if let Some(display) = get_int(layer, "display")? {
+        let spec = format!("display={}", display);
+        Content::Capture(spec.clone(), spec.parse()?)
+    } else if let Some(title) = get_str(layer, "window")? {
+        let spec = format!("window={}", title);
+        Content::Capture(spec.clone(), spec.parse()?)
+    } else if let Some(pid) = get_int(layer, "pid")? {
+        let spec = format!("pid={}", pid);
+        Content::Capture(spec.clone(), spec.parse()?)
+    } else if let Some(image) = get_str(layer, "image")? {
+        Content::Image(base.join(image))
+    } else if let Some(text) = get_str(layer, "text")? {
+        Content::Text(text.to_string())
+    } else if let Some(format) = get_str(layer, "clock")? {
+        Content::Clock(overlay::check_clock(format)?.to_string())
+    } else {
+        return Err("expected one of display, window, pid, image, text or clock".to_string());
+    };
+
+    let rect = match layer.get("rect") {
+        Some(Value::String(rect)) => rect.parse()?,
+        Some(Value::Array(values)) => {
+            let values: Vec<f64> = values.iter().filter_map(number).collect();
+            match values[..] {
+                [x, y, width, height] if width > 0.0 && height > 0.0 => Rect::new(x, y, width, height),
+                _ => return Err("rect: expected [x, y, width, height]".to_string()),
+            }
+        }
+        _ => return Err("rect: expected [x, y, width, height]".to_string()),
+    };
+    let (width, height) = (size.0 as f64, size.1 as f64);
+    if rect.width <= 0.0 || rect.height <= 0.0 || rect.x < 0.0 || rect.y < 0.0 || rect.x + rect.width > width || rect.y + rect.height > height {
+        return Err(format!("rect: expected a positive size within the {}x{} canvas", size.0, size.1));
+    }
+
+    let filters = match layer.get("filters") {
+        None => Filters::default(),
+        Some(Value::Table(f)) => Filters {
+            brightness: get_float(f, "brightness")?,
+            contrast: get_float(f, "contrast")?,
+            gamma: get_float(f, "gamma")?,
+            temperature: get_float(f, "temperature")?,
+            grayscale: get_bool(f, "grayscale")?,
+            invert: get_bool(f, "invert")?,
+            sharpen: get_float(f, "sharpen")?,
+        },
+        Some(_) => return Err("filters: expected a table".to_string()),
+    };
+
+    Ok(SceneLayer {
+        content,
+        rect,
+        z: get_int(layer, "z")?.unwrap_or(0),
+        fit: get_str(layer, "fit")?.map(|f| FitMode::from_str(f, true)).transpose()?.unwrap_or(fit),
+        filters,
+        color: get_str(layer, "color")?.map(str::parse).transpose()?.unwrap_or(Color { r: 255, g: 255, b: 255 }),
+        opacity: get_float(layer, "opacity")?.unwrap_or(1.0),
+        font_size: get_float(layer, "font_size")?.unwrap_or(rect.height as f32),
+    })
+}
+
+pub fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+pub fn get_str<'a>(table: &'a Table, key: &str) -> Result<Option<&'a str>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(format!("{}: expected a string", key)),
+    }
+}
+
+pub fn get_int(table: &Table, key: &str) -> Result<Option<i64>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::Integer(i)) => Ok(Some(*i)),
+        Some(_) => Err(format!("{}: expected an integer", key)),
+    }
+}
+
+pub fn get_float(table: &Table, key: &str) -> Result<Option<f32>, String> {
+    table.get(key).map(|v| number(v).map(|n| n as f32).ok_or(format!("{}: expected a number", key))).transpose()
+}
+
+pub fn get_bool(table: &Table, key: &str) -> Result<Option<bool>, String> {
+    table.get(key).map(|v| v.as_bool().ok_or(format!("{}: expected true or false", key))).transpose()
+}
+
+enum Draw {
+    Capture { source: usize, resizer: Box<Resizer>, filters: FilterChain, tile: Option<FrameCaptureData> },
+    Still(Layer),
+    Clock { format: String, text: String, layer: Layer },
+}
+
+struct LayerState {
+    rect: Rect,
+    pen: Pen,
+    draw: Draw,
+}
+
+/// Renders the scene on every source frame and at the scene rate, and reloads the file when it changes.
+pub struct SceneComposer {
+    scene: Scene,
+    options: Options,
+    /// Sources the backends capture, fixed at startup
+    sources: Vec<String>,
+    latest: Vec<Option<FrameCaptureData>>,
+    layers: Vec<LayerState>,
+    mapping: Arc<Mutex<Mapping>>,
+    watch: Watch,
+    /// Of the last source frame, the canvas matches it
+    pixel_format: turbojpeg::PixelFormat,
+}
+
+impl SceneComposer {
+    pub fn new(scene: Scene, options: &Options, mapping: Arc<Mutex<Mapping>>) -> Self {
+        let sources: Vec<String> = scene.sources().into_iter().map(|(spec, _)| spec).collect();
+        let mut composer = SceneComposer {
+            watch: Watch::new(&scene.path),
+            latest: sources.iter().map(|_| None).collect(),
+            sources,
+            scene,
+            options: options.clone(),
+            layers: Vec::new(),
+            mapping,
+            pixel_format: PIXEL_FORMAT,
+        };
+        composer.build();
+        composer
+    }
+
+    fn build(&mut self) {
+        let font = overlay::load_font(self.options.font.as_deref());
+        self.layers = self.scene.layers.iter().filter_map(|layer| {
+            let pen = Pen::new(font.clone(), layer.font_size, layer.color, layer.opacity);
+            let size = (layer.rect.width.round() as usize, layer.rect.height.round() as usize);
+            let draw = match &layer.content {
+                Content::Capture(spec, _) => {
+                    let Some(source) = self.sources.iter().position(|s| s == spec) else {
+                        println!("Scene: {} is not captured, restart to show it", spec);
+                        return None;
+                    };
+                    let options = self.layer_options(layer);
+                    let resizer = Box::new(Resizer::new(&options).with_output(size));
+                    Draw::Capture { source, resizer, filters: FilterChain::new(&options), tile: None }
+                }
+                Content::Image(path) => match overlay::load_image(path, Some(size)) {
+                    Ok(image) => Draw::Still(image),
+                    Err(e) => {
+                        println!("Scene: {}", e);
+                        return None;
+                    }
+                },
+                Content::Text(text) => Draw::Still(pen.render(text)),
+                Content::Clock(format) => Draw::Clock { format: format.clone(), text: String::new(), layer: pen.render("") },
+            };
+            Some(LayerState { rect: layer.rect, pen, draw })
+        }).collect();
+
+        // Sources do not resend unchanged screens, fill the new layers from the last frames
+        for source in 0..self.latest.len() {
+            self.refresh(source);
+        }
+    }
+
+    fn layer_options(&self, layer: &SceneLayer) -> Options {
+        let f = &layer.filters;
+        Options {
+            fit: layer.fit,
+            background: self.scene.background,
+            brightness: f.brightness.unwrap_or(0.0),
+            contrast: f.contrast.unwrap_or(1.0),
+            gamma: f.gamma.unwrap_or(1.0),
+            temperature: f.temperature.unwrap_or(6500.0),
+            grayscale: f.grayscale.unwrap_or(false),
+            invert: f.invert.unwrap_or(false),
+            sharpen: f.sharpen.unwrap_or(0.0),
+            ..self.options.pip()
+        }
+    }
+
+    fn reload(&mut self) {
+        if !self.watch.changed() { return }
+        match Scene::load(&self.scene.path) {
+            Ok(scene) => {
+                println!("Scene Reloaded: {}", scene.path.display());
+                self.scene = scene;
+                self.build();
+            }
+            Err(e) => println!("Scene: {}, keeping the previous one", e),
+        }
+    }
+
+    // Scales the last frame of `source` into every layer that shows it
+    fn refresh(&mut self, source: usize) {
+        let Some(frame) = &self.latest[source] else { return };
+        let mut primary = true;
+        for layer in &mut self.layers {
+            let Draw::Capture { source: s, resizer, filters, tile } = &mut layer.draw else { continue };
+            if *s != source {
+                primary = false;
+                continue;
+            }
+            let mut resized = resizer.resize(frame);
+            filters.apply(&mut resized);
+            *tile = Some(resized);
+            // The bottom capture layer is the one touch and the cursor refer to
+            if primary {
+                let m = resizer.mapping();
+                let dst = Rect::new(m.dst.x + layer.rect.x, m.dst.y + layer.rect.y, m.dst.width, m.dst.height);
+                *self.mapping.lock().unwrap() = Mapping { src: m.src, dst };
+                primary = false;
+            }
+        }
+    }
+}
+
+impl Compose for SceneComposer {
+    fn update(&mut self, source: usize, mut frame: FrameCaptureData) -> Option<FrameCaptureData> {
+        self.reload();
+        let (fps, captured) = (frame.fps.take(), frame.captured);
+        self.pixel_format = frame.pixel_format;
+        self.latest[source] = Some(frame);
+        self.refresh(source);
+        Some(self.render(if source == 0 { fps } else { None }, captured))
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f64(1.0 / self.scene.fps))
+    }
+
+    fn tick(&mut self) -> Option<FrameCaptureData> {
+        self.reload();
+        Some(self.render(None, Instant::now()))
+    }
+}
+
+impl SceneComposer {
+    // Layers bottom first onto the background, captures from their last frames
+    fn render(&mut self, fps: Option<usize>, captured: Instant) -> FrameCaptureData {
+        let (width, height) = self.scene.size;
+        let color = self.scene.background.bytes(self.pixel_format);
+        let mut canvas = FrameCaptureData {
+            data: color.repeat(width * height),
+            width,
+            height,
+            pixel_format: self.pixel_format,
+            desktop: self.mapping.lock().unwrap().output_source((width, height)),
+            fps,
+            captured,
+        };
+
+        let now = chrono::Local::now();
+        for layer in &mut self.layers {
+            let position = (layer.rect.x.round() as isize, layer.rect.y.round() as isize);
+            match &mut layer.draw {
+                Draw::Capture { tile: Some(tile), .. } => compose::paste(&mut canvas, tile, position),
+                Draw::Capture { tile: None, .. } => {}
+                Draw::Still(still) => layer.pen.draw(&mut canvas, still, position),
+                Draw::Clock { format, text, layer: rendered } => {
+                    let current = now.format(format).to_string();
+                    if current != *text {
+                        *rendered = layer.pen.render(&current);
+                        *text = current;
+                    }
+                    layer.pen.draw(&mut canvas, rendered, position);
+                }
+            }
+        }
+        canvas
+    }
+}
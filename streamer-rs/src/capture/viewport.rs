@@ -0,0 +1,109 @@
+use crate::capture::fit::Rect;
+use std::time::Instant;
+
+/// Largest magnification a pinch reaches, relative to the full view
+const MAX_SCALE: f64 = 16.0;
+/// A fling slows down by e every 1/FRICTION seconds
+const FRICTION: f64 = 4.0;
+/// Below this speed, in views per second, a fling has come to rest
+const REST: f64 = 0.01;
+
+/// Part of the captured frame the touch screen pans and zooms, cropped out by the resize stage.
+pub struct Viewport {
+    /// 1.0 shows everything, 2.0 half the width and height
+    scale: f64,
+    /// Centre as a fraction of the frame
+    center: (f64, f64),
+    /// Fling speed in frame fractions per second
+    velocity: (f64, f64),
+    moved: Instant,
+    /// Panned, zoomed or reset since the crop was last taken
+    changed: bool,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport { scale: 1.0, center: (0.5, 0.5), velocity: (0.0, 0.0), moved: Instant::now(), changed: false }
+    }
+}
+
+impl Viewport {
+    /// Moves the picture with the fingers, `delta` in fractions of the shown picture.
+    pub fn pan(&mut self, delta: (f64, f64)) {
+        self.velocity = (0.0, 0.0);
+        self.center = (self.center.0 - delta.0 / self.scale, self.center.1 - delta.1 / self.scale);
+        self.changed = true;
+        self.clamp();
+    }
+
+    /// Keeps the picture moving after the fingers lift, `velocity` in shown pictures per second.
+    pub fn fling(&mut self, velocity: (f64, f64)) {
+        self.velocity = (-velocity.0 / self.scale, -velocity.1 / self.scale);
+        self.moved = Instant::now();
+    }
+
+    /// Magnifies by `factor` around `focus`, a fraction of the shown picture that stays put.
+    pub fn zoom(&mut self, factor: f64, focus: (f64, f64)) {
+        let scale = (self.scale * factor).clamp(1.0, MAX_SCALE);
+        let offset = (focus.0 - 0.5, focus.1 - 0.5);
+        self.center = (
+            self.center.0 + offset.0 / self.scale - offset.0 / scale,
+            self.center.1 + offset.1 / self.scale - offset.1 / scale,
+        );
+        self.scale = scale;
+        self.velocity = (0.0, 0.0);
+        self.changed = true;
+        self.clamp();
+    }
+
+    /// A finger landing catches a fling.
+    pub fn stop(&mut self) {
+        self.velocity = (0.0, 0.0);
+    }
+
+    /// Back to the full view.
+    pub fn reset(&mut self) {
+        *self = Viewport { changed: true, ..Viewport::default() };
+    }
+
+    /// Whether the crop changed since it was last taken or a fling carries it on, the picture
+    /// has to be resized again even when no new frame comes.
+    pub fn moving(&self) -> bool {
+        self.changed || self.velocity != (0.0, 0.0)
+    }
+
+    /// The crop of `bounds` to show now, carrying on a fling.
+    pub fn source(&mut self, bounds: Rect) -> Rect {
+        self.changed = false;
+        if self.velocity != (0.0, 0.0) {
+            let elapsed = self.moved.elapsed().as_secs_f64();
+            let decay = (-FRICTION * elapsed).exp();
+            // Distance covered while the speed decays exponentially
+            let travel = (1.0 - decay) / FRICTION;
+            self.center = (self.center.0 + self.velocity.0 * travel, self.center.1 + self.velocity.1 * travel);
+            self.velocity = (self.velocity.0 * decay, self.velocity.1 * decay);
+            if self.velocity.0.hypot(self.velocity.1) * self.scale < REST {
+                self.velocity = (0.0, 0.0);
+            }
+            self.moved = Instant::now();
+            self.clamp();
+        }
+        let (width, height) = (bounds.width / self.scale, bounds.height / self.scale);
+        Rect::new(
+            bounds.x + self.center.0 * bounds.width - width / 2.0,
+            bounds.y + self.center.1 * bounds.height - height / 2.0,
+            width,
+            height,
+        )
+    }
+
+    /// Keeps the crop inside the frame, a fling stops at the edge it hits.
+    fn clamp(&mut self) {
+        let half = 0.5 / self.scale;
+        let x = self.center.0.clamp(half, 1.0 - half);
+        let y = self.center.1.clamp(half, 1.0 - half);
+        if x != self.center.0 { self.velocity.0 = 0.0 }
+        if y != self.center.1 { self.velocity.1 = 0.0 }
+        self.center = (x, y);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::capture::{FrameCaptureData, fit::Rect};
+use std::str::FromStr;
+
+const PANEL: (usize, usize) = (1280, 720);
+/// Active area of the Tab5 panel in millimetres
+const PANEL_MM: (f64, f64) = (110.0, 62.0);
+
+/// Grid of tablets showing one canvas, the gaps between panels are part of the canvas but never shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wall {
+    pub cols: usize,
+    pub rows: usize,
+    /// Gap between neighbouring active areas in canvas pixels
+    gap: (usize, usize),
+}
+
+impl FromStr for Wall {
+    type Err = String;
+
+    /// `COLSxROWS`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (cols, rows) = s.split_once('x').ok_or_else(|| format!("expected COLSxROWS: {}", s))?;
+        let cols = cols.parse().map_err(|_| format!("invalid columns: {}", cols))?;
+        let rows = rows.parse().map_err(|_| format!("invalid rows: {}", rows))?;
+        if cols == 0 || rows == 0 {
+            return Err(format!("empty wall: {}", s));
+        }
+        Ok(Wall { cols, rows, gap: (0, 0) })
+    }
+}
+
+impl Wall {
+    /// Bezel compensation, `mm` is the distance from the edge of one active area to the next.
+    pub fn with_bezel(self, mm: f64) -> Self {
+        let gap = |panel: usize, panel_mm: f64| (mm.max(0.0) * panel as f64 / panel_mm).round() as usize;
+        Wall { gap: (gap(PANEL.0, PANEL_MM.0), gap(PANEL.1, PANEL_MM.1)), ..self }
+    }
+
+    pub fn count(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    pub fn canvas_size(&self) -> (usize, usize) {
+        (
+            self.cols * PANEL.0 + (self.cols - 1) * self.gap.0,
+            self.rows * PANEL.1 + (self.rows - 1) * self.gap.1,
+        )
+    }
+
+    /// Canvas area shown by each tablet, row by row.
+    pub fn tiles(&self) -> Vec<Rect> {
+        (0..self.count()).map(|i| {
+            let (col, row) = (i % self.cols, i / self.cols);
+            let x = col * (PANEL.0 + self.gap.0);
+            let y = row * (PANEL.1 + self.gap.1);
+            Rect::new(x as f64, y as f64, PANEL.0 as f64, PANEL.1 as f64)
+        }).collect()
+    }
+
+    /// Copies one tile out of a wall canvas.
+    pub fn slice(frame: &FrameCaptureData, tile: &Rect) -> FrameCaptureData {
+        let (x, y, width, height) = (tile.x as usize, tile.y as usize, tile.width as usize, tile.height as usize);
+        let mut data = Vec::with_capacity(width * height * 4);
+        for row in frame.data.chunks_exact(frame.width * 4).skip(y).take(height) {
+            data.extend_from_slice(&row[x * 4..(x + width) * 4]);
+        }
+        FrameCaptureData {
+            data,
+            width,
+            height,
+            pixel_format: frame.pixel_format,
+            desktop: tile.to_desktop(&frame.desktop, (frame.width, frame.height)),
+            fps: frame.fps,
+            captured: frame.captured,
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use regex::Regex;
+
+#[derive(Clone, Debug)]
+pub enum WindowSelector {
+    Title(Regex),
+    Pid(u32),
+}
+
+impl WindowSelector {
+    pub fn matches(&self, title: &str, pid: Option<u32>) -> bool {
+        match self {
+            WindowSelector::Title(regex) => regex.is_match(title),
+            WindowSelector::Pid(p) => pid == Some(*p),
+        }
+    }
+}
+
+impl std::fmt::Display for WindowSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WindowSelector::Title(regex) => write!(f, "title=/{}/", regex),
+            WindowSelector::Pid(pid) => write!(f, "pid={}", pid),
+        }
+    }
+}
@@ -1,143 +1,135 @@
-use crate::capture::FrameConvertedData;
-use std::{sync::mpsc::{self, SyncSender}, thread, time::Duration};
+use crate::capture::{CaptureSource, FrameCaptureData, Context, Options, WindowSelector, fit::{Mapping, Rect}, mask, pipeline};
+use std::{thread, time::Duration};
 use windows_capture::{
     capture::GraphicsCaptureApiHandler,
-    monitor::Monitor, settings::Settings,
+    monitor::Monitor, settings::Settings, window::Window,
+};
+use ::windows::Win32::{
+    Foundation::{HWND, RECT},
+    Graphics::Gdi::{GetMonitorInfoW, HMONITOR, MONITORINFO},
+    UI::WindowsAndMessaging::{GetWindowRect, IsIconic},
 };
-use fast_image_resize as fir;
-
-const BUF_SIZE: usize = 512 * 1024;
-const JPEG_QUALITY_LEVELS: [i32; 4] = [40, 60, 70, 80];
-
-pub struct FrameCaptureData {
-    pub data: Vec<u8>,
-    pub width: usize,
-    pub height: usize,
-    pub fps: Option<usize>,
-}
-
-pub struct Context {
-    rx: mpsc::Receiver<FrameConvertedData>,
-}
 
-pub fn start<F>(display_index: Option<usize>, tx_thread: F)
-where
-    F: FnOnce(Context) + Send + 'static,
-{
-    let (resz_tx, resz_rx) = mpsc::sync_channel::<FrameCaptureData>(1);
-    let (jpeg_tx, jpeg_rx) = mpsc::sync_channel::<FrameCaptureData>(1);
-    let (conv_tx, conv_rx) = mpsc::sync_channel::<FrameConvertedData>(1);
-
-    // Capture Thread
-    let jpeg_tx_capture = jpeg_tx.clone();
-    thread::spawn(move || {
-        let display = Monitor::primary().expect("Display not found.");
-        let settings = Settings::new(
-            display,
-            windows_capture::settings::CursorCaptureSettings::Default,
+macro_rules! capture_settings {
+    ($item:expr, $cursor:expr, $sender:expr) => {
+        Settings::new(
+            $item,
+            // The pipeline draws its own cursor with --cursor
+            if $cursor {
+                windows_capture::settings::CursorCaptureSettings::WithoutCursor
+            } else {
+                windows_capture::settings::CursorCaptureSettings::Default
+            },
             windows_capture::settings::DrawBorderSettings::WithoutBorder,
             windows_capture::settings::SecondaryWindowSettings::Default,
             windows_capture::settings::MinimumUpdateIntervalSettings::Default,
             windows_capture::settings::DirtyRegionSettings::Default,
             windows_capture::settings::ColorFormat::Bgra8,
-            (resz_tx, jpeg_tx_capture)
-        );
-        StreamOutput::start(settings).expect("Start windows-capture failed!");
-    });
-
-    // Resize Thread
-    thread::spawn(move || {
-        let mut resizer = fir::Resizer::new();
-        for frame in resz_rx {
-            let (rwidth, rheight) = if frame.width > frame.height { (1280, 720) } else { (720, 1280) };
-            let original = fir::images::Image::from_vec_u8(
-                frame.width as u32, frame.height as u32, frame.data, fir::PixelType::U8x4
-            ).expect("Failed to create original image container");
-            let mut resized = fir::images::Image::from_vec_u8(
-                rwidth as u32, rheight as u32, vec![0; rwidth * rheight * 4], fir::PixelType::U8x4
-            ).expect("Failed to create resized image container");
-
-            resizer.resize(&original, &mut resized, &fir::ResizeOptions {
-                algorithm: fir::ResizeAlg::Nearest,
-                cropping: fir::SrcCropping::None,
-                mul_div_alpha: false,
-            }).expect("Resize Image Failed!");
-            let data = FrameCaptureData { data: resized.into_vec(), width: rwidth, height: rheight, fps: frame.fps };
-            let _ = jpeg_tx.try_send(data);
+            $sender
+        )
+    };
+}
+
+pub fn start<F>(options: Options, tx_thread: F)
+where
+    F: FnOnce(Context) + Send + 'static,
+{
+    let (senders, context) = pipeline::start(&options);
+
+    // Capture Threads, one per source
+    for ((source, options), sender) in options.sources().into_iter().zip(senders) {
+        thread::spawn(move || capture(source, options, sender));
+    }
+
+    tx_thread(context);
+}
+
+fn capture(source: CaptureSource, options: Options, sender: pipeline::Sender) {
+    let selector = match source {
+        CaptureSource::Display(index) => {
+            let display = match index {
+                Some(i) => Monitor::from_index(i + 1),
+                None => Monitor::primary(),
+            }.expect("Display not found.");
+            // Graphics Capture can not leave windows out, excluded ones are blacked out instead
+            let monitor = monitor_rect(&display);
+            StreamOutput::start(capture_settings!(display, options.cursor, (sender, options.exclude_windows, monitor, None)))
+                .expect("Start windows-capture failed!");
+            return;
         }
-    });
-
-    // JPEG Encode Thread
-    thread::spawn(move || {
-        let mut compressor = turbojpeg::Compressor::new().expect("Failed to create turbojpeg Compressor");
-        let mut transformer = turbojpeg::Transformer::new().expect("Failed to create turbojpeg Transformer");
-
-        let mut quality_level: usize = 0;
-        compressor.set_quality(JPEG_QUALITY_LEVELS[0]).expect("set jpeg quality failed!");
-        compressor.set_optimize(false).expect("set jpeg optimize failed!");
-        compressor.set_subsamp(turbojpeg::Subsamp::Sub2x2).expect("set jpeg subsamp failed!");
-
-        let mut compress_buffer: [u8; BUF_SIZE] = [0; BUF_SIZE];
-        let mut last = std::time::Instant::now();
-
-        for frame in jpeg_rx {
-            let image = turbojpeg::Image {
-                pixels: frame.data.as_ref(),
-                width: 1280,
-                pitch: frame.width * 4,
-                height: 720,
-                format: turbojpeg::PixelFormat::BGRA,
-            };
-
-            let mut converted = unsafe { Box::<[u8]>::new_uninit_slice(BUF_SIZE).assume_init() };
-            let size = if frame.width > frame.height { // need rotate
-                compressor.compress_to_slice(image, &mut compress_buffer)
-                    .expect("JPEG Encode Failed!");
-
-                let transform = turbojpeg::Transform::op(turbojpeg::TransformOp::Rot270);
-                // transform.optimize = true;
-                transformer.transform_to_slice(&transform, &compress_buffer, &mut converted[4..])
-                    .expect("JPEG Rotate Failed!")
-            } else {
-                compressor.compress_to_slice(image, &mut converted[4..])
-                    .expect("JPEG Encode Failed!")
-            };
-
-            let size = size + 4;
-            let bytes = (size as u32).to_le_bytes();
-            converted[..4].copy_from_slice(&bytes);
-            let data = FrameConvertedData { data: converted, data_size: size, quality: JPEG_QUALITY_LEVELS[quality_level], fps: frame.fps };
-            let _ = conv_tx.try_send(data);
-
-            let tx_speed = (size as f64) / last.elapsed().as_secs_f64();
-            if quality_level > 0 && tx_speed > 7e6 {
-                quality_level -= 1;
-                compressor.set_quality(JPEG_QUALITY_LEVELS[quality_level]).expect("set jpeg quality failed!");
-            } else if quality_level + 1 < JPEG_QUALITY_LEVELS.len() && tx_speed < 4e6 {
-                quality_level += 1;
-                compressor.set_quality(JPEG_QUALITY_LEVELS[quality_level]).expect("set jpeg quality failed!");
-            }
-            last = std::time::Instant::now();
+        CaptureSource::Window(selector) => selector,
+    };
+    loop {
+        let Some(window) = find_window(&selector) else {
+            thread::sleep(Duration::from_secs(1));
+            continue;
+        };
+        println!("Capturing Window: {}", window.title().unwrap_or_default());
+        // Returns when the window is closed, then wait for it to be reopened
+        if let Err(e) = StreamOutput::start(capture_settings!(window, options.cursor, (sender.clone(), Vec::new(), window_rect(&window), Some(window)))) {
+            println!("Window capture failed: {}", e);
         }
-    });
+        println!("Window closed, waiting for {}...", selector);
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+fn find_window(selector: &WindowSelector) -> Option<Window> {
+    Window::enumerate().ok()?.into_iter().find(|w| window_matches(w, selector))
+}
+
+fn window_matches(window: &Window, selector: &WindowSelector) -> bool {
+    selector.matches(&window.title().unwrap_or_default(), window.process_id().ok())
+}
+
+/// The monitor's area of the virtual screen, which starts left of or above the primary monitor when others are there.
+fn monitor_rect(monitor: &Monitor) -> Option<Rect> {
+    let mut info = MONITORINFO { cbSize: std::mem::size_of::<MONITORINFO>() as u32, ..Default::default() };
+    unsafe { GetMonitorInfoW(HMONITOR(monitor.as_raw_hmonitor()), &mut info) }.ok().ok()?;
+    let r = info.rcMonitor;
+    Some(Rect::new(r.left as f64, r.top as f64, (r.right - r.left) as f64, (r.bottom - r.top) as f64))
+}
+
+/// The window's area of the virtual screen, `None` while it is minimised.
+fn window_rect(window: &Window) -> Option<Rect> {
+    let hwnd = HWND(window.as_raw_hwnd());
+    let mut rect = RECT::default();
+    unsafe {
+        if IsIconic(hwnd).as_bool() { return None }
+        GetWindowRect(hwnd, &mut rect).ok()?;
+    }
+    Some(Rect::new(rect.left as f64, rect.top as f64, (rect.right - rect.left) as f64, (rect.bottom - rect.top) as f64))
+}
 
-    tx_thread(Context { rx: conv_rx });
+/// Rects of the excluded windows in virtual screen coordinates.
+fn excluded_rects(selectors: &[WindowSelector]) -> Vec<Rect> {
+    let Ok(windows) = Window::enumerate() else { return Vec::new() };
+    windows.into_iter().filter(|w| selectors.iter().any(|s| window_matches(w, s))).filter_map(|w| window_rect(&w)).collect()
 }
 
 struct StreamOutput {
-    resz_tx: mpsc::SyncSender<FrameCaptureData>,
-    jpeg_tx: mpsc::SyncSender<FrameCaptureData>,
+    sender: pipeline::Sender,
+    exclude: Vec<WindowSelector>,
+    /// Captured monitor or window on the virtual screen, what the excluded rects and touches are relative to
+    location: Option<Rect>,
+    /// Captured window, its rect is looked up again as it moves
+    window: Option<Window>,
+    located: std::time::Instant,
     frames: usize,
     start: std::time::Instant,
 }
 impl GraphicsCaptureApiHandler for StreamOutput {
-    type Flags = (SyncSender<FrameCaptureData>, SyncSender<FrameCaptureData>);
+    type Flags = (pipeline::Sender, Vec<WindowSelector>, Option<Rect>, Option<Window>);
     type Error = Box<dyn std::error::Error + Send + Sync>;
 
     fn new(ctx: windows_capture::capture::Context<Self::Flags>) -> Result<Self, Self::Error> {
+        let (sender, exclude, location, window) = ctx.flags;
         Ok(Self {
-            resz_tx: ctx.flags.0,
-            jpeg_tx: ctx.flags.1,
+            sender,
+            exclude,
+            location,
+            window,
+            located: std::time::Instant::now(),
             frames: 0,
             start: std::time::Instant::now(),
         })
@@ -149,8 +141,33 @@ impl GraphicsCaptureApiHandler for StreamOutput {
         _capture_control: windows_capture::graphics_capture_api::InternalCaptureControl,
     ) -> Result<(), Self::Error> {
         let mut frame_buffer = frame.buffer()?;
-        let data = frame_buffer.as_raw_buffer().to_vec();
-        let size = (frame_buffer.width(), frame_buffer.height());
+        // Window widths are arbitrary, so rows may be padded
+        let mut buffer = Vec::new();
+        let mut data = frame_buffer.as_nopadding_buffer(&mut buffer).to_vec();
+        let (width, height) = (frame_buffer.width() as usize, frame_buffer.height() as usize);
+
+        // Follow the captured window as it moves
+        if self.located.elapsed() >= Duration::from_secs(1) {
+            if let Some(window) = &self.window {
+                self.location = window_rect(window).or(self.location);
+            }
+            self.located = std::time::Instant::now();
+        }
+
+        let frame_rect = Rect::new(0.0, 0.0, width as f64, height as f64);
+        let desktop = self.location.unwrap_or(frame_rect);
+
+        if !self.exclude.is_empty() {
+            let mapping = Mapping { src: desktop, dst: frame_rect };
+            // Looked up for every frame, so an excluded window never shows while it moves or opens
+            for rect in excluded_rects(&self.exclude) {
+                // Only the part on this monitor, moved to its pixels
+                let Some(rect) = rect.intersect(&desktop) else { continue };
+                let (x0, y0) = mapping.to_output((rect.x, rect.y));
+                let (x1, y1) = mapping.to_output((rect.x + rect.width, rect.y + rect.height));
+                mask::fill(&mut data, width, height, Rect::new(x0, y0, x1 - x0, y1 - y0), [0, 0, 0, 255]);
+            }
+        }
 
         self.frames += 1;
         let fps = if self.start.elapsed() >= Duration::from_secs(1) {
@@ -162,23 +179,15 @@ impl GraphicsCaptureApiHandler for StreamOutput {
             None
         };
 
-        let captured = FrameCaptureData {
+        self.sender.send(FrameCaptureData {
             data,
-            width: frame_buffer.width() as usize,
-            height: frame_buffer.height() as usize,
+            width,
+            height,
+            pixel_format: turbojpeg::PixelFormat::BGRA,
+            desktop,
             fps,
-        };
-        if size == (1280, 720) || size == (720, 1280) {
-            let _ = self.jpeg_tx.try_send(captured);
-        } else {
-            let _ = self.resz_tx.try_send(captured);
-        }
+            captured: std::time::Instant::now(),
+        });
         Ok(())
     }
 }
-
-impl Context {
-    pub fn get_frame(&self) -> FrameConvertedData {
-        self.rx.recv().expect("Recv FrameConvertedData failed!")
-    }
-}
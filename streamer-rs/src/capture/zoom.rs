@@ -0,0 +1,32 @@
+use crate::capture::fit::Rect;
+
+/// Viewport that follows the cursor, cropped out of the captured frame by the resize stage.
+pub struct Zoom {
+    factor: f64,
+    smoothing: f64,
+    center: Option<(f64, f64)>,
+}
+
+impl Zoom {
+    /// `factor` 1.0 shows the desktop 1:1 on the panel, `smoothing` 1.0 jumps straight to the cursor.
+    pub fn new(factor: f64, smoothing: f64) -> Self {
+        Zoom { factor, smoothing, center: None }
+    }
+
+    pub fn viewport(&mut self, bounds: Rect, output: (usize, usize), cursor: Option<(f64, f64)>) -> Rect {
+        let width = (output.0 as f64 / self.factor).min(bounds.width);
+        let height = (output.1 as f64 / self.factor).min(bounds.height);
+
+        let target = cursor.unwrap_or((bounds.x + bounds.width / 2.0, bounds.y + bounds.height / 2.0));
+        let (cx, cy) = match self.center {
+            Some((cx, cy)) => (cx + (target.0 - cx) * self.smoothing, cy + (target.1 - cy) * self.smoothing),
+            None => target,
+        };
+
+        // Clamp to the edges, and keep the smoothed centre clamped so leaving an edge is immediate
+        let x = (cx - width / 2.0).clamp(bounds.x, bounds.x + bounds.width - width);
+        let y = (cy - height / 2.0).clamp(bounds.y, bounds.y + bounds.height - height);
+        self.center = Some((x + width / 2.0, y + height / 2.0));
+        Rect::new(x, y, width, height)
+    }
+}
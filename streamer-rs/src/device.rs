@@ -0,0 +1,242 @@
+use std::{sync::{mpsc, Arc, Mutex}, thread, time::Duration};
+use rusb::{DeviceHandle, GlobalContext};
+use crate::{capture::Subsampling, mux::{Channel, Demuxer, Muxer}};
+
+pub const VID: u16 = 0x303a;
+pub const PID: u16 = 0x4020;
+pub const EP_OUT: u8 = 0x01;
+pub const EP_IN: u8 = 0x81;
+
+// Packets start with their total size and a kind, old firmware only knows size + JPEG.
+// Reports on the IN endpoint start with the kind. With the mux on, both go in `mux` fragments.
+pub const PACKET_FRAME: u32 = 1;
+pub const PACKET_SYNC: u32 = 2;
+pub const PACKET_TOUCH: u32 = 3;
+pub const PACKET_AUDIO: u32 = 4;
+const SYNC_REPORT_SIZE: usize = 24;
+const TOUCH_POINTS_MAX: usize = 5;
+const TOUCH_REPORT_SIZE: usize = 8 + TOUCH_POINTS_MAX * 4;
+
+const REQUEST_GET_CAPS: u8 = 0x01;
+const REQUEST_SET_MUX: u8 = 0x02;
+const CAPS_SUBSAMP_444: u8 = 1 << 0;
+const CAPS_SUBSAMP_422: u8 = 1 << 1;
+const CAPS_SUBSAMP_420: u8 = 1 << 2;
+const CAPS_SUBSAMP_GRAY: u8 = 1 << 3;
+const CAPS_FEATURE_SYNC: u8 = 1 << 0;
+const CAPS_FEATURE_AUDIO: u8 = 1 << 1;
+const CAPS_FEATURE_MUX: u8 = 1 << 2;
+
+pub struct Capabilities {
+    pub subsampling: Vec<Subsampling>,
+    /// Answers clock sync packets and holds frames until their present time
+    pub sync: bool,
+    /// Plays audio packets on its speaker
+    pub audio: bool,
+    /// Takes packets as channel fragments once asked to
+    pub mux: bool,
+}
+
+impl Capabilities {
+    /// What the ESP32-P4 JPEG decoder accepts, for firmware without GET_CAPS support.
+    pub fn esp32p4() -> Self {
+        Capabilities { subsampling: Subsampling::ALL.to_vec(), sync: false, audio: false, mux: false }
+    }
+
+    pub fn query(device: &DeviceHandle<GlobalContext>) -> Self {
+        let request_type = rusb::request_type(rusb::Direction::In, rusb::RequestType::Vendor, rusb::Recipient::Device);
+        let mut buf = [0u8; 8];
+        match device.read_control(request_type, REQUEST_GET_CAPS, 0, 0, &mut buf, Duration::from_millis(100)) {
+            Ok(size) if size >= 2 => Capabilities::parse(&buf[..size]),
+            _ => Capabilities::esp32p4(),
+        }
+    }
+
+    fn parse(data: &[u8]) -> Self {
+        let subsampling = [
+            (CAPS_SUBSAMP_420, Subsampling::Sub420),
+            (CAPS_SUBSAMP_422, Subsampling::Sub422),
+            (CAPS_SUBSAMP_444, Subsampling::Sub444),
+            (CAPS_SUBSAMP_GRAY, Subsampling::Gray),
+        ].into_iter().filter(|(bit, _)| data[1] & bit != 0).map(|(_, s)| s).collect();
+        let feature = |bit: u8| data.get(2).is_some_and(|features| features & bit != 0);
+        Capabilities {
+            subsampling,
+            sync: feature(CAPS_FEATURE_SYNC),
+            audio: feature(CAPS_FEATURE_AUDIO),
+            mux: feature(CAPS_FEATURE_MUX),
+        }
+    }
+
+    pub fn supports(&self, subsampling: Subsampling) -> bool {
+        subsampling == Subsampling::Auto || self.subsampling.contains(&subsampling)
+    }
+}
+
+/// Bulk pipe to a tablet, a USB device or `simulator::SimulatedDevice`.
+pub trait Link: Send + Sync {
+    fn write(&self, data: &[u8]) -> Result<usize, rusb::Error>;
+    fn read(&self, buf: &mut [u8]) -> Result<usize, rusb::Error>;
+    fn capabilities(&self) -> Capabilities;
+    /// Switches both directions to channel fragments until the tablet is unplugged.
+    fn enable_mux(&self) -> Result<(), rusb::Error>;
+}
+
+impl Link for DeviceHandle<GlobalContext> {
+    fn write(&self, data: &[u8]) -> Result<usize, rusb::Error> {
+        self.write_bulk(EP_OUT, data, Duration::from_secs(1))
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+        self.read_bulk(EP_IN, buf, Duration::from_millis(100))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::query(self)
+    }
+
+    fn enable_mux(&self) -> Result<(), rusb::Error> {
+        let request_type = rusb::request_type(rusb::Direction::Out, rusb::RequestType::Vendor, rusb::Recipient::Device);
+        self.write_control(request_type, REQUEST_SET_MUX, 1, 0, &[], Duration::from_millis(100)).map(|_| ())
+    }
+}
+
+/// Message from a tablet on the IN endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Report {
+    /// Echoed host time and the tablet's timer when the sync packet arrived
+    Sync { host: u64, device: u64 },
+    /// Points in panel coordinates, empty when the finger is lifted
+    Touch(Vec<(u16, u16)>),
+}
+
+/// Splits one IN transfer, which may hold several reports.
+pub fn parse_reports(mut data: &[u8]) -> Vec<Report> {
+    let u32_at = |data: &[u8], i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+    let u64_at = |data: &[u8], i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+    let mut reports = Vec::new();
+    while data.len() >= 8 {
+        let size = match u32_at(data, 0) {
+            PACKET_SYNC if data.len() >= SYNC_REPORT_SIZE => {
+                reports.push(Report::Sync { host: u64_at(data, 8), device: u64_at(data, 16) });
+                SYNC_REPORT_SIZE
+            }
+            PACKET_TOUCH if data.len() >= TOUCH_REPORT_SIZE => {
+                let count = (u32_at(data, 4) as usize).min(TOUCH_POINTS_MAX);
+                let point = |i: usize| {
+                    let at = 8 + i * 4;
+                    (u16::from_le_bytes([data[at], data[at + 1]]), u16::from_le_bytes([data[at + 2], data[at + 3]]))
+                };
+                reports.push(Report::Touch((0..count).map(point).collect()));
+                TOUCH_REPORT_SIZE
+            }
+            _ => break,
+        };
+        data = &data[size..];
+    }
+    reports
+}
+
+/// Touch points tagged with the index of their tablet
+pub type Touch = (usize, Vec<(u16, u16)>);
+
+/// A link with its IN endpoint drained on a thread, sync replies and touch reports sorted apart.
+/// Packets go out on their channel through a muxer when the firmware takes fragments.
+pub struct Tablet {
+    link: Arc<dyn Link>,
+    muxer: Option<Muxer>,
+    sync_rx: Mutex<mpsc::Receiver<(u64, u64)>>,
+    /// Held while a packet goes out in parts, so another thread's packet cannot land in between
+    writing: Mutex<()>,
+}
+
+impl Tablet {
+    /// Touch reports go to `touch_tx` tagged with `index`, or are dropped without one.
+    /// `mux` when the capabilities offer it, the tablet is switched over here.
+    pub fn new(link: Box<dyn Link>, mux: bool, index: usize, touch_tx: Option<mpsc::Sender<Touch>>) -> Self {
+        let link: Arc<dyn Link> = Arc::from(link);
+        let muxer = (mux && link.enable_mux().is_ok()).then(|| Muxer::new(link.clone()));
+        let mut demuxer = muxer.is_some().then(Demuxer::default);
+        let (sync_tx, sync_rx) = mpsc::channel();
+        let reader = link.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            loop {
+                let size = match reader.read(&mut buf) {
+                    Ok(size) => size,
+                    Err(rusb::Error::Timeout) => continue,
+                    Err(_) => break,
+                };
+                // Reports come whole inside their channel's message
+                let reports = match &mut demuxer {
+                    Some(demuxer) => demuxer.push(&buf[..size]).into_iter()
+                        .filter(|(channel, _)| matches!(channel, Channel::Control | Channel::Touch))
+                        .flat_map(|(_, message)| parse_reports(&message))
+                        .collect(),
+                    None => parse_reports(&buf[..size]),
+                };
+                for report in reports {
+                    match report {
+                        Report::Sync { host, device } => { let _ = sync_tx.send((host, device)); }
+                        Report::Touch(points) => if let Some(touch_tx) = &touch_tx {
+                            let _ = touch_tx.send((index, points));
+                        }
+                    }
+                }
+            }
+        });
+        Tablet { link, muxer, sync_rx: Mutex::new(sync_rx), writing: Mutex::new(()) }
+    }
+
+    /// One packet in parts, e.g. a header and the data after it. Muxed it waits behind nothing
+    /// but higher channels, otherwise the parts go out back to back after whatever is being written.
+    pub fn send(&self, channel: Channel, parts: &[&[u8]]) -> Result<usize, rusb::Error> {
+        if let Some(muxer) = &self.muxer {
+            return muxer.send(channel, parts.concat());
+        }
+        let _writing = self.writing.lock().unwrap();
+        parts.iter().try_fold(0, |size, part| Ok(size + self.link.write(part)?))
+    }
+
+    /// Next sync reply, as echoed host time and device time
+    pub fn sync_reply(&self, timeout: Duration) -> Option<(u64, u64)> {
+        self.sync_rx.lock().unwrap().recv_timeout(timeout).ok()
+    }
+}
+
+pub fn open_device() -> Result<DeviceHandle<GlobalContext>, rusb::Error> {
+    let device = rusb::open_device_with_vid_pid(VID, PID)
+        .expect("Device not found!");
+    claim(device)
+}
+
+/// Opens the tablet whose USB serial number matches, for telling the tablets of a wall apart.
+pub fn open_device_by_serial(serial: &str) -> Result<DeviceHandle<GlobalContext>, rusb::Error> {
+    let device = tablets()?.into_iter().find(|(_, s)| s == serial).map(|(device, _)| device)
+        .ok_or(rusb::Error::NotFound)?;
+    claim(device)
+}
+
+/// Serial numbers of every connected tablet.
+pub fn serials() -> Result<Vec<String>, rusb::Error> {
+    Ok(tablets()?.into_iter().map(|(_, serial)| serial).collect())
+}
+
+fn tablets() -> Result<Vec<(DeviceHandle<GlobalContext>, String)>, rusb::Error> {
+    let mut tablets = Vec::new();
+    for device in rusb::devices()?.iter() {
+        let descriptor = device.device_descriptor()?;
+        if descriptor.vendor_id() != VID || descriptor.product_id() != PID { continue }
+        let Ok(handle) = device.open() else { continue };
+        let serial = handle.read_serial_number_string_ascii(&descriptor).unwrap_or_default();
+        tablets.push((handle, serial));
+    }
+    Ok(tablets)
+}
+
+fn claim(device: DeviceHandle<GlobalContext>) -> Result<DeviceHandle<GlobalContext>, rusb::Error> {
+    let _ = device.detach_kernel_driver(0);
+    device.set_active_configuration(1)?;
+    device.claim_interface(0)?;
+    Ok(device)
+}
@@ -0,0 +1,403 @@
+use crate::inject::{Button, Event, Key, Shortcut};
+use std::{str::FromStr, time::Duration};
+
+/// Travel in output pixels before a touch is a drag rather than a tap or long press
+const SLOP: f64 = 12.0;
+const LONG_PRESS: Duration = Duration::from_millis(600);
+/// Two-finger travel per wheel notch, in output pixels
+const NOTCH: f64 = 40.0;
+/// Change of finger spread per zoom step
+const ZOOM_STEP: f64 = 1.1;
+
+/// What the recogniser makes of the touch stream, positions in upright output coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gesture {
+    /// The pointer follows the first finger
+    Point(f64, f64),
+    /// A drag starts or ends
+    Press,
+    Release,
+    Tap(f64, f64),
+    /// Two fingers moved together by this much
+    Scroll(f64, f64),
+    /// Two fingers spread apart by `factor` around their centre
+    Pinch { factor: f64, x: f64, y: f64 },
+    LongPress(f64, f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TwoFingers {
+    Undecided,
+    Scroll,
+    Pinch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// One finger down, may still become a tap, a drag or a long press
+    Pending { start: (f64, f64), since: Duration },
+    Dragging,
+    TwoFingers { mode: TwoFingers, start: ((f64, f64), f64), last: ((f64, f64), f64) },
+    /// Gesture over, waiting for every finger to lift
+    Done,
+}
+
+/// Touch stream to gestures. Pure: time comes in with every call, so recorded traces replay the same.
+pub struct Recognizer {
+    state: State,
+}
+
+impl Default for Recognizer {
+    fn default() -> Self {
+        Recognizer { state: State::Idle }
+    }
+}
+
+impl Recognizer {
+    /// A touch report, `time` since any fixed start.
+    pub fn update(&mut self, time: Duration, points: &[(f64, f64)]) -> Vec<Gesture> {
+        let mut gestures = self.tick(time);
+        let first = points.first().copied();
+        gestures.extend(match (self.state, points.len(), first) {
+            (State::Idle, 1, Some(point)) => {
+                self.state = State::Pending { start: point, since: time };
+                vec![Gesture::Point(point.0, point.1)]
+            }
+            (State::Pending { .. } | State::Idle, 2.., _) => {
+                let (center, spread) = span(points);
+                self.state = State::TwoFingers { mode: TwoFingers::Undecided, start: (center, spread), last: (center, spread) };
+                Vec::new()
+            }
+            (State::Pending { start, .. }, 0, _) => {
+                self.state = State::Idle;
+                vec![Gesture::Tap(start.0, start.1)]
+            }
+            (State::Pending { start, .. }, 1, Some(point)) if distance(start, point) > SLOP => {
+                self.state = State::Dragging;
+                vec![Gesture::Press, Gesture::Point(point.0, point.1)]
+            }
+            (State::Dragging, 0, _) => {
+                self.state = State::Idle;
+                vec![Gesture::Release]
+            }
+            // Extra fingers during a drag are ignored
+            (State::Dragging, _, Some(point)) => vec![Gesture::Point(point.0, point.1)],
+            (State::TwoFingers { mode, start, last }, 2.., _) => self.two_fingers(mode, start, last, span(points)),
+            (State::TwoFingers { .. } | State::Done, 0, _) => {
+                self.state = State::Idle;
+                Vec::new()
+            }
+            // Lifting one of two fingers ends the gesture, the other one does not start a drag
+            (State::TwoFingers { .. }, _, _) => {
+                self.state = State::Done;
+                Vec::new()
+            }
+            _ => Vec::new(),
+        });
+        gestures
+    }
+
+    /// Time passing without reports, a finger held still only reports when it lands.
+    pub fn tick(&mut self, time: Duration) -> Vec<Gesture> {
+        match self.state {
+            State::Pending { start, since } if time.saturating_sub(since) >= LONG_PRESS => {
+                self.state = State::Done;
+                vec![Gesture::LongPress(start.0, start.1)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn two_fingers(&mut self, mode: TwoFingers, start: ((f64, f64), f64), last: ((f64, f64), f64), now: ((f64, f64), f64)) -> Vec<Gesture> {
+        // Scroll or pinch, whichever moves past the slop first, stays for the whole gesture
+        let mode = match mode {
+            TwoFingers::Undecided => {
+                let travel = distance(start.0, now.0);
+                let spread = (now.1 - start.1).abs();
+                if travel.max(spread) <= SLOP {
+                    return Vec::new();
+                }
+                if travel >= spread { TwoFingers::Scroll } else { TwoFingers::Pinch }
+            }
+            mode => mode,
+        };
+        self.state = State::TwoFingers { mode, start, last: now };
+        match mode {
+            TwoFingers::Scroll => vec![Gesture::Scroll(now.0.0 - last.0.0, now.0.1 - last.0.1)],
+            _ => vec![Gesture::Pinch { factor: now.1 / last.1.max(1.0), x: now.0.0, y: now.0.1 }],
+        }
+    }
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+/// Centre of and distance between the first two points
+fn span(points: &[(f64, f64)]) -> ((f64, f64), f64) {
+    let (a, b) = (points[0], points[1]);
+    (((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0), distance(a, b))
+}
+
+/// What a gesture does on the host.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    None,
+    /// Wheel notches with these keys held, ctrl+wheel zooms in most applications
+    Wheel(Vec<Key>),
+    /// A shortcut per step, the first one scrolling up or zooming in
+    Keys(Shortcut, Option<Shortcut>),
+    RightClick,
+    /// Switches between trackpad and absolute pointing, long press only
+    Trackpad,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    /// `none`, `wheel[:KEYS]`, `keys:SHORTCUT[/SHORTCUT]`, `right-click` or `trackpad`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':').map_or((s, None), |(k, v)| (k, Some(v))) {
+            ("none", None) => Ok(Action::None),
+            ("wheel", None) => Ok(Action::Wheel(Vec::new())),
+            ("wheel", Some(keys)) => Ok(Action::Wheel(keys.parse::<Shortcut>()?.0)),
+            ("keys", Some(keys)) => match keys.split_once('/') {
+                Some((first, second)) => Ok(Action::Keys(first.parse()?, Some(second.parse()?))),
+                None => Ok(Action::Keys(keys.parse()?, None)),
+            },
+            ("right-click", None) => Ok(Action::RightClick),
+            ("trackpad", None) => Ok(Action::Trackpad),
+            _ => Err(format!("expected none, wheel[:KEYS], keys:SHORTCUT[/SHORTCUT], right-click or trackpad: {}", s)),
+        }
+    }
+}
+
+/// `scroll=ACTION`, `pinch=ACTION` or `long-press=ACTION` from the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    gesture: String,
+    action: Action,
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (gesture, action) = s.split_once('=').ok_or_else(|| format!("expected GESTURE=ACTION: {}", s))?;
+        if !["scroll", "pinch", "long-press"].contains(&gesture) {
+            return Err(format!("expected scroll, pinch or long-press: {}", gesture));
+        }
+        let action = action.parse()?;
+        // Scroll and pinch fire all along the gesture, they would switch back and forth or click over and over
+        if action == Action::Trackpad && gesture != "long-press" {
+            return Err(format!("only long-press can toggle the trackpad: {}", s));
+        }
+        if action == Action::RightClick && gesture != "long-press" {
+            return Err(format!("only long-press can right click: {}", s));
+        }
+        Ok(Binding { gesture: gesture.to_string(), action })
+    }
+}
+
+/// Turns gestures into host input, keeping the fractions of wheel notches and zoom steps.
+pub struct GestureMap {
+    scroll: Action,
+    pinch: Action,
+    long_press: Action,
+    wheel: (f64, f64),
+    zoom: f64,
+}
+
+impl GestureMap {
+    /// Two-finger scroll to the wheel, pinch to ctrl+wheel and long press to right click, unless bound otherwise.
+    pub fn new(bindings: &[Binding]) -> Self {
+        let action = |gesture: &str, default: Action| {
+            bindings.iter().rev().find(|b| b.gesture == gesture).map_or(default, |b| b.action.clone())
+        };
+        GestureMap {
+            scroll: action("scroll", Action::Wheel(Vec::new())),
+            pinch: action("pinch", Action::Wheel(vec![Key::Ctrl])),
+            long_press: action("long-press", Action::RightClick),
+            wheel: (0.0, 0.0),
+            zoom: 0.0,
+        }
+    }
+
+    /// Whether a long press switches between trackpad and absolute pointing.
+    pub fn trackpad_toggle(&self) -> bool {
+        self.long_press == Action::Trackpad
+    }
+
+    /// Whether `gesture` is the one that switches to or from the trackpad, it has no events then.
+    pub fn toggles_trackpad(&self, gesture: Gesture) -> bool {
+        matches!(gesture, Gesture::LongPress(..)) && self.trackpad_toggle()
+    }
+
+    /// `to_desktop` maps output coordinates to desktop coordinates, `None` outside the picture.
+    pub fn events(&mut self, gesture: Gesture, to_desktop: impl Fn((f64, f64)) -> Option<(f64, f64)>) -> Vec<Event> {
+        let move_to = |(x, y)| to_desktop((x, y)).map(|(x, y)| Event::Move(x, y));
+        match gesture {
+            Gesture::Point(x, y) => move_to((x, y)).into_iter().collect(),
+            Gesture::Press => vec![Event::Press(Button::Left)],
+            Gesture::Release => vec![Event::Release(Button::Left)],
+            Gesture::Tap(x, y) => move_to((x, y)).into_iter()
+                .chain([Event::Press(Button::Left), Event::Release(Button::Left)]).collect(),
+            Gesture::LongPress(x, y) => match &self.long_press {
+                Action::RightClick => move_to((x, y)).into_iter()
+                    .chain([Event::Press(Button::Right), Event::Release(Button::Right)]).collect(),
+                Action::Keys(shortcut, _) => shortcut.events(),
+                // Plain click, the wheel has nothing to do with a long press
+                Action::Wheel(_) => vec![Event::Press(Button::Left), Event::Release(Button::Left)],
+                Action::None | Action::Trackpad => Vec::new(),
+            },
+            Gesture::Scroll(dx, dy) => {
+                // Content follows the fingers, moving them down scrolls up
+                self.wheel = (self.wheel.0 - dx / NOTCH, self.wheel.1 + dy / NOTCH);
+                let notches = (self.wheel.0.trunc(), self.wheel.1.trunc());
+                self.wheel = (self.wheel.0.fract(), self.wheel.1.fract());
+                match &self.scroll {
+                    Action::Wheel(keys) if notches != (0.0, 0.0) => held(keys, vec![Event::Wheel(notches.0, notches.1)]),
+                    Action::Keys(up, down) => steps(notches.1, up, down.as_ref()),
+                    _ => Vec::new(),
+                }
+            }
+            Gesture::Pinch { factor, .. } => {
+                self.zoom += factor.ln() / ZOOM_STEP.ln();
+                let zoom = self.zoom.trunc();
+                self.zoom = self.zoom.fract();
+                match &self.pinch {
+                    Action::Wheel(keys) if zoom != 0.0 => held(keys, vec![Event::Wheel(0.0, zoom)]),
+                    Action::Keys(zoom_in, zoom_out) => steps(zoom, zoom_in, zoom_out.as_ref()),
+                    _ => Vec::new(),
+                }
+            }
+        }
+    }
+}
+
+/// `events` with `keys` held down around them.
+fn held(keys: &[Key], events: Vec<Event>) -> Vec<Event> {
+    let press = keys.iter().map(|key| Event::Key(*key, true));
+    let release = keys.iter().rev().map(|key| Event::Key(*key, false));
+    press.chain(events).chain(release).collect()
+}
+
+/// `count` taps of `up` when positive, of `down` when negative.
+fn steps(count: f64, up: &Shortcut, down: Option<&Shortcut>) -> Vec<Event> {
+    let shortcut = if count > 0.0 { Some(up) } else { down };
+    shortcut.map_or(Vec::new(), |shortcut| {
+        (0..count.abs() as usize).flat_map(|_| shortcut.events()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inject::{TICK, session::parse_session};
+
+    /// Replays a trace in the touch record format through a recogniser, ticking between reports as the
+    /// Touch Thread does, and the gestures through a map with `bindings`.
+    fn replay(trace: &str, bindings: &[&str]) -> (Vec<Gesture>, Vec<Event>) {
+        let session = parse_session(trace).unwrap();
+
+        let mut recognizer = Recognizer::default();
+        let mut gestures = Vec::new();
+        let mut time = Duration::ZERO;
+        for (at, (_, points)) in session {
+            while time + TICK < at {
+                time += TICK;
+                gestures.extend(recognizer.tick(time));
+            }
+            time = at;
+            let points: Vec<_> = points.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+            gestures.extend(recognizer.update(time, &points));
+        }
+
+        let bindings: Vec<Binding> = bindings.iter().map(|b| b.parse().unwrap()).collect();
+        let mut map = GestureMap::new(&bindings);
+        let events = gestures.iter().flat_map(|gesture| map.events(*gesture, Some)).collect();
+        (gestures, events)
+    }
+
+    const TAP: &str = "# tap\n0 0 100,200\n80 0 102,201\n120 0\n";
+    const LONG_PRESS: &str = "0 0 300,300\n700 0 301,300\n900 0\n";
+    const SCROLL: &str = "0 0 100,100 200,100\n20 0 100,120 200,120\n40 0 100,160 200,160\n60 0 100,180\n80 0\n";
+    const PINCH: &str = "0 0 100,100 200,100\n20 0 80,100 220,100\n40 0 60,100 240,100\n60 0\n";
+
+    #[test]
+    fn tap() {
+        let (gestures, events) = replay(TAP, &[]);
+        assert_eq!(gestures, [Gesture::Point(100.0, 200.0), Gesture::Tap(100.0, 200.0)]);
+        assert_eq!(events, [
+            Event::Move(100.0, 200.0), Event::Move(100.0, 200.0),
+            Event::Press(Button::Left), Event::Release(Button::Left),
+        ]);
+    }
+
+    #[test]
+    fn long_press() {
+        let (gestures, events) = replay(LONG_PRESS, &[]);
+        assert_eq!(gestures, [Gesture::Point(300.0, 300.0), Gesture::LongPress(300.0, 300.0)]);
+        assert_eq!(events, [
+            Event::Move(300.0, 300.0), Event::Move(300.0, 300.0),
+            Event::Press(Button::Right), Event::Release(Button::Right),
+        ]);
+
+        let (_, events) = replay(LONG_PRESS, &["long-press=keys:escape"]);
+        assert_eq!(events, [Event::Move(300.0, 300.0), Event::Key(Key::Escape, true), Event::Key(Key::Escape, false)]);
+    }
+
+    #[test]
+    fn two_finger_scroll() {
+        // Half a notch, then one and a half, the half left over is kept
+        let (gestures, events) = replay(SCROLL, &[]);
+        assert_eq!(gestures, [Gesture::Scroll(0.0, 20.0), Gesture::Scroll(0.0, 40.0)]);
+        assert_eq!(events, [Event::Wheel(0.0, 1.0)]);
+
+        let (_, events) = replay(SCROLL, &["scroll=keys:up/down"]);
+        assert_eq!(events, [Event::Key(Key::Up, true), Event::Key(Key::Up, false)]);
+    }
+
+    #[test]
+    fn pinch() {
+        let (gestures, events) = replay(PINCH, &[]);
+        assert_eq!(gestures, [
+            Gesture::Pinch { factor: 1.4, x: 150.0, y: 100.0 },
+            Gesture::Pinch { factor: 180.0 / 140.0, x: 150.0, y: 100.0 },
+        ]);
+        // 3.5 and 2.6 zoom steps
+        let zoom = |steps| [Event::Key(Key::Ctrl, true), Event::Wheel(0.0, steps), Event::Key(Key::Ctrl, false)];
+        assert_eq!(events, [zoom(3.0), zoom(3.0)].concat());
+
+        let (_, events) = replay(PINCH, &["pinch=none"]);
+        assert_eq!(events, []);
+    }
+
+    #[test]
+    fn cancelled_and_ambiguous() {
+        // Two fingers that barely move, then lift one after the other: neither scroll, pinch nor drag
+        let trace = "0 0 100,100 200,100\n30 0 104,102 203,101\n60 0 104,102\n90 0\n";
+        assert_eq!(replay(trace, &[]), (Vec::new(), Vec::new()));
+
+        // A second finger lands before the first one lifts, the tap is dropped
+        let trace = "0 0 100,100\n40 0 100,100 160,100\n80 0\n";
+        assert_eq!(replay(trace, &[]), (vec![Gesture::Point(100.0, 100.0)], vec![Event::Move(100.0, 100.0)]));
+    }
+
+    #[test]
+    fn long_press_toggles_trackpad() {
+        let (gestures, events) = replay(LONG_PRESS, &["long-press=trackpad"]);
+        let map = GestureMap::new(&["long-press=trackpad".parse().unwrap()]);
+        assert!(map.toggles_trackpad(gestures[1]));
+        assert_eq!(events, [Event::Move(300.0, 300.0)]);
+        assert!("scroll=trackpad".parse::<Binding>().is_err());
+    }
+
+    #[test]
+    fn right_click_only_on_long_press() {
+        assert!("long-press=right-click".parse::<Binding>().is_ok());
+        assert!("scroll=right-click".parse::<Binding>().is_err());
+        assert!("pinch=right-click".parse::<Binding>().is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Ctrl, Shift, Alt,
+    /// Command on macOS, the Windows key elsewhere
+    Meta,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Up, Down, Left, Right, PageUp, PageDown, Home, End,
+    Enter, Escape, Tab, Space, Backspace, Delete, Minus, Equal,
+    VolumeUp, VolumeDown, Mute,
+}
+
+impl Key {
+    const LETTERS: [Key; 26] = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+    const DIGITS: [Key; 10] = [
+        Key::Digit0, Key::Digit1, Key::Digit2, Key::Digit3, Key::Digit4,
+        Key::Digit5, Key::Digit6, Key::Digit7, Key::Digit8, Key::Digit9,
+    ];
+    const FUNCTIONS: [Key; 12] = [
+        Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+    ];
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        let key = match name.as_str() {
+            "ctrl" | "control" => Key::Ctrl,
+            "shift" => Key::Shift,
+            "alt" | "option" => Key::Alt,
+            "meta" | "cmd" | "command" | "win" | "super" => Key::Meta,
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "left" => Key::Left,
+            "right" => Key::Right,
+            "pageup" => Key::PageUp,
+            "pagedown" => Key::PageDown,
+            "home" => Key::Home,
+            "end" => Key::End,
+            "enter" | "return" => Key::Enter,
+            "escape" | "esc" => Key::Escape,
+            "tab" => Key::Tab,
+            "space" => Key::Space,
+            "backspace" => Key::Backspace,
+            "delete" | "del" => Key::Delete,
+            "minus" | "-" => Key::Minus,
+            "equal" | "plus" | "=" => Key::Equal,
+            "volumeup" => Key::VolumeUp,
+            "volumedown" => Key::VolumeDown,
+            "mute" => Key::Mute,
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.as_str()) {
+                    (Some(c @ 'a'..='z'), "") => Key::LETTERS[c as usize - 'a' as usize],
+                    (Some(c @ '0'..='9'), "") => Key::DIGITS[c as usize - '0' as usize],
+                    (Some('f'), n) => n.parse::<usize>().ok()
+                        .and_then(|n| Key::FUNCTIONS.get(n.wrapping_sub(1)).copied())
+                        .ok_or_else(|| format!("unknown key: {}", s))?,
+                    _ => return Err(format!("unknown key: {}", s)),
+                }
+            }
+        };
+        Ok(key)
+    }
+}
+
+/// Keys pressed together, modifiers first, e.g. `ctrl+shift+t`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shortcut(pub Vec<Key>);
+
+impl FromStr for Shortcut {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `+` on its own is the plus key, `ctrl++` holds it with ctrl
+        let keys = match s.strip_suffix("++") {
+            Some(rest) => rest.split('+').chain(["plus"]).map(str::parse).collect::<Result<Vec<Key>, String>>()?,
+            None if s == "+" => vec![Key::Equal],
+            None => s.split('+').map(str::parse).collect::<Result<Vec<Key>, String>>()?,
+        };
+        Ok(Shortcut(keys))
+    }
+}
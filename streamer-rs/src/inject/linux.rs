@@ -0,0 +1,154 @@
+use crate::{capture::pointer, inject::{Button, Event, Injector, Key}};
+use std::{fs::File, io::Write, os::fd::AsRawFd};
+
+// linux/uinput.h and linux/input-event-codes.h
+const UI_SET_EVBIT: libc::c_ulong = 0x4004_5564;
+const UI_SET_KEYBIT: libc::c_ulong = 0x4004_5565;
+const UI_SET_RELBIT: libc::c_ulong = 0x4004_5566;
+const UI_SET_ABSBIT: libc::c_ulong = 0x4004_5567;
+const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_CNT: usize = 0x40;
+/// Key codes up to here are registered, covers every `Key`
+const KEY_MAX: u16 = 0x7f;
+
+// linux/input-event-codes.h
+fn code(key: Key) -> u16 {
+    match key {
+        Key::Ctrl => 29, Key::Shift => 42, Key::Alt => 56, Key::Meta => 125,
+        Key::Q => 16, Key::W => 17, Key::E => 18, Key::R => 19, Key::T => 20,
+        Key::Y => 21, Key::U => 22, Key::I => 23, Key::O => 24, Key::P => 25,
+        Key::A => 30, Key::S => 31, Key::D => 32, Key::F => 33, Key::G => 34,
+        Key::H => 35, Key::J => 36, Key::K => 37, Key::L => 38,
+        Key::Z => 44, Key::X => 45, Key::C => 46, Key::V => 47, Key::B => 48, Key::N => 49, Key::M => 50,
+        Key::Digit1 => 2, Key::Digit2 => 3, Key::Digit3 => 4, Key::Digit4 => 5, Key::Digit5 => 6,
+        Key::Digit6 => 7, Key::Digit7 => 8, Key::Digit8 => 9, Key::Digit9 => 10, Key::Digit0 => 11,
+        Key::F1 => 59, Key::F2 => 60, Key::F3 => 61, Key::F4 => 62, Key::F5 => 63, Key::F6 => 64,
+        Key::F7 => 65, Key::F8 => 66, Key::F9 => 67, Key::F10 => 68, Key::F11 => 87, Key::F12 => 88,
+        Key::Home => 102, Key::Up => 103, Key::PageUp => 104, Key::Left => 105,
+        Key::Right => 106, Key::End => 107, Key::Down => 108, Key::PageDown => 109,
+        Key::Escape => 1, Key::Minus => 12, Key::Equal => 13, Key::Backspace => 14, Key::Tab => 15,
+        Key::Enter => 28, Key::Space => 57, Key::Delete => 111,
+        Key::Mute => 113, Key::VolumeDown => 114, Key::VolumeUp => 115,
+    }
+}
+
+#[repr(C)]
+struct UinputUserDev {
+    name: [u8; 80],
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+    ff_effects_max: u32,
+    absmax: [i32; ABS_CNT],
+    absmin: [i32; ABS_CNT],
+    absfuzz: [i32; ABS_CNT],
+    absflat: [i32; ABS_CNT],
+}
+
+#[repr(C)]
+struct InputEvent {
+    time: libc::timeval,
+    kind: u16,
+    code: u16,
+    value: i32,
+}
+
+/// Absolute pointer with a wheel and a keyboard on /dev/uinput, its axes span the X screen so
+/// desktop coordinates go in as they are.
+pub struct Uinput {
+    file: File,
+}
+
+pub fn system() -> Result<Box<dyn Injector>, String> {
+    let (width, height) = pointer::screen_size().ok_or("screen size unknown, touch input needs X11")?;
+    let mut file = File::options().write(true).open("/dev/uinput").map_err(|e| format!("/dev/uinput: {}", e))?;
+    let fd = file.as_raw_fd();
+
+    let mut device = UinputUserDev {
+        name: [0; 80],
+        bustype: 0x03, // BUS_USB
+        vendor: crate::device::VID,
+        product: crate::device::PID,
+        version: 1,
+        ff_effects_max: 0,
+        absmax: [0; ABS_CNT],
+        absmin: [0; ABS_CNT],
+        absfuzz: [0; ABS_CNT],
+        absflat: [0; ABS_CNT],
+    };
+    let name = b"M5Stack Tab5 Touch";
+    device.name[..name.len()].copy_from_slice(name);
+    device.absmax[ABS_X as usize] = width as i32 - 1;
+    device.absmax[ABS_Y as usize] = height as i32 - 1;
+
+    let bytes = unsafe {
+        std::slice::from_raw_parts(&device as *const UinputUserDev as *const u8, size_of::<UinputUserDev>())
+    };
+    let created = unsafe {
+        libc::ioctl(fd, UI_SET_EVBIT, EV_KEY as libc::c_int) == 0
+            && libc::ioctl(fd, UI_SET_EVBIT, EV_ABS as libc::c_int) == 0
+            && libc::ioctl(fd, UI_SET_EVBIT, EV_REL as libc::c_int) == 0
+            && libc::ioctl(fd, UI_SET_EVBIT, EV_SYN as libc::c_int) == 0
+            && [BTN_LEFT, BTN_RIGHT].into_iter().chain(1..=KEY_MAX)
+                .all(|code| libc::ioctl(fd, UI_SET_KEYBIT, code as libc::c_int) == 0)
+            && libc::ioctl(fd, UI_SET_RELBIT, REL_WHEEL as libc::c_int) == 0
+            && libc::ioctl(fd, UI_SET_RELBIT, REL_HWHEEL as libc::c_int) == 0
+            && libc::ioctl(fd, UI_SET_ABSBIT, ABS_X as libc::c_int) == 0
+            && libc::ioctl(fd, UI_SET_ABSBIT, ABS_Y as libc::c_int) == 0
+            && file.write_all(bytes).is_ok()
+            && libc::ioctl(fd, UI_DEV_CREATE) == 0
+    };
+    if !created {
+        return Err(format!("uinput: {}", std::io::Error::last_os_error()));
+    }
+    Ok(Box::new(Uinput { file }))
+}
+
+impl Uinput {
+    fn emit(&mut self, kind: u16, code: u16, value: i32) {
+        let event = InputEvent { time: libc::timeval { tv_sec: 0, tv_usec: 0 }, kind, code, value };
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&event as *const InputEvent as *const u8, size_of::<InputEvent>())
+        };
+        let _ = self.file.write_all(bytes);
+    }
+}
+
+impl Injector for Uinput {
+    fn inject(&mut self, event: Event) {
+        let button = |button| if button == Button::Left { BTN_LEFT } else { BTN_RIGHT };
+        match event {
+            Event::Move(x, y) => {
+                self.emit(EV_ABS, ABS_X, x.round() as i32);
+                self.emit(EV_ABS, ABS_Y, y.round() as i32);
+            }
+            Event::Press(b) => self.emit(EV_KEY, button(b), 1),
+            Event::Release(b) => self.emit(EV_KEY, button(b), 0),
+            Event::Wheel(x, y) => {
+                self.emit(EV_REL, REL_HWHEEL, x.round() as i32);
+                self.emit(EV_REL, REL_WHEEL, y.round() as i32);
+            }
+            Event::Key(key, pressed) => self.emit(EV_KEY, code(key), pressed as i32),
+        }
+        self.emit(EV_SYN, SYN_REPORT, 0);
+    }
+}
+
+impl Drop for Uinput {
+    fn drop(&mut self) {
+        unsafe { libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY) };
+    }
+}
@@ -0,0 +1,82 @@
+use crate::inject::{Button, Event, Injector, Key};
+use core_graphics::{event::{CGEvent, CGEventFlags, CGEventTapLocation, CGEventType, CGKeyCode, CGMouseButton, ScrollEventUnit}, event_source::{CGEventSource, CGEventSourceStateID}, geometry::CGPoint};
+
+/// Posts mouse and key events into the HID event stream, needs the Accessibility permission.
+pub struct Quartz {
+    position: CGPoint,
+    buttons: [bool; 2],
+    /// Modifiers held by earlier key events, Quartz does not track them for posted events
+    flags: CGEventFlags,
+}
+
+pub fn system() -> Result<Box<dyn Injector>, String> {
+    Ok(Box::new(Quartz { position: CGPoint::new(0.0, 0.0), buttons: [false; 2], flags: CGEventFlags::empty() }))
+}
+
+// Carbon virtual key codes (HIToolbox Events.h)
+fn code(key: Key) -> CGKeyCode {
+    match key {
+        Key::A => 0x00, Key::S => 0x01, Key::D => 0x02, Key::F => 0x03, Key::H => 0x04, Key::G => 0x05,
+        Key::Z => 0x06, Key::X => 0x07, Key::C => 0x08, Key::V => 0x09, Key::B => 0x0B, Key::Q => 0x0C,
+        Key::W => 0x0D, Key::E => 0x0E, Key::R => 0x0F, Key::Y => 0x10, Key::T => 0x11, Key::O => 0x1F,
+        Key::U => 0x20, Key::I => 0x22, Key::P => 0x23, Key::L => 0x25, Key::J => 0x26, Key::K => 0x28,
+        Key::N => 0x2D, Key::M => 0x2E,
+        Key::Digit1 => 0x12, Key::Digit2 => 0x13, Key::Digit3 => 0x14, Key::Digit4 => 0x15, Key::Digit6 => 0x16,
+        Key::Digit5 => 0x17, Key::Digit9 => 0x19, Key::Digit7 => 0x1A, Key::Digit8 => 0x1C, Key::Digit0 => 0x1D,
+        Key::Equal => 0x18, Key::Minus => 0x1B, Key::Enter => 0x24, Key::Tab => 0x30, Key::Space => 0x31,
+        Key::Backspace => 0x33, Key::Escape => 0x35, Key::Delete => 0x75,
+        Key::Meta => 0x37, Key::Shift => 0x38, Key::Alt => 0x3A, Key::Ctrl => 0x3B,
+        Key::VolumeUp => 0x48, Key::VolumeDown => 0x49, Key::Mute => 0x4A,
+        Key::F1 => 0x7A, Key::F2 => 0x78, Key::F3 => 0x63, Key::F4 => 0x76, Key::F5 => 0x60, Key::F6 => 0x61,
+        Key::F7 => 0x62, Key::F8 => 0x64, Key::F9 => 0x65, Key::F10 => 0x6D, Key::F11 => 0x67, Key::F12 => 0x6F,
+        Key::Home => 0x73, Key::PageUp => 0x74, Key::End => 0x77, Key::PageDown => 0x79,
+        Key::Left => 0x7B, Key::Right => 0x7C, Key::Down => 0x7D, Key::Up => 0x7E,
+    }
+}
+
+fn modifier(key: Key) -> CGEventFlags {
+    match key {
+        Key::Ctrl => CGEventFlags::CGEventFlagControl,
+        Key::Shift => CGEventFlags::CGEventFlagShift,
+        Key::Alt => CGEventFlags::CGEventFlagAlternate,
+        Key::Meta => CGEventFlags::CGEventFlagCommand,
+        _ => CGEventFlags::empty(),
+    }
+}
+
+impl Injector for Quartz {
+    fn inject(&mut self, event: Event) {
+        let Ok(source) = CGEventSource::new(CGEventSourceStateID::HIDSystemState) else { return };
+        if let Event::Press(button) | Event::Release(button) = event {
+            self.buttons[button as usize] = matches!(event, Event::Press(_));
+        }
+        // Desktop coordinates are Quartz global coordinates, top left of the main display
+        let position = match event {
+            Event::Move(x, y) => CGPoint::new(x, y),
+            _ => self.position,
+        };
+        self.position = position;
+        let mouse = |kind, button| CGEvent::new_mouse_event(source.clone(), kind, position, button);
+        let posted = match event {
+            Event::Move(..) => match self.buttons {
+                [true, _] => mouse(CGEventType::LeftMouseDragged, CGMouseButton::Left),
+                [_, true] => mouse(CGEventType::RightMouseDragged, CGMouseButton::Right),
+                _ => mouse(CGEventType::MouseMoved, CGMouseButton::Left),
+            },
+            Event::Press(Button::Left) => mouse(CGEventType::LeftMouseDown, CGMouseButton::Left),
+            Event::Release(Button::Left) => mouse(CGEventType::LeftMouseUp, CGMouseButton::Left),
+            Event::Press(Button::Right) => mouse(CGEventType::RightMouseDown, CGMouseButton::Right),
+            Event::Release(Button::Right) => mouse(CGEventType::RightMouseUp, CGMouseButton::Right),
+            // The second wheel axis counts positive to the left
+            Event::Wheel(x, y) => CGEvent::new_scroll_event(source.clone(), ScrollEventUnit::LINE, 2, y.round() as i32, -x.round() as i32, 0),
+            Event::Key(key, pressed) => {
+                self.flags.set(modifier(key), pressed);
+                CGEvent::new_keyboard_event(source.clone(), code(key), pressed)
+            }
+        };
+        if let Ok(posted) = posted {
+            posted.set_flags(self.flags);
+            posted.post(CGEventTapLocation::HID);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{capture::{self, PanelAction, Placement}, device::Touch};
+use std::{sync::{mpsc, Arc, Mutex}, thread, time::{Duration, Instant}};
+
+mod gesture;
+mod keys;
+mod session;
+mod trackpad;
+mod viewport;
+
+pub use self::gesture::{Binding, Gesture, GestureMap, Recognizer};
+pub use self::keys::{Key, Shortcut};
+pub use self::session::{SessionWriter, load_session, record, replay};
+pub use self::trackpad::{Acceleration, Trackpad};
+pub use self::viewport::ViewportControl;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Left,
+    Right,
+}
+
+/// Input sent to the host, positions in desktop coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Move(f64, f64),
+    Press(Button),
+    Release(Button),
+    /// Wheel notches, positive scrolls right and up
+    Wheel(f64, f64),
+    Key(Key, bool),
+}
+
+pub trait Injector: Send {
+    fn inject(&mut self, event: Event);
+}
+
+impl Shortcut {
+    /// Presses the keys in order and releases them the other way round.
+    pub fn events(&self) -> Vec<Event> {
+        let press = self.0.iter().map(|key| Event::Key(*key, true));
+        let release = self.0.iter().rev().map(|key| Event::Key(*key, false));
+        press.chain(release).collect()
+    }
+}
+
+/// Keeps and prints the events instead of moving the real pointer, for `--simulate`. Clones share the events.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    pub events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl Injector for Recorder {
+    fn inject(&mut self, event: Event) {
+        println!("Inject: {:?}", event);
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+/// What touches drive.
+pub enum Target {
+    /// Pointer and keyboard of the host
+    Host(GestureMap, Box<dyn Injector>),
+    /// Host pointer moved relative to where it is, or like `Host` while the trackpad is toggled off
+    Trackpad(Trackpad, GestureMap, Box<dyn Injector>),
+    /// The part of the desktop the tablets show
+    Viewport,
+    /// Buttons of `--panel`, the injector types their shortcuts. It is opened on the first
+    /// shortcut, so a panel of commands needs no input device
+    Panel(Option<Box<dyn Injector>>),
+    /// Strokes drawn over the stream
+    Annotate,
+}
+
+/// How often a held finger is checked for a long press
+const TICK: Duration = Duration::from_millis(50);
+
+/// Touch Thread: recognises gestures per tablet and injects what they map to, or moves the viewport.
+pub fn run(touch_rx: mpsc::Receiver<Touch>, placement: Placement, mut target: Target) {
+    let start = Instant::now();
+    let mut recognizers: Vec<Recognizer> = Vec::new();
+    let mut controls: Vec<ViewportControl> = Vec::new();
+    loop {
+        let report = touch_rx.recv_timeout(TICK);
+        let time = start.elapsed();
+        let gestures: Vec<_> = match report {
+            Ok((device, points)) => {
+                if recognizers.len() <= device {
+                    recognizers.resize_with(device + 1, Recognizer::default);
+                    controls.resize_with(device + 1, ViewportControl::default);
+                }
+                let points: Vec<_> = points.iter().map(|&(u, v)| placement.upright(device, (u as f64, v as f64))).collect();
+                recognizers[device].update(time, &points).into_iter().map(|gesture| (device, gesture)).collect()
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => recognizers.iter_mut().enumerate()
+                .flat_map(|(device, recognizer)| recognizer.tick(time).into_iter().map(move |gesture| (device, gesture)))
+                .collect(),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        for (device, gesture) in gestures {
+            match &mut target {
+                Target::Host(map, injector) => {
+                    for event in map.events(gesture, |point| placement.to_desktop(device, point)) {
+                        injector.inject(event);
+                    }
+                }
+                Target::Trackpad(trackpad, map, injector) => {
+                    let events = if map.toggles_trackpad(gesture) {
+                        println!("Trackpad: {}", if trackpad.toggle() { "On" } else { "Off" });
+                        Vec::new()
+                    } else if trackpad.enabled() {
+                        trackpad.events(gesture, time, map)
+                    } else {
+                        map.events(gesture, |point| placement.to_desktop(device, point))
+                    };
+                    for event in events {
+                        injector.inject(event);
+                    }
+                }
+                Target::Panel(injector) => {
+                    let Some(deck) = placement.panel() else { continue };
+                    let action = deck.lock().unwrap().touch(gesture, |point| placement.to_desktop(device, point));
+                    match action {
+                        Some(PanelAction::Keys(shortcut)) => {
+                            if injector.is_none() {
+                                match system() {
+                                    Ok(opened) => *injector = Some(opened),
+                                    Err(e) => println!("Panel: {}", e),
+                                }
+                            }
+                            if let Some(injector) = injector {
+                                shortcut.events().into_iter().for_each(|event| injector.inject(event));
+                            }
+                        }
+                        Some(PanelAction::Command(command)) => match capture::shell(&command).spawn() {
+                            // Reaped in the background so long running commands do not hold up the panel
+                            Ok(mut child) => drop(thread::spawn(move || child.wait())),
+                            Err(e) => println!("Panel: {}: {}", command, e),
+                        },
+                        _ => {}
+                    }
+                }
+                Target::Annotate => {
+                    let Some(annotation) = placement.annotation() else { continue };
+                    annotation.lock().unwrap().touch(gesture, |point| placement.to_canvas(device, point));
+                }
+                Target::Viewport => {
+                    let Some(viewport) = placement.viewport() else { continue };
+                    controls[device].apply(&mut viewport.lock().unwrap(), time, gesture, |point| placement.in_picture(device, point));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+pub use self::macos::system;
+
+#[cfg(target_os = "windows")]
+mod windows;
+#[cfg(target_os = "windows")]
+pub use self::windows::system;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub use self::linux::system;
@@ -0,0 +1,75 @@
+use crate::device::Touch;
+use std::{fs::File, io::{LineWriter, Write}, path::Path, sync::mpsc, thread, time::{Duration, Instant}};
+
+const HEADER: &str = "# tab5 touch session: milliseconds device x,y... (no points when every finger lifted)";
+
+/// Touch reports with the time since the recording started, one line each.
+pub struct SessionWriter {
+    file: LineWriter<File>,
+    start: Instant,
+}
+
+impl SessionWriter {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let mut file = LineWriter::new(File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?);
+        writeln!(file, "{}", HEADER).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(SessionWriter { file, start: Instant::now() })
+    }
+
+    fn write(&mut self, (device, points): &Touch) -> std::io::Result<()> {
+        write!(self.file, "{} {}", self.start.elapsed().as_millis(), device)?;
+        for (x, y) in points {
+            write!(self.file, " {},{}", x, y)?;
+        }
+        writeln!(self.file)
+    }
+}
+
+/// Reads a file `SessionWriter` wrote.
+pub fn load_session(path: &Path) -> Result<Vec<(Duration, Touch)>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_session(&text).map_err(|e| format!("{}:{}", path.display(), e))
+}
+
+/// Touch reports in the format `SessionWriter` writes, blank lines and `#` comments are skipped.
+pub fn parse_session(text: &str) -> Result<Vec<(Duration, Touch)>, String> {
+    text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| parse_line(line).map_err(|e| format!("{}: {}", i + 1, e)))
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<(Duration, Touch), String> {
+    let mut fields = line.split_whitespace();
+    let mut next = |name: &str| fields.next().ok_or(format!("expected {}", name));
+    let time = next("milliseconds")?.parse().map_err(|_| "invalid milliseconds".to_string())?;
+    let device = next("device")?.parse().map_err(|_| "invalid device".to_string())?;
+    let points = fields.map(|point| {
+        let (x, y) = point.split_once(',').ok_or(format!("expected x,y: {}", point))?;
+        Ok((x.parse().map_err(|_| format!("invalid x: {}", x))?, y.parse().map_err(|_| format!("invalid y: {}", y))?))
+    }).collect::<Result<Vec<(u16, u16)>, String>>()?;
+    Ok((Duration::from_millis(time), (device, points)))
+}
+
+/// Touch Record Thread: writes every report and passes it on when touches drive something too.
+pub fn record(mut writer: SessionWriter, raw_rx: mpsc::Receiver<Touch>, touch_tx: Option<mpsc::Sender<Touch>>) {
+    for touch in raw_rx {
+        if let Err(e) = writer.write(&touch) {
+            println!("Touch Record: {}", e);
+            return;
+        }
+        if let Some(touch_tx) = &touch_tx {
+            let _ = touch_tx.send(touch);
+        }
+    }
+}
+
+/// Touch Replay Thread: sends the recorded reports at their times, `speed` 2.0 plays twice as fast.
+pub fn replay(session: Vec<(Duration, Touch)>, speed: f64, touch_tx: mpsc::Sender<Touch>) {
+    let start = Instant::now();
+    for (time, touch) in session {
+        thread::sleep(time.div_f64(speed).saturating_sub(start.elapsed()));
+        if touch_tx.send(touch).is_err() { return }
+    }
+    println!("Touch Replay Finished");
+}
@@ -0,0 +1,105 @@
+use crate::{capture::pointer, inject::{Event, gesture::{Gesture, GestureMap}}};
+use std::time::Duration;
+use clap::ValueEnum;
+
+/// Finger speed in output pixels per millisecond at which the curves double the gain
+const DOUBLE_AT: f64 = 2.0;
+const MAX_GAIN: f64 = 6.0;
+
+/// How the pointer speeds up with the finger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Acceleration {
+    /// Pointer travel is finger travel times the speed
+    Flat,
+    Linear,
+    /// Slow moves stay precise, fast ones cover the screen
+    Quadratic,
+}
+
+impl Acceleration {
+    fn gain(self, speed: f64) -> f64 {
+        let relative = speed / DOUBLE_AT;
+        match self {
+            Acceleration::Flat => 1.0,
+            Acceleration::Linear => 1.0 + relative,
+            Acceleration::Quadratic => 1.0 + relative * relative,
+        }.min(MAX_GAIN)
+    }
+}
+
+/// The tablet as a laptop trackpad: one finger moves the pointer relative to where it is, a tap
+/// clicks, two-finger scroll, pinch and long press go through the gesture map as usual.
+pub struct Trackpad {
+    speed: f64,
+    acceleration: Acceleration,
+    /// Pointer position the moves are added to
+    position: Option<(f64, f64)>,
+    /// Last finger position in output coordinates, and when
+    last: Option<((f64, f64), Duration)>,
+    moving: bool,
+    /// Off, touches point where the finger is until a toggle turns it back on
+    enabled: bool,
+}
+
+impl Trackpad {
+    /// `speed` is desktop pixels per output pixel of finger travel before acceleration.
+    pub fn new(speed: f64, acceleration: Acceleration) -> Self {
+        Trackpad { speed, acceleration, position: None, last: None, moving: false, enabled: true }
+    }
+
+    pub fn with_enabled(self, enabled: bool) -> Self {
+        Trackpad { enabled, ..self }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Switches on or off, the finger that asked is not followed until it lifts. Returns the new state.
+    pub fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled;
+        self.last = None;
+        self.moving = false;
+        self.enabled
+    }
+
+    pub fn events(&mut self, gesture: Gesture, time: Duration, map: &mut GestureMap) -> Vec<Event> {
+        match gesture {
+            Gesture::Point(x, y) => {
+                let previous = self.last.replace(((x, y), time));
+                let Some(((px, py), since)) = previous.filter(|_| self.moving) else {
+                    // Finger down, pick up the pointer where the mouse left it
+                    self.position = pointer::position().or(self.position);
+                    return Vec::new();
+                };
+                let (dx, dy) = (x - px, y - py);
+                let elapsed = time.saturating_sub(since).as_secs_f64() * 1000.0;
+                let speed = if elapsed > 0.0 { dx.hypot(dy) / elapsed } else { 0.0 };
+                let gain = self.speed * self.acceleration.gain(speed);
+                let (x, y) = self.position.unwrap_or_default();
+                let position = (x + dx * gain, y + dy * gain);
+                self.position = Some(position);
+                vec![Event::Move(position.0, position.1)]
+            }
+            // Past the slop the finger moves the pointer, it does not drag
+            Gesture::Press => {
+                self.moving = true;
+                Vec::new()
+            }
+            Gesture::Release => {
+                self.moving = false;
+                self.last = None;
+                Vec::new()
+            }
+            Gesture::Tap(..) => {
+                self.last = None;
+                map.events(Gesture::Press, |_| None).into_iter().chain(map.events(Gesture::Release, |_| None)).collect()
+            }
+            // Positions on the panel mean nothing for the pointer, these act where it is
+            gesture => {
+                self.last = None;
+                map.events(gesture, |_| None)
+            }
+        }
+    }
+}
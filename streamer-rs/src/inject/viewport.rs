@@ -0,0 +1,82 @@
+use crate::{capture::Viewport, inject::gesture::Gesture};
+use std::time::Duration;
+
+/// Second tap within this time and distance in output pixels resets the view
+const DOUBLE_TAP: Duration = Duration::from_millis(300);
+const DOUBLE_TAP_DISTANCE: f64 = 40.0;
+/// A finger resting longer than this before lifting does not fling
+const FLING_WINDOW: Duration = Duration::from_millis(100);
+/// Weight of the newest move in the fling speed
+const FLING_SMOOTHING: f64 = 0.5;
+
+/// Gestures of one tablet steering the viewport: drag and two-finger scroll pan, pinch zooms
+/// around the fingers, a flick keeps gliding and a double tap shows the full view again.
+#[derive(Default)]
+pub struct ViewportControl {
+    dragging: bool,
+    /// Last finger position as a fraction of the picture, and when
+    last: Option<((f64, f64), Duration)>,
+    /// Pictures per second
+    velocity: (f64, f64),
+    /// Previous tap in output pixels, and when
+    tap: Option<((f64, f64), Duration)>,
+}
+
+impl ViewportControl {
+    /// `in_picture` maps output coordinates to fractions of the shown picture.
+    pub fn apply(&mut self, viewport: &mut Viewport, time: Duration, gesture: Gesture, in_picture: impl Fn((f64, f64)) -> Option<(f64, f64)>) {
+        let (Some(origin), Some(unit)) = (in_picture((0.0, 0.0)), in_picture((1.0, 1.0))) else { return };
+        let fraction = |(dx, dy): (f64, f64)| (dx * (unit.0 - origin.0), dy * (unit.1 - origin.1));
+        match gesture {
+            Gesture::Point(x, y) => {
+                let Some(point) = in_picture((x, y)) else { return };
+                match self.last {
+                    Some((last, since)) if self.dragging => {
+                        let delta = (point.0 - last.0, point.1 - last.1);
+                        viewport.pan(delta);
+                        let elapsed = time.saturating_sub(since).as_secs_f64();
+                        if elapsed > 0.0 {
+                            let speed = (delta.0 / elapsed, delta.1 / elapsed);
+                            self.velocity = (
+                                self.velocity.0 + (speed.0 - self.velocity.0) * FLING_SMOOTHING,
+                                self.velocity.1 + (speed.1 - self.velocity.1) * FLING_SMOOTHING,
+                            );
+                        }
+                    }
+                    _ => viewport.stop(),
+                }
+                self.last = Some((point, time));
+            }
+            Gesture::Press => {
+                self.dragging = true;
+                self.velocity = (0.0, 0.0);
+            }
+            Gesture::Release => {
+                if self.last.is_some_and(|(_, since)| time.saturating_sub(since) < FLING_WINDOW) {
+                    viewport.fling(self.velocity);
+                }
+                self.dragging = false;
+                self.last = None;
+            }
+            Gesture::Tap(x, y) => {
+                let double = self.tap.is_some_and(|((tx, ty), since)| {
+                    time.saturating_sub(since) < DOUBLE_TAP && (x - tx).hypot(y - ty) < DOUBLE_TAP_DISTANCE
+                });
+                if double {
+                    viewport.reset();
+                    self.tap = None;
+                } else {
+                    self.tap = Some(((x, y), time));
+                }
+                self.last = None;
+            }
+            Gesture::Scroll(dx, dy) => viewport.pan(fraction((dx, dy))),
+            Gesture::Pinch { factor, x, y } => {
+                if let Some(focus) = in_picture((x, y)) {
+                    viewport.zoom(factor, focus);
+                }
+            }
+            Gesture::LongPress(..) => self.last = None,
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::inject::{Button, Event, Injector, Key};
+use windows::Win32::UI::{Input::KeyboardAndMouse::*, WindowsAndMessaging::{GetSystemMetrics, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN, WHEEL_DELTA}};
+
+/// SendInput with absolute coordinates over the virtual screen, all monitors included.
+pub struct SendInputInjector;
+
+pub fn system() -> Result<Box<dyn Injector>, String> {
+    Ok(Box::new(SendInputInjector))
+}
+
+fn code(key: Key) -> VIRTUAL_KEY {
+    let letter = |c: u8| VIRTUAL_KEY(c as u16);
+    match key {
+        Key::Ctrl => VK_CONTROL, Key::Shift => VK_SHIFT, Key::Alt => VK_MENU, Key::Meta => VK_LWIN,
+        Key::A => letter(b'A'), Key::B => letter(b'B'), Key::C => letter(b'C'), Key::D => letter(b'D'),
+        Key::E => letter(b'E'), Key::F => letter(b'F'), Key::G => letter(b'G'), Key::H => letter(b'H'),
+        Key::I => letter(b'I'), Key::J => letter(b'J'), Key::K => letter(b'K'), Key::L => letter(b'L'),
+        Key::M => letter(b'M'), Key::N => letter(b'N'), Key::O => letter(b'O'), Key::P => letter(b'P'),
+        Key::Q => letter(b'Q'), Key::R => letter(b'R'), Key::S => letter(b'S'), Key::T => letter(b'T'),
+        Key::U => letter(b'U'), Key::V => letter(b'V'), Key::W => letter(b'W'), Key::X => letter(b'X'),
+        Key::Y => letter(b'Y'), Key::Z => letter(b'Z'),
+        Key::Digit0 => letter(b'0'), Key::Digit1 => letter(b'1'), Key::Digit2 => letter(b'2'), Key::Digit3 => letter(b'3'),
+        Key::Digit4 => letter(b'4'), Key::Digit5 => letter(b'5'), Key::Digit6 => letter(b'6'), Key::Digit7 => letter(b'7'),
+        Key::Digit8 => letter(b'8'), Key::Digit9 => letter(b'9'),
+        Key::F1 => VK_F1, Key::F2 => VK_F2, Key::F3 => VK_F3, Key::F4 => VK_F4, Key::F5 => VK_F5, Key::F6 => VK_F6,
+        Key::F7 => VK_F7, Key::F8 => VK_F8, Key::F9 => VK_F9, Key::F10 => VK_F10, Key::F11 => VK_F11, Key::F12 => VK_F12,
+        Key::Up => VK_UP, Key::Down => VK_DOWN, Key::Left => VK_LEFT, Key::Right => VK_RIGHT,
+        Key::PageUp => VK_PRIOR, Key::PageDown => VK_NEXT, Key::Home => VK_HOME, Key::End => VK_END,
+        Key::Enter => VK_RETURN, Key::Escape => VK_ESCAPE, Key::Tab => VK_TAB, Key::Space => VK_SPACE,
+        Key::Backspace => VK_BACK, Key::Delete => VK_DELETE, Key::Minus => VK_OEM_MINUS, Key::Equal => VK_OEM_PLUS,
+        Key::VolumeUp => VK_VOLUME_UP, Key::VolumeDown => VK_VOLUME_DOWN, Key::Mute => VK_VOLUME_MUTE,
+    }
+}
+
+impl Injector for SendInputInjector {
+    fn inject(&mut self, event: Event) {
+        let input = match event {
+            Event::Move(x, y) => {
+                // Absolute input is normalised to 0-65535 across the virtual screen
+                let (left, top, width, height) = unsafe {(
+                    GetSystemMetrics(SM_XVIRTUALSCREEN) as f64, GetSystemMetrics(SM_YVIRTUALSCREEN) as f64,
+                    GetSystemMetrics(SM_CXVIRTUALSCREEN) as f64, GetSystemMetrics(SM_CYVIRTUALSCREEN) as f64,
+                )};
+                let dx = ((x - left) * 65535.0 / (width - 1.0).max(1.0)).round() as i32;
+                let dy = ((y - top) * 65535.0 / (height - 1.0).max(1.0)).round() as i32;
+                vec![mouse(MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE | MOUSEEVENTF_VIRTUALDESK, dx, dy, 0)]
+            }
+            Event::Press(Button::Left) => vec![mouse(MOUSEEVENTF_LEFTDOWN, 0, 0, 0)],
+            Event::Release(Button::Left) => vec![mouse(MOUSEEVENTF_LEFTUP, 0, 0, 0)],
+            Event::Press(Button::Right) => vec![mouse(MOUSEEVENTF_RIGHTDOWN, 0, 0, 0)],
+            Event::Release(Button::Right) => vec![mouse(MOUSEEVENTF_RIGHTUP, 0, 0, 0)],
+            Event::Wheel(x, y) => [(MOUSEEVENTF_HWHEEL, x), (MOUSEEVENTF_WHEEL, y)].into_iter()
+                .filter(|(_, notches)| *notches != 0.0)
+                .map(|(flags, notches)| mouse(flags, 0, 0, (notches * WHEEL_DELTA as f64).round() as i32))
+                .collect(),
+            Event::Key(key, pressed) => {
+                let flags = if pressed { KEYBD_EVENT_FLAGS(0) } else { KEYEVENTF_KEYUP };
+                vec![INPUT {
+                    r#type: INPUT_KEYBOARD,
+                    Anonymous: INPUT_0 { ki: KEYBDINPUT { wVk: code(key), wScan: 0, dwFlags: flags, time: 0, dwExtraInfo: 0 } },
+                }]
+            }
+        };
+        unsafe { SendInput(&input, size_of::<INPUT>() as i32) };
+    }
+}
+
+fn mouse(flags: MOUSE_EVENT_FLAGS, dx: i32, dy: i32, data: i32) -> INPUT {
+    INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 { mi: MOUSEINPUT { dx, dy, mouseData: data as u32, dwFlags: flags, time: 0, dwExtraInfo: 0 } },
+    }
+}
@@ -1,12 +1,13 @@
 use std::time::Duration;
 use clap::Parser;
-use rusb::{DeviceHandle, GlobalContext};
 
+mod audio;
 mod capture;
-
-const VID: u16 = 0x303a;
-const PID: u16 = 0x4020;
-const EP_OUT: u8 = 0x01;
+mod device;
+mod inject;
+mod mux;
+mod simulator;
+mod sync;
 
 #[derive(Parser, Debug)]
 #[command(version, about, author = "Hiroki Kawakami")]
@@ -14,43 +15,518 @@ struct Args {
     /// Display Select
     #[arg(long)]
     display: Option<usize>,
+
+    /// Capture the Window whose Title matches this Regex
+    #[arg(long, conflicts_with_all = ["display", "pid"])]
+    window: Option<regex::Regex>,
+
+    /// Capture the Window owned by this Process
+    #[arg(long, conflicts_with = "display")]
+    pid: Option<u32>,
+
+    /// Hide Windows whose Title matches this Regex (repeatable, not on Linux)
+    #[arg(long = "exclude-window")]
+    exclude_windows: Vec<regex::Regex>,
+
+    /// JPEG Chroma Subsampling
+    #[arg(long, value_enum, default_value_t = capture::Subsampling::Sub420)]
+    subsampling: capture::Subsampling,
+
+    /// Resize Algorithm
+    #[arg(long, value_enum, default_value_t = capture::Scaler::Nearest)]
+    scaler: capture::Scaler,
+
+    /// Sharpen Amount after Resize (0 = off)
+    #[arg(long, default_value_t = 0.0)]
+    sharpen: f32,
+
+    /// Brightness Offset (-1 to 1)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    brightness: f32,
+
+    /// Contrast Factor (1 = unchanged)
+    #[arg(long, default_value_t = 1.0)]
+    contrast: f32,
+
+    /// Gamma Correction (1 = unchanged)
+    #[arg(long, default_value_t = 1.0)]
+    gamma: f32,
+
+    /// White Point in Kelvin (6500 = unchanged)
+    #[arg(long, default_value_t = 6500.0)]
+    temperature: f32,
+
+    /// Convert to Grayscale
+    #[arg(long)]
+    grayscale: bool,
+
+    /// Invert Colors (Night Mode)
+    #[arg(long)]
+    invert: bool,
+
+    /// Aspect Ratio Handling
+    #[arg(long, value_enum, default_value_t = capture::FitMode::Stretch)]
+    fit: capture::FitMode,
+
+    /// Letterbox Background Color (RRGGBB)
+    #[arg(long, default_value = "000000")]
+    background: capture::Color,
+
+    /// Capture Region in Desktop Coordinates (x,y,width,height)
+    #[arg(long)]
+    region: Option<capture::fit::Rect>,
+
+    /// Hide an Area in Desktop Coordinates (x,y,width,height[:black|pixelate|blur], repeatable)
+    #[arg(long = "mask")]
+    masks: Vec<capture::Mask>,
+
+    /// Stamp text=LABEL, clock[=FORMAT], hostname or watermark=FILE.jpg (JPEG only, convert PNG first), with an optional @top-left, @top-right, @bottom-left, @bottom-right or @center (repeatable)
+    #[arg(long = "overlay")]
+    overlays: Vec<capture::Overlay>,
+
+    /// Overlay Text Height in Pixels
+    #[arg(long, default_value_t = 24.0)]
+    overlay_size: f32,
+
+    /// Overlay Text Color (RRGGBB)
+    #[arg(long, default_value = "ffffff")]
+    overlay_color: capture::Color,
+
+    /// Overlay Opacity (0-1)
+    #[arg(long, default_value_t = 0.8)]
+    overlay_opacity: f32,
+
+    /// Watermark Opacity (0-1, default: --overlay-opacity)
+    #[arg(long)]
+    watermark_opacity: Option<f32>,
+
+    /// TrueType Font for Overlays (default: bundled DejaVu Sans Mono)
+    #[arg(long)]
+    font: Option<std::path::PathBuf>,
+
+    /// Follow the Mouse Cursor, Magnified by this Factor (1 = Desktop Pixels 1:1)
+    #[arg(long, num_args = 0..=1, default_missing_value = "1.0", value_parser = parse_zoom)]
+    zoom: Option<f64>,
+
+    /// Cursor Following Speed (0-1, 1 = No Smoothing)
+    #[arg(long, default_value_t = 0.2, value_parser = parse_zoom_smoothing)]
+    zoom_smoothing: f64,
+
+    /// Show Stream Statistics on the Tablet
+    #[arg(long)]
+    hud: bool,
+
+    /// Picture-in-Picture Source in an Output Rectangle (display=INDEX|window=REGEX|pid=PID@x,y,width,height, repeatable)
+    #[arg(long = "pip")]
+    pips: Vec<capture::Pip>,
+
+    /// Scene File (TOML) Laying out Several Sources, Reloaded on Change
+    #[arg(long, conflicts_with_all = ["display", "window", "pid", "pip", "region", "zoom"])]
+    scene: Option<std::path::PathBuf>,
+
+    /// Button Panel File (TOML) Shown instead of a Capture, Tapping a Button Runs its Command or Shortcut
+    #[arg(long, conflicts_with_all = ["display", "window", "pid", "pip", "region", "zoom", "scene", "wall", "viewport"])]
+    panel: Option<std::path::PathBuf>,
+
+    /// Draw on the Stream with a Finger, Saving Snapshots (palette button) as PNG into this Directory
+    #[arg(long, num_args = 0..=1, default_missing_value = ".", conflicts_with_all = ["viewport", "panel"])]
+    annotate: Option<std::path::PathBuf>,
+
+    /// Draw a Scaled-up Cursor after Resize instead of the Captured One
+    #[arg(long)]
+    cursor: bool,
+
+    /// Cursor Height in Output Pixels
+    #[arg(long, default_value_t = 32.0)]
+    cursor_size: f64,
+
+    /// Highlight Clicks with a Ripple
+    #[arg(long, requires = "cursor")]
+    click_highlight: bool,
+
+    /// Span the Capture across a Grid of Tablets (COLSxROWS)
+    #[arg(long, conflicts_with = "scene")]
+    wall: Option<capture::Wall>,
+
+    /// Distance between the Screens of Neighbouring Tablets in Millimetres
+    #[arg(long, default_value_t = 0.0, requires = "wall")]
+    wall_bezel: f64,
+
+    /// USB Serial of the Tablet at each Wall Position, Row by Row (repeatable)
+    #[arg(long = "wall-device", requires = "wall")]
+    wall_devices: Vec<String>,
+
+    /// Use Touches on the Tablet as Mouse Input
+    #[arg(long)]
+    touch: bool,
+
+    /// Pan and Zoom the Streamed View with Touches instead of Clicking, Double Tap Shows Everything
+    #[arg(long, requires = "touch", conflicts_with_all = ["zoom", "scene", "gestures"])]
+    viewport: bool,
+
+    /// Move the Pointer like a Laptop Trackpad instead of Clicking where the Finger is (--gesture long-press=trackpad switches while streaming)
+    #[arg(long, requires = "touch", conflicts_with_all = ["viewport", "annotate", "panel"])]
+    trackpad: bool,
+
+    /// Trackpad Pointer Travel per Pixel of Finger Travel, before Acceleration
+    #[arg(long, default_value_t = 1.5)]
+    trackpad_speed: f64,
+
+    /// Trackpad Acceleration Curve
+    #[arg(long, value_enum, default_value_t = inject::Acceleration::Quadratic)]
+    trackpad_acceleration: inject::Acceleration,
+
+    /// Map a Gesture to Host Input: scroll|pinch|long-press=none|wheel[:KEYS]|keys:SHORTCUT[/SHORTCUT], or long-press=right-click|trackpad (repeatable)
+    #[arg(long = "gesture", requires = "touch")]
+    gestures: Vec<inject::Binding>,
+
+    /// Play a 16-bit WAV File on the Tablet Speaker, or Raw PCM with --audio-raw (- = stdin, e.g. from parec)
+    #[arg(long)]
+    audio: Option<std::path::PathBuf>,
+
+    /// Read --audio as Headerless 16-bit Little-endian PCM (RATE:CHANNELS)
+    #[arg(long, requires = "audio")]
+    audio_raw: Option<audio::RawFormat>,
+
+    /// Audio Coding on the USB Link
+    #[arg(long, value_enum, default_value_t = audio::Codec::Adpcm, requires = "audio")]
+    audio_codec: audio::Codec,
+
+    /// Speaker Volume (0-100)
+    #[arg(long, default_value_t = 60, requires = "audio")]
+    audio_volume: u8,
+
+    /// Start the Audio File over at its End
+    #[arg(long, requires = "audio")]
+    audio_loop: bool,
+
+    /// Time from Sending a Frame until all Tablets Flip Together, in Milliseconds
+    #[arg(long, default_value_t = 50)]
+    present_delay: u64,
+
+    /// Drive Simulated Tablets instead of USB Devices, Print how far apart they Flip and the Touch Input they would give
+    #[arg(long, conflicts_with = "wall_devices")]
+    simulate: bool,
+
+    /// Print per-frame cost of each resize algorithm and exit
+    #[arg(long)]
+    benchmark: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Stream as usual and Write every Touch Report of the Tablets to a File
+    TouchRecord {
+        file: std::path::PathBuf,
+    },
+    /// Stream as usual and Play a Recorded File back as Touch Input
+    TouchReplay {
+        file: std::path::PathBuf,
+
+        /// Playback Speed (2 = Twice as Fast)
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+}
+
+fn parse_zoom(s: &str) -> Result<f64, String> {
+    let factor: f64 = s.parse().map_err(|_| format!("Invalid zoom factor: {}", s))?;
+    if !factor.is_finite() || factor <= 0.0 {
+        return Err(format!("Zoom factor must be greater than 0: {}", s));
+    }
+    Ok(factor)
+}
+
+fn parse_zoom_smoothing(s: &str) -> Result<f64, String> {
+    let smoothing: f64 = s.parse().map_err(|_| format!("Invalid zoom smoothing: {}", s))?;
+    if !(smoothing > 0.0 && smoothing <= 1.0) {
+        return Err(format!("Zoom smoothing must be greater than 0 and at most 1: {}", s));
+    }
+    Ok(smoothing)
 }
 
 fn main() {
     let args = Args::parse();
 
-    if !capture::check_permission() {
+    let mut options = capture::Options {
+        display: args.display,
+        window: args.window.map(capture::WindowSelector::Title)
+            .or(args.pid.map(capture::WindowSelector::Pid)),
+        exclude_windows: args.exclude_windows.into_iter().map(capture::WindowSelector::Title).collect(),
+        subsampling: args.subsampling,
+        supported_subsampling: Vec::new(),
+        scaler: args.scaler,
+        sharpen: args.sharpen,
+        brightness: args.brightness,
+        contrast: args.contrast,
+        gamma: args.gamma,
+        temperature: args.temperature,
+        grayscale: args.grayscale,
+        invert: args.invert,
+        fit: args.fit,
+        background: args.background,
+        region: args.region,
+        masks: args.masks,
+        overlays: args.overlays,
+        overlay_size: args.overlay_size,
+        overlay_color: args.overlay_color,
+        overlay_opacity: args.overlay_opacity,
+        watermark_opacity: args.watermark_opacity,
+        font: args.font,
+        zoom: args.zoom,
+        zoom_smoothing: args.zoom_smoothing,
+        viewport: args.viewport,
+        hud: args.hud,
+        cursor: args.cursor,
+        cursor_size: args.cursor_size,
+        click_highlight: args.click_highlight,
+        pips: args.pips,
+        scene: None,
+        annotate: args.annotate.clone(),
+        panel: None,
+        wall: args.wall.map(|wall| wall.with_bezel(args.wall_bezel)),
+    };
+
+    // The screen share portal decides what is shared, windows can not be left out of it
+    if cfg!(target_os = "linux") && !options.exclude_windows.is_empty() {
+        println!("--exclude-window is not supported on Linux; use --mask");
+        return;
+    }
+
+    // Without a pointer position (Wayland) the cursor could not be drawn, the captured one stays
+    if options.cursor && capture::pointer::position().is_none() {
+        println!("Cursor: pointer position unavailable, keeping the captured cursor");
+        options.cursor = false;
+    }
+
+    if let Some(path) = &args.scene {
+        match capture::Scene::load(path) {
+            Ok(scene) => options.scene = Some(scene),
+            Err(e) => {
+                println!("Scene: {}", e);
+                return;
+            }
+        }
+    }
+
+    if let Some(path) = &args.panel {
+        match capture::Panel::load(path) {
+            Ok(panel) => options.panel = Some(panel),
+            Err(e) => {
+                println!("Panel: {}", e);
+                return;
+            }
+        }
+    }
+
+    let audio = match &args.audio {
+        Some(path) => match audio::Source::open(path, args.audio_raw) {
+            Ok(source) => Some(source),
+            Err(e) => {
+                println!("Audio: {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    if args.benchmark {
+        capture::benchmark_scalers(&options);
+        return;
+    }
+
+    // The panel is drawn here, nothing is captured
+    if options.panel.is_none() && !capture::check_permission() {
         println!("Platform not supported!");
         return;
     }
 
-    let device = open_device().expect("Device Open Failed!");
-    capture::start(args.display, move |capture_context| {
+    let simulated: Vec<simulator::SimulatedDevice> = match args.simulate {
+        true => (0..options.wall.map_or(1, |wall| wall.count())).map(simulator::SimulatedDevice::new).collect(),
+        false => Vec::new(),
+    };
+    let devices: Vec<Box<dyn device::Link>> = match &options.wall {
+        _ if args.simulate => simulated.iter().map(|device| Box::new(device.clone()) as Box<dyn device::Link>).collect(),
+        Some(wall) if args.wall_devices.len() != wall.count() => {
+            println!("Wall needs {} --wall-device serials, connected: {}",
+                wall.count(), device::serials().unwrap_or_default().join(", "));
+            return;
+        }
+        Some(_) => {
+            let mut devices: Vec<Box<dyn device::Link>> = Vec::new();
+            for serial in &args.wall_devices {
+                let device = match device::open_device_by_serial(serial) {
+                    Err(rusb::Error::NotFound) => {
+                        println!("Wall device {} not found, connected: {}", serial, device::serials().unwrap_or_default().join(", "));
+                        return;
+                    }
+                    device => device.expect("Device Open Failed!"),
+                };
+                devices.push(Box::new(device));
+            }
+            devices
+        }
+        None => vec![Box::new(device::open_device().expect("Device Open Failed!"))],
+    };
+    let caps: Vec<_> = devices.iter().map(|device| device.capabilities()).collect();
+    if !caps.iter().all(|caps| caps.supports(args.subsampling)) {
+        println!("Subsampling {} not supported by device!", args.subsampling);
+        return;
+    }
+    if audio.is_some() && !caps[0].audio {
+        println!("Audio not supported by device!");
+        return;
+    }
+    // Tiles of a wall are encoded alike, only what every tablet decodes
+    options.supported_subsampling = caps[0].subsampling.iter()
+        .filter(|s| caps.iter().all(|caps| caps.supports(**s))).copied().collect();
+    let (mut recording, mut replay) = match &args.command {
+        Some(Command::TouchRecord { file }) => match inject::SessionWriter::create(file) {
+            Ok(writer) => (Some(writer), None),
+            Err(e) => {
+                println!("Touch Record: {}", e);
+                return;
+            }
+        },
+        Some(Command::TouchReplay { file, speed }) if *speed > 0.0 => match inject::load_session(file) {
+            Ok(session) => (None, Some((session, *speed))),
+            Err(e) => {
+                println!("Touch Replay: {}", e);
+                return;
+            }
+        },
+        Some(Command::TouchReplay { .. }) => {
+            println!("Touch Replay: speed must be positive");
+            return;
+        }
+        None => (None, None),
+    };
+    let gestures = inject::GestureMap::new(&args.gestures);
+    // A panel always takes touches, its shortcuts go out through the injector too, and so does a replay
+    let touch_input = args.touch || options.panel.is_some() || replay.is_some();
+    let injector = match touch_input && !args.viewport && args.annotate.is_none() {
+        false => None,
+        true if args.simulate => Some(Box::new(inject::Recorder::default()) as Box<dyn inject::Injector>),
+        // The panel opens it on the first shortcut
+        true if options.panel.is_some() => None,
+        true => match inject::system() {
+            Ok(injector) => Some(injector),
+            Err(e) => {
+                println!("Touch: {}", e);
+                return;
+            }
+        },
+    };
+    let mut touch = match injector {
+        _ if args.viewport => Some(inject::Target::Viewport),
+        _ if args.annotate.is_some() => Some(inject::Target::Annotate),
+        injector if options.panel.is_some() => Some(inject::Target::Panel(injector)),
+        Some(injector) if args.trackpad || gestures.trackpad_toggle() => {
+            let trackpad = inject::Trackpad::new(args.trackpad_speed, args.trackpad_acceleration).with_enabled(args.trackpad);
+            Some(inject::Target::Trackpad(trackpad, gestures, injector))
+        }
+        Some(injector) => Some(inject::Target::Host(gestures, injector)),
+        None => None,
+    };
+    let (touch_tx, touch_rx) = std::sync::mpsc::channel::<device::Touch>();
+    let (record_tx, record_rx) = std::sync::mpsc::channel::<device::Touch>();
+    // Tablet touches are not used while a recording plays back
+    let tablet_tx = if replay.is_some() {
+        None
+    } else if recording.is_some() {
+        Some(record_tx)
+    } else {
+        touch.is_some().then(|| touch_tx.clone())
+    };
+    let devices: std::sync::Arc<Vec<_>> = std::sync::Arc::new(devices.into_iter().zip(&caps).enumerate()
+        .map(|(i, (link, caps))| device::Tablet::new(link, caps.mux, i, tablet_tx.clone()))
+        .collect());
+    // A single tablet flips as soon as it has decoded, several wait for a common present time, and so does sound for lip sync
+    let presenter = match (devices.len() > 1 || audio.is_some()) && caps.iter().all(|caps| caps.sync) {
+        true => match sync::Presenter::new(devices.clone(), Duration::from_millis(args.present_delay)) {
+            Ok(presenter) => Some(std::sync::Arc::new(std::sync::Mutex::new(presenter))),
+            Err(e) => {
+                println!("Clock Sync Failed: {}", e);
+                return;
+            }
+        },
+        false => None,
+    };
+    // Audio Thread
+    if let Some(source) = audio {
+        let options = audio::Options { codec: args.audio_codec, volume: args.audio_volume, repeat: args.audio_loop };
+        let (devices, presenter) = (devices.clone(), presenter.clone());
+        std::thread::spawn(move || audio::run(source, options, devices, presenter));
+    }
+    capture::start(options, move |capture_context| {
+        // Touch Thread
+        let touching = touch.is_some();
+        if let Some(target) = touch.take() {
+            let placement = capture_context.placement();
+            std::thread::spawn(move || inject::run(touch_rx, placement, target));
+        }
+        // Touch Record Thread
+        if let Some(writer) = recording.take() {
+            let touch_tx = touching.then(|| touch_tx.clone());
+            std::thread::spawn(move || inject::record(writer, record_rx, touch_tx));
+        }
+        // Touch Replay Thread
+        if let Some((session, speed)) = replay.take() {
+            let touch_tx = touch_tx.clone();
+            std::thread::spawn(move || inject::replay(session, speed, touch_tx));
+        }
         let mut frames: usize = 0;
         let mut transferred: usize = 0;
+        let mut latency = Duration::ZERO;
+        let mut output = None;
         loop {
-            let frame = capture_context.get_frame();
-            if let Ok(size) = device.write_bulk(EP_OUT, &frame.data[..frame.data_size], Duration::from_secs(1)) {
-                transferred += size;
+            // Every tablet gets its tile before the next frame, the wall stays on one sequence
+            let tiles = capture_context.get_frames();
+            let headers = presenter.as_ref().map(|presenter| {
+                presenter.lock().unwrap().headers(&tiles.iter().map(|tile| tile.data_size - 4).collect::<Vec<_>>())
+            });
+            let sizes: Vec<_> = std::thread::scope(|scope| {
+                let writers: Vec<_> = devices.iter().zip(&tiles).enumerate().map(|(i, (device, tile))| {
+                    let header = headers.as_ref().map(|headers| headers[i]);
+                    scope.spawn(move || match header {
+                        // The header replaces the size word the encoder put in front
+                        Some(header) => device.send(mux::Channel::Video, &[&header, &tile.data[4..tile.data_size]]),
+                        None => device.send(mux::Channel::Video, &[&tile.data[..tile.data_size]]),
+                    })
+                }).collect();
+                writers.into_iter().map(|writer| writer.join().expect("USB Tx Failed!")).collect()
+            });
+            if let Ok(sizes) = sizes.into_iter().collect::<Result<Vec<_>, _>>() {
+                transferred += sizes.iter().sum::<usize>();
                 frames += 1;
+                latency += tiles[0].captured.elapsed();
             } else {
                 panic!("USB Tx Failed!")
             }
+            let frame = &tiles[0];
+            // Zoom moves the source area every frame, only report size changes
+            let m = capture_context.mapping();
+            if output != Some((m.src.width, m.src.height, m.dst)) {
+                println!("Source {:.0}x{:.0}+{:.0}+{:.0} -> Output {:.0}x{:.0}+{:.0}+{:.0}",
+                    m.src.width, m.src.height, m.src.x, m.src.y, m.dst.width, m.dst.height, m.dst.x, m.dst.y);
+                output = Some((m.src.width, m.src.height, m.dst));
+            }
             if let Some(fps) = frame.fps {
                 let speed = transferred / 1000;
-                println!("Capture: {}fps, USB Tx: {}fps, {}kB/s, quality={}", fps, frames, speed, frame.quality);
+                let latency_avg = latency / frames.max(1) as u32;
+                println!("Capture: {}fps, USB Tx: {}fps, {}kB/s, quality={}, subsampling={}, latency={}ms",
+                    fps, frames, speed, frame.quality, frame.subsampling, latency_avg.as_millis());
+                capture_context.report(frames, speed, latency_avg);
+                if let Some(skew) = simulator::skew(&simulated) {
+                    println!("Simulated Tablets flip within {}us", skew.as_micros());
+                }
                 frames = 0;
                 transferred = 0;
+                latency = Duration::ZERO;
             }
         }
     });
 }
-
-fn open_device() -> Result<DeviceHandle<GlobalContext>, rusb::Error> {
-    let device = rusb::open_device_with_vid_pid(VID, PID)
-        .expect("Device not found!");
-    let _ = device.detach_kernel_driver(0);
-    device.set_active_configuration(1)?;
-    device.claim_interface(0)?;
-    Ok(device)
-}
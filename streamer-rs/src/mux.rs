@@ -0,0 +1,336 @@
+use crate::device::{Capabilities, Link};
+use std::{collections::VecDeque, sync::{mpsc, Arc, Condvar, Mutex}, thread, time::Duration};
+
+/// Channel, flags and payload length (u16) in front of every fragment
+pub const FRAGMENT_HEADER_SIZE: usize = 4;
+/// Largest payload of a fragment, a message on a higher channel waits for at most one of these
+pub const FRAGMENT_SIZE: usize = 16 * 1024;
+const FLAG_FIRST: u8 = 1 << 0;
+const FLAG_LAST: u8 = 1 << 1;
+const CHANNELS: usize = 5;
+
+/// Logical streams on the vendor endpoints, earlier ones go first when several have data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// Clock sync and other small requests and replies
+    Control = 0,
+    Touch = 1,
+    Audio = 2,
+    Video = 3,
+    File = 4,
+}
+
+impl Channel {
+    pub const ALL: [Channel; CHANNELS] = [Channel::Control, Channel::Touch, Channel::Audio, Channel::Video, Channel::File];
+
+    pub fn from_id(id: u8) -> Option<Channel> {
+        Channel::ALL.get(id as usize).copied()
+    }
+}
+
+/// Header and payload of one fragment, ready to write.
+pub fn fragment(channel: Channel, first: bool, last: bool, payload: &[u8]) -> Vec<u8> {
+    let flags = if first { FLAG_FIRST } else { 0 } | if last { FLAG_LAST } else { 0 };
+    let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + payload.len());
+    fragment.extend([channel as u8, flags]);
+    fragment.extend((payload.len() as u16).to_le_bytes());
+    fragment.extend(payload);
+    fragment
+}
+
+struct Message {
+    data: Vec<u8>,
+    sent: usize,
+    done: mpsc::Sender<Result<usize, rusb::Error>>,
+}
+
+#[derive(Default)]
+struct Queues {
+    channels: [VecDeque<Message>; CHANNELS],
+    closed: bool,
+}
+
+/// Cuts messages into fragments and interleaves them by channel priority on one link.
+pub struct Muxer {
+    queues: Arc<(Mutex<Queues>, Condvar)>,
+}
+
+impl Muxer {
+    pub fn new(link: Arc<dyn Link>) -> Self {
+        let queues = Arc::new((Mutex::new(Queues::default()), Condvar::new()));
+        let shared = queues.clone();
+        // Mux Thread
+        thread::spawn(move || write_fragments(link, shared));
+        Muxer { queues }
+    }
+
+    /// Queues `data` on `channel` and blocks until all of it is written, returns its size.
+    pub fn send(&self, channel: Channel, data: Vec<u8>) -> Result<usize, rusb::Error> {
+        self.queue(channel, data).recv().unwrap_or(Err(rusb::Error::NoDevice))
+    }
+
+    /// Queues `data` on `channel`, the size or the error arrives on the receiver once it is written,
+    /// fails, or the muxer is dropped.
+    pub fn queue(&self, channel: Channel, data: Vec<u8>) -> mpsc::Receiver<Result<usize, rusb::Error>> {
+        let (done, done_rx) = mpsc::channel();
+        let (queues, ready) = &*self.queues;
+        queues.lock().unwrap().channels[channel as usize].push_back(Message { data, sent: 0, done });
+        ready.notify_one();
+        done_rx
+    }
+}
+
+impl Drop for Muxer {
+    /// Fails whatever is still queued, a message being written when the muxer goes is failed too.
+    fn drop(&mut self) {
+        let (queues, ready) = &*self.queues;
+        let mut queues = queues.lock().unwrap();
+        queues.closed = true;
+        for message in queues.channels.iter_mut().flat_map(std::mem::take) {
+            let _ = message.done.send(Err(rusb::Error::NoDevice));
+        }
+        ready.notify_one();
+    }
+}
+
+fn write_fragments(link: Arc<dyn Link>, queues: Arc<(Mutex<Queues>, Condvar)>) {
+    let (queues, ready) = &*queues;
+    loop {
+        // The next fragment of the first channel with something to send
+        let (channel, fragment, done) = {
+            let mut queues = ready.wait_while(queues.lock().unwrap(), |queues| {
+                !queues.closed && queues.channels.iter().all(VecDeque::is_empty)
+            }).unwrap();
+            if queues.closed { return }
+            let (channel, queue) = queues.channels.iter_mut().enumerate().find(|(_, queue)| !queue.is_empty()).unwrap();
+            let message = queue.front_mut().unwrap();
+            let end = (message.sent + FRAGMENT_SIZE).min(message.data.len());
+            let last = end == message.data.len();
+            let fragment = fragment(Channel::ALL[channel], message.sent == 0, last, &message.data[message.sent..end]);
+            message.sent = end;
+            let done = last.then(|| queue.pop_front().unwrap());
+            (channel, fragment, done)
+        };
+        match (link.write(&fragment), done) {
+            (Ok(_), Some(message)) => { let _ = message.done.send(Ok(message.data.len())); }
+            (Ok(_), None) => {}
+            (Err(e), Some(message)) => { let _ = message.done.send(Err(e)); }
+            // The rest of a message that lost a fragment would not make sense on the other end
+            (Err(e), None) => if let Some(message) = queues.lock().unwrap().channels[channel].pop_front() {
+                let _ = message.done.send(Err(e));
+            },
+        }
+    }
+}
+
+/// Puts messages back together from fragments as they are read, for either end of a link.
+#[derive(Default)]
+pub struct Demuxer {
+    /// Bytes of a fragment that is not complete yet
+    pending: Vec<u8>,
+    messages: [Vec<u8>; CHANNELS],
+}
+
+impl Demuxer {
+    /// Takes one read, fragments may span reads. Returns the messages it completed, fragments of
+    /// unknown channels are skipped.
+    pub fn push(&mut self, data: &[u8]) -> Vec<(Channel, Vec<u8>)> {
+        self.pending.extend_from_slice(data);
+        let mut complete = Vec::new();
+        let mut at = 0;
+        while self.pending.len() - at >= FRAGMENT_HEADER_SIZE {
+            let header = &self.pending[at..at + FRAGMENT_HEADER_SIZE];
+            let (id, flags) = (header[0], header[1]);
+            let size = u16::from_le_bytes([header[2], header[3]]) as usize;
+            let end = at + FRAGMENT_HEADER_SIZE + size;
+            if self.pending.len() < end { break }
+            if let Some(channel) = Channel::from_id(id) {
+                let message = &mut self.messages[channel as usize];
+                if flags & FLAG_FIRST != 0 {
+                    message.clear();
+                }
+                message.extend_from_slice(&self.pending[at + FRAGMENT_HEADER_SIZE..end]);
+                if flags & FLAG_LAST != 0 {
+                    complete.push((channel, std::mem::take(message)));
+                }
+            }
+            at = end;
+        }
+        self.pending.drain(..at);
+        complete
+    }
+}
+
+/// In-memory byte stream, what is written is read back in order. As a `Link` it loops the muxer
+/// into a demuxer without USB, the simulator uses it for the tablet to host direction.
+#[derive(Clone, Default)]
+pub struct Pipe {
+    bytes: Arc<(Mutex<VecDeque<u8>>, Condvar)>,
+}
+
+impl Pipe {
+    pub fn push(&self, data: &[u8]) {
+        let (bytes, ready) = &*self.bytes;
+        bytes.lock().unwrap().extend(data);
+        ready.notify_all();
+    }
+
+    /// Whatever is there up to `buf.len()`, waiting up to `timeout` for the first byte.
+    pub fn pull(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, rusb::Error> {
+        let (bytes, ready) = &*self.bytes;
+        let (mut bytes, _) = ready.wait_timeout_while(bytes.lock().unwrap(), timeout, |bytes| bytes.is_empty()).unwrap();
+        if bytes.is_empty() {
+            return Err(rusb::Error::Timeout);
+        }
+        let size = bytes.len().min(buf.len());
+        for (to, from) in buf.iter_mut().zip(bytes.drain(..size)) {
+            *to = from;
+        }
+        Ok(size)
+    }
+}
+
+impl Link for Pipe {
+    fn write(&self, data: &[u8]) -> Result<usize, rusb::Error> {
+        self.push(data);
+        Ok(data.len())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+        self.pull(buf, Duration::from_millis(100))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { mux: true, ..Capabilities::esp32p4() }
+    }
+
+    fn enable_mux(&self) -> Result<(), rusb::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A `Pipe` whose writes take a while, fail from a given write on, or wait for `open`.
+    #[derive(Default)]
+    struct TestLink {
+        pipe: Pipe,
+        delay: Duration,
+        writes: AtomicUsize,
+        fail_at: Option<usize>,
+        gate: Option<Arc<(Mutex<bool>, Condvar)>>,
+    }
+
+    impl Link for TestLink {
+        fn write(&self, data: &[u8]) -> Result<usize, rusb::Error> {
+            thread::sleep(self.delay);
+            if let Some(gate) = &self.gate {
+                let (open, opened) = &**gate;
+                drop(opened.wait_while(open.lock().unwrap(), |open| !*open).unwrap());
+            }
+            if self.writes.fetch_add(1, Ordering::SeqCst) == self.fail_at.unwrap_or(usize::MAX) {
+                return Err(rusb::Error::Io);
+            }
+            self.pipe.write(data)
+        }
+
+        fn read(&self, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+            self.pipe.read(buf)
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            self.pipe.capabilities()
+        }
+
+        fn enable_mux(&self) -> Result<(), rusb::Error> {
+            Ok(())
+        }
+    }
+
+    /// Every message the pipe holds, in the order they completed.
+    fn drain(pipe: &Pipe) -> Vec<(Channel, Vec<u8>)> {
+        let mut demuxer = Demuxer::default();
+        let mut buf = vec![0; 64 * 1024];
+        let mut messages = Vec::new();
+        while let Ok(size) = pipe.pull(&mut buf, Duration::from_millis(10)) {
+            messages.extend(demuxer.push(&buf[..size]));
+        }
+        messages
+    }
+
+    fn pattern(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let pipe = Pipe::default();
+        let muxer = Muxer::new(Arc::new(pipe.clone()));
+        let (small, large) = (pattern(100), pattern(5 * FRAGMENT_SIZE + 123));
+        assert_eq!(muxer.send(Channel::Touch, small.clone()), Ok(100));
+        assert_eq!(muxer.send(Channel::Video, large.clone()), Ok(large.len()));
+        assert_eq!(drain(&pipe), [(Channel::Touch, small), (Channel::Video, large)]);
+    }
+
+    #[test]
+    fn control_overtakes_video() {
+        let link = Arc::new(TestLink { delay: Duration::from_millis(2), ..TestLink::default() });
+        let muxer = Muxer::new(link.clone());
+        let video = muxer.queue(Channel::Video, pattern(500 * 1024));
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(muxer.send(Channel::Control, vec![1, 2, 3]), Ok(3));
+        assert_eq!(video.try_recv(), Err(mpsc::TryRecvError::Empty));
+        assert_eq!(video.recv().unwrap(), Ok(500 * 1024));
+
+        let messages = drain(&link.pipe);
+        assert_eq!(messages.iter().map(|(channel, _)| *channel).collect::<Vec<_>>(), [Channel::Control, Channel::Video]);
+        assert_eq!(messages[1].1, pattern(500 * 1024));
+    }
+
+    #[test]
+    fn header_split_across_reads() {
+        let bytes = fragment(Channel::Audio, true, true, &[9, 8, 7]);
+        let mut demuxer = Demuxer::default();
+        assert_eq!(demuxer.push(&bytes[..2]), []);
+        assert_eq!(demuxer.push(&bytes[2..]), [(Channel::Audio, vec![9, 8, 7])]);
+    }
+
+    #[test]
+    fn unknown_channel_skipped() {
+        let mut bytes = fragment(Channel::Control, true, true, &[1]);
+        bytes[0] = CHANNELS as u8 + 4;
+        bytes.extend(fragment(Channel::File, true, true, &[2, 3]));
+        assert_eq!(Demuxer::default().push(&bytes), [(Channel::File, vec![2, 3])]);
+    }
+
+    #[test]
+    fn failed_write_drops_the_rest_of_the_message() {
+        let link = Arc::new(TestLink { fail_at: Some(1), ..TestLink::default() });
+        let muxer = Muxer::new(link.clone());
+        assert_eq!(muxer.send(Channel::Video, pattern(3 * FRAGMENT_SIZE)), Err(rusb::Error::Io));
+        assert_eq!(muxer.send(Channel::Video, pattern(10)), Ok(10));
+        // Only the first fragment of the failed message went out, the next message starts over
+        assert_eq!(link.writes.load(Ordering::SeqCst), 3);
+        assert_eq!(drain(&link.pipe), [(Channel::Video, pattern(10))]);
+    }
+
+    #[test]
+    fn drop_fails_queued_sends() {
+        let gate = Arc::new((Mutex::new(false), Condvar::new()));
+        let link = Arc::new(TestLink { gate: Some(gate.clone()), ..TestLink::default() });
+        let muxer = Muxer::new(link);
+        let sends = [muxer.queue(Channel::Video, pattern(2 * FRAGMENT_SIZE)), muxer.queue(Channel::Audio, pattern(10))];
+        drop(muxer);
+        for send in sends {
+            assert_eq!(send.recv_timeout(Duration::from_secs(1)), Ok(Err(rusb::Error::NoDevice)));
+        }
+        // The write in progress finishes, then the Mux Thread ends
+        let (open, opened) = &*gate;
+        *open.lock().unwrap() = true;
+        opened.notify_all();
+    }
+}
+
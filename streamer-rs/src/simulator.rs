@@ -0,0 +1,173 @@
+use crate::{device::{Capabilities, Link, PACKET_AUDIO, PACKET_FRAME, PACKET_SYNC, PACKET_TOUCH}, sync::{FRAME_HEADER_SIZE, host_time}};
+use crate::mux::{self, Channel, Demuxer, Pipe};
+use std::{collections::VecDeque, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+const BYTES_PER_SEC: f64 = 40e6;
+const HISTORY: usize = 64;
+// Fake touch stream of the first tablet, a vertical swipe down the middle of the panel every few seconds
+const SWIPE_PERIOD: Duration = Duration::from_secs(3);
+const SWIPE_STEP: Duration = Duration::from_millis(50);
+const SWIPE_STEPS: u32 = 10;
+
+/// Tablet stand-in for checking the host without hardware: its timer runs at an offset and drifts, the link
+/// has a delay and bandwidth, decoding takes a while, and every flip is logged in host time.
+#[derive(Clone)]
+pub struct SimulatedDevice {
+    offset: i64,
+    /// Device microseconds gained per host microsecond
+    drift: f64,
+    delay: Duration,
+    decode: Duration,
+    /// Sync replies on their way to the host
+    replies: Pipe,
+    /// Puts packets back together once the host turned the mux on
+    demuxer: Arc<Mutex<Option<Demuxer>>>,
+    /// Sequence and present time of a header whose JPEG has not arrived yet
+    pending: Arc<Mutex<Option<(u32, u64)>>>,
+    shown: Arc<Mutex<VecDeque<(u32, u64)>>>,
+    /// Start and reports sent so far of the fake touch stream
+    touch: Option<Arc<Mutex<(Instant, u32)>>>,
+}
+
+impl SimulatedDevice {
+    /// Every index gets a different clock, link delay and decode time, the first one also touches.
+    pub fn new(index: usize) -> Self {
+        SimulatedDevice {
+            offset: 1_000_000_000 + index as i64 * 7_345_678,
+            drift: index as f64 * 20e-6,
+            delay: Duration::from_micros(200 + 150 * index as u64),
+            decode: Duration::from_millis(10 + 4 * index as u64),
+            replies: Pipe::default(),
+            demuxer: Arc::new(Mutex::new(None)),
+            pending: Arc::new(Mutex::new(None)),
+            shown: Arc::new(Mutex::new(VecDeque::new())),
+            touch: (index == 0).then(|| Arc::new(Mutex::new((Instant::now(), 0)))),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_clock(self, offset: i64, drift: f64) -> Self {
+        SimulatedDevice { offset, drift, ..self }
+    }
+
+    /// What the device timer reads at host time `host`.
+    pub fn device_time(&self, host: u64) -> u64 {
+        (host as f64 * (1.0 + self.drift)) as i64 as u64 + self.offset as u64
+    }
+
+    fn host_time(&self, device: u64) -> u64 {
+        ((device as i64 - self.offset) as f64 / (1.0 + self.drift)) as u64
+    }
+
+    fn show(&self, sequence: u32, host: u64) {
+        let mut shown = self.shown.lock().unwrap();
+        shown.push_back((sequence, host));
+        if shown.len() > HISTORY {
+            shown.pop_front();
+        }
+    }
+
+    /// A report as it goes on the IN endpoint, in a fragment of its channel when muxed.
+    fn report(&self, channel: Channel, report: &[u8]) -> Vec<u8> {
+        match self.demuxer.lock().unwrap().is_some() {
+            true => mux::fragment(channel, true, true, report),
+            false => report.to_vec(),
+        }
+    }
+
+    fn receive(&self, data: &[u8]) {
+        let kind = data.get(4..8).map(|kind| u32::from_le_bytes(kind.try_into().unwrap()));
+        match kind {
+            Some(PACKET_SYNC) => {
+                let mut reply = [0u8; 24];
+                reply[..4].copy_from_slice(&PACKET_SYNC.to_le_bytes());
+                reply[8..16].copy_from_slice(&data[8..16]);
+                reply[16..].copy_from_slice(&self.device_time(host_time()).to_le_bytes());
+                self.replies.push(&self.report(Channel::Control, &reply));
+            }
+            Some(PACKET_FRAME) => {
+                let sequence = u32::from_le_bytes(data[8..12].try_into().unwrap());
+                let present = u64::from_le_bytes(data[16..24].try_into().unwrap());
+                *self.pending.lock().unwrap() = Some((sequence, present));
+                // Muxed, the JPEG comes in the same message
+                if data.len() > FRAME_HEADER_SIZE {
+                    self.decode();
+                }
+            }
+            // Header and samples in one go, there is no speaker to time
+            Some(PACKET_AUDIO) => {}
+            // JPEG data, after a header or a legacy size word
+            _ => self.decode(),
+        }
+    }
+
+    fn decode(&self) {
+        let decoded = self.device_time(host_time()) + self.decode.as_micros() as u64;
+        let (sequence, present) = self.pending.lock().unwrap().take()
+            .unwrap_or_else(|| (self.shown.lock().unwrap().back().map_or(0, |(s, _)| s + 1), 0));
+        self.show(sequence, self.host_time(decoded.max(present)));
+    }
+
+    /// The next report of the fake touch stream, once it is due.
+    fn touch_report(&self) -> Option<[u8; 28]> {
+        let mut touch = self.touch.as_ref()?.lock().unwrap();
+        let (start, sent) = *touch;
+        let (swipe, step) = (sent / (SWIPE_STEPS + 1), sent % (SWIPE_STEPS + 1));
+        if start.elapsed() < SWIPE_PERIOD * (swipe + 1) + SWIPE_STEP * step { return None }
+        touch.1 += 1;
+
+        let mut report = [0u8; 28];
+        report[..4].copy_from_slice(&PACKET_TOUCH.to_le_bytes());
+        // The last step lifts the finger
+        if step < SWIPE_STEPS {
+            report[4..8].copy_from_slice(&1u32.to_le_bytes());
+            report[8..10].copy_from_slice(&360u16.to_le_bytes());
+            report[10..12].copy_from_slice(&(300 + 60 * step as u16).to_le_bytes());
+        }
+        Some(report)
+    }
+}
+
+impl Link for SimulatedDevice {
+    fn write(&self, data: &[u8]) -> Result<usize, rusb::Error> {
+        thread::sleep(self.delay + Duration::from_secs_f64(data.len() as f64 / BYTES_PER_SEC));
+        let messages = self.demuxer.lock().unwrap().as_mut().map(|demuxer| demuxer.push(data));
+        match messages {
+            Some(messages) => messages.iter().for_each(|(_, message)| self.receive(message)),
+            None => self.receive(data),
+        }
+        Ok(data.len())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+        if let Ok(size) = self.replies.pull(buf, SWIPE_STEP) {
+            thread::sleep(self.delay);
+            return Ok(size);
+        }
+        let report = self.report(Channel::Touch, &self.touch_report().ok_or(rusb::Error::Timeout)?);
+        let size = report.len().min(buf.len());
+        buf[..size].copy_from_slice(&report[..size]);
+        Ok(size)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { sync: true, audio: true, mux: true, ..Capabilities::esp32p4() }
+    }
+
+    fn enable_mux(&self) -> Result<(), rusb::Error> {
+        *self.demuxer.lock().unwrap() = Some(Demuxer::default());
+        Ok(())
+    }
+}
+
+/// Spread of the flip times of the newest sequence every simulated tablet has shown.
+pub fn skew(devices: &[SimulatedDevice]) -> Option<Duration> {
+    let histories: Vec<_> = devices.iter().map(|device| device.shown.lock().unwrap().clone()).collect();
+    let (newest, _) = *histories.first()?.back()?;
+    (0..=newest).rev().take(HISTORY).find_map(|sequence| {
+        let times = histories.iter()
+            .map(|history| history.iter().find(|(s, _)| *s == sequence).map(|(_, time)| *time))
+            .collect::<Option<Vec<u64>>>()?;
+        Some(Duration::from_micros(times.iter().max()? - times.iter().min()?))
+    })
+}
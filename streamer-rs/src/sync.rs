@@ -0,0 +1,166 @@
+use crate::{device::{PACKET_FRAME, PACKET_SYNC, Tablet}, mux::Channel};
+use std::{sync::{Arc, Mutex, OnceLock}, thread, time::{Duration, Instant}};
+
+pub const FRAME_HEADER_SIZE: usize = 24;
+const SYNC_PACKET_SIZE: usize = 16;
+const SYNC_ROUNDS: usize = 8;
+const SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+const RESYNC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Microseconds since the first call, the host side of every timestamp.
+pub fn host_time() -> u64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+/// Offset between the host clock and one tablet's timer.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    /// Device time minus host time in microseconds
+    offset: i64,
+    round_trip: u64,
+}
+
+impl Clock {
+    /// Ping-pong over the vendor endpoints, keeps the exchange with the shortest round trip.
+    pub fn sync(tablet: &Tablet) -> Result<Clock, rusb::Error> {
+        let mut best: Option<Clock> = None;
+        for _ in 0..SYNC_ROUNDS {
+            let sent = host_time();
+            let mut packet = [0u8; SYNC_PACKET_SIZE];
+            packet[..4].copy_from_slice(&(SYNC_PACKET_SIZE as u32).to_le_bytes());
+            packet[4..8].copy_from_slice(&PACKET_SYNC.to_le_bytes());
+            packet[8..].copy_from_slice(&sent.to_le_bytes());
+            tablet.send(Channel::Control, &[&packet])?;
+
+            let Some((echoed, device)) = tablet.sync_reply(SYNC_TIMEOUT) else { continue };
+            let received = host_time();
+            // A late reply of an earlier round
+            if echoed != sent { continue }
+
+            let round_trip = received - sent;
+            let clock = Clock { offset: device as i64 - (sent + round_trip / 2) as i64, round_trip };
+            if best.is_none_or(|best| clock.round_trip < best.round_trip) {
+                best = Some(clock);
+            }
+        }
+        best.ok_or(rusb::Error::Timeout)
+    }
+
+    pub fn to_device(self, host: u64) -> u64 {
+        (host as i64 + self.offset).max(0) as u64
+    }
+}
+
+/// Stamps frames for several tablets with one sequence number and a common present time, and audio to match.
+pub struct Presenter {
+    /// Replaced by the Resync Thread
+    clocks: Arc<Mutex<Vec<Clock>>>,
+    sequence: u32,
+    delay: Duration,
+}
+
+impl Presenter {
+    /// `delay` covers transfer and decode, the tablets flip that long after the frame is sent.
+    /// Fails when a tablet does not answer the first sync.
+    pub fn new(tablets: Arc<Vec<Tablet>>, delay: Duration) -> Result<Self, rusb::Error> {
+        let clocks = Arc::new(Mutex::new(sync_all(&tablets)?));
+        // Resync Thread, the tablet timers drift. It ends with the presenter
+        let weak = Arc::downgrade(&clocks);
+        thread::spawn(move || loop {
+            thread::sleep(RESYNC_INTERVAL);
+            let Some(clocks) = weak.upgrade() else { return };
+            resync(&clocks, &tablets);
+        });
+        Ok(Presenter { clocks, sequence: 0, delay })
+    }
+
+    /// Frame header for each tablet, `sizes` are the JPEG sizes.
+    pub fn headers(&mut self, sizes: &[usize]) -> Vec<[u8; FRAME_HEADER_SIZE]> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let present = host_time() + self.delay.as_micros() as u64;
+        self.clocks.lock().unwrap().iter().zip(sizes).map(|(clock, size)| {
+            let mut header = [0u8; FRAME_HEADER_SIZE];
+            header[..4].copy_from_slice(&((FRAME_HEADER_SIZE + size) as u32).to_le_bytes());
+            header[4..8].copy_from_slice(&PACKET_FRAME.to_le_bytes());
+            header[8..12].copy_from_slice(&self.sequence.to_le_bytes());
+            header[16..].copy_from_slice(&clock.to_device(present).to_le_bytes());
+            header
+        }).collect()
+    }
+
+    /// Device time at which tablet `index` plays audio stamped with host time `at`, as late as a frame sent then.
+    pub fn present_at(&self, index: usize, at: u64) -> u64 {
+        self.clocks.lock().unwrap()[index].to_device(at + self.delay.as_micros() as u64)
+    }
+}
+
+fn sync_all(tablets: &[Tablet]) -> Result<Vec<Clock>, rusb::Error> {
+    tablets.iter().map(Clock::sync).collect()
+}
+
+/// Replaces the clocks with a fresh sync, a failed round keeps the ones there were.
+fn resync(clocks: &Mutex<Vec<Clock>>, tablets: &[Tablet]) {
+    match sync_all(tablets) {
+        Ok(synced) => *clocks.lock().unwrap() = synced,
+        Err(e) => println!("Clock Resync Failed: {}, keeping the previous clocks", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{device::{Capabilities, Link}, simulator::SimulatedDevice};
+
+    const OFFSET: i64 = 3_600_000_000;
+    /// Clock of a tablet whose timer drifted 50ms since it was synced
+    const STALE: Clock = Clock { offset: OFFSET - 50_000, round_trip: 0 };
+
+    /// Tablet that went away, every write fails at once.
+    struct Unplugged;
+
+    impl Link for Unplugged {
+        fn write(&self, _: &[u8]) -> Result<usize, rusb::Error> { Err(rusb::Error::NoDevice) }
+        fn read(&self, _: &mut [u8]) -> Result<usize, rusb::Error> { Err(rusb::Error::NoDevice) }
+        fn capabilities(&self) -> Capabilities { unreachable!() }
+        fn enable_mux(&self) -> Result<(), rusb::Error> { Err(rusb::Error::NoDevice) }
+    }
+
+    fn stale() -> Presenter {
+        Presenter { clocks: Arc::new(Mutex::new(vec![STALE])), sequence: 0, delay: Duration::ZERO }
+    }
+
+    /// The tablet stamps its reply somewhere within the round trip, so the estimate is off by
+    /// half of it at most, however long the exchange took.
+    fn assert_synced(presenter: &Presenter, device: &SimulatedDevice) {
+        let round_trip = presenter.clocks.lock().unwrap()[0].round_trip;
+        let now = host_time();
+        let error = presenter.present_at(0, now).abs_diff(device.device_time(now));
+        assert!(error <= round_trip / 2 + 1, "off by {}us after a {}us round trip", error, round_trip);
+    }
+
+    #[test]
+    fn sync_is_within_half_a_round_trip() {
+        let device = SimulatedDevice::new(0).with_clock(OFFSET, 0.0);
+        let tablets = Arc::new(vec![Tablet::new(Box::new(device.clone()), false, 0, None)]);
+        let presenter = Presenter::new(tablets, Duration::ZERO).expect("Clock Sync Failed!");
+        assert_synced(&presenter, &device);
+    }
+
+    #[test]
+    fn resync_replaces_stale_clocks() {
+        let device = SimulatedDevice::new(0).with_clock(OFFSET, 0.0);
+        let tablets = vec![Tablet::new(Box::new(device.clone()), false, 0, None)];
+        let presenter = stale();
+        resync(&presenter.clocks, &tablets);
+        assert_synced(&presenter, &device);
+    }
+
+    #[test]
+    fn failed_resync_keeps_the_clocks() {
+        let tablets = vec![Tablet::new(Box::new(Unplugged), false, 0, None)];
+        let presenter = stale();
+        resync(&presenter.clocks, &tablets);
+        assert_eq!(presenter.clocks.lock().unwrap()[0].offset, STALE.offset);
+    }
+}